@@ -0,0 +1,221 @@
+//! Sending a file of prompts one after another with `aika batch`.
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::provider::{Provider, QueryOptions, Role};
+
+/// One prompt of the batch, with the model to send it to when not the default
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BatchItem {
+    pub prompt: String,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Line written to the results for each prompt
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub prompt: String,
+    pub model: String,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Number of prompts answered and failed in a batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Read the prompts of a batch file: JSONL objects with a `prompt` and an
+/// optional `model` for `.jsonl` files, otherwise one prompt per line.
+/// Blank lines are skipped.
+pub fn load_batch(path: &Path) -> Result<Vec<BatchItem>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read batch file {:?}", path))?;
+    let jsonl = path.extension().is_some_and(|ext| ext == "jsonl");
+    parse_batch(&contents, jsonl).with_context(|| format!("Invalid batch file {:?}", path))
+}
+
+fn parse_batch(contents: &str, jsonl: bool) -> Result<Vec<BatchItem>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            if jsonl {
+                serde_json::from_str(line).with_context(|| format!("line {}", index + 1))
+            } else {
+                Ok(BatchItem {
+                    prompt: line.to_string(),
+                    model: None,
+                })
+            }
+        })
+        .collect()
+}
+
+/// Send every prompt to `provider` in turn, writing one JSON result line per
+/// prompt to `out`. Failed prompts are recorded with their error and the
+/// batch goes on; `resolve_model` maps the model of each prompt (or
+/// `default_model`) to the ID sent.
+pub fn run_batch(
+    provider: &dyn Provider,
+    default_model: &str,
+    items: &[BatchItem],
+    resolve_model: &dyn Fn(&str) -> String,
+    out: &mut dyn Write,
+) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+
+    for item in items {
+        let model = resolve_model(item.model.as_deref().unwrap_or(default_model));
+        let reply = provider.query_with_history(
+            &model,
+            &[(Role::User, item.prompt.clone())],
+            None,
+            &QueryOptions::default(),
+        );
+
+        let result = match reply {
+            Ok(response) => {
+                summary.succeeded += 1;
+                BatchResult {
+                    prompt: item.prompt.clone(),
+                    model,
+                    response: Some(response),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                summary.failed += 1;
+                log::warn!(
+                    "Prompt {} failed: {:#}",
+                    summary.succeeded + summary.failed,
+                    e
+                );
+                BatchResult {
+                    prompt: item.prompt.clone(),
+                    model,
+                    response: None,
+                    error: Some(format!("{:#}", e)),
+                }
+            }
+        };
+
+        writeln!(out, "{}", serde_json::to_string(&result)?)
+            .context("Failed to write batch results")?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config_for;
+    use crate::provider::create_provider;
+
+    #[test]
+    fn test_parse_batch_lines_and_jsonl() {
+        let items = parse_batch("first prompt\n\nsecond prompt\n", false).unwrap();
+        assert_eq!(
+            items,
+            [
+                BatchItem {
+                    prompt: "first prompt".to_string(),
+                    model: None
+                },
+                BatchItem {
+                    prompt: "second prompt".to_string(),
+                    model: None
+                },
+            ]
+        );
+
+        let items = parse_batch(
+            "{\"prompt\": \"Hi\"}\n{\"prompt\": \"Bye\", \"model\": \"fast\"}\n",
+            true,
+        )
+        .unwrap();
+        assert_eq!(items[1].model.as_deref(), Some("fast"));
+
+        let err = parse_batch("{\"prompt\": \"Hi\"}\nnot json\n", true).unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+    }
+
+    #[test]
+    fn test_run_batch_continues_past_failures() {
+        let mut server = mockito::Server::new();
+        let ok = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"model": "claude-good"}),
+            ))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "Answer"}]}"#)
+            .expect(2)
+            .create();
+        let failing = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"model": "claude-missing"}),
+            ))
+            .with_status(404)
+            .with_body(r#"{"error": {"message": "model not found"}}"#)
+            .create();
+
+        let config = test_config_for(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let items = [
+            BatchItem {
+                prompt: "One".to_string(),
+                model: None,
+            },
+            BatchItem {
+                prompt: "Two".to_string(),
+                model: Some("missing".to_string()),
+            },
+            BatchItem {
+                prompt: "Three".to_string(),
+                model: None,
+            },
+        ];
+        let mut out = Vec::new();
+        let summary = run_batch(
+            provider.as_ref(),
+            "good",
+            &items,
+            &|model| format!("claude-{}", model),
+            &mut out,
+        )
+        .unwrap();
+
+        ok.assert();
+        failing.assert();
+        assert_eq!(
+            summary,
+            BatchSummary {
+                succeeded: 2,
+                failed: 1
+            }
+        );
+
+        let results: Vec<BatchResult> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].response.as_deref(), Some("Answer"));
+        assert_eq!(results[1].model, "claude-missing");
+        assert!(results[1].error.as_deref().unwrap().contains("404"));
+        assert_eq!(results[2].prompt, "Three");
+    }
+}
@@ -1,10 +1,24 @@
-use std::io::{BufRead as _, BufReader, Write};
+use std::cell::{Cell, RefCell};
+use std::ops::ControlFlow;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::{config::Config, provider::Provider as ProviderTrait};
+use crate::{
+    config::Config,
+    error::{AikaError, api_error},
+    http::{
+        DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT, agent_for_options, select_agent, send_with_retry,
+    },
+    provider::{
+        ModelInfo, PromptContent, Provider as ProviderTrait, QueryOptions, QueryResponse, Role,
+        Usage, apply_extra, default_max_tokens, text_messages, warn_truncated,
+    },
+    redact::{redact, register_secret},
+    request_log, sse,
+    tools::{Tool, ToolCall},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Model {
@@ -21,14 +35,46 @@ struct ModelsResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ContentItem {
+    #[serde(default)]
     text: String,
     #[serde(rename = "type")]
     content_type: String,
+    /// Tool name of a `tool_use` block
+    #[serde(default)]
+    name: Option<String>,
+    /// Arguments of a `tool_use` block
+    #[serde(default)]
+    input: Option<serde_json::Value>,
+    /// Reasoning of a `thinking` block
+    #[serde(default)]
+    thinking: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ClaudeResponse {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
     content: Vec<ContentItem>,
+    usage: Option<ClaudeUsage>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ClaudeUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl From<ClaudeUsage> for Usage {
+    fn from(usage: ClaudeUsage) -> Self {
+        Usage {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        }
+    }
 }
 
 // Streaming response structures for Claude
@@ -47,6 +93,11 @@ enum ClaudeStreamData {
         index: u32,
         delta: ClaudeContentDelta,
     },
+    MessageDelta {
+        delta: ClaudeMessageDelta,
+        #[serde(default)]
+        usage: Option<ClaudeDeltaUsage>,
+    },
     MessageStart {
         message: ClaudeResponse,
     },
@@ -54,20 +105,106 @@ enum ClaudeStreamData {
     Other(serde_json::Value),
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ClaudeMessageDelta {
+    stop_reason: Option<String>,
+}
+
+/// Cumulative usage sent with `message_delta`; input tokens are usually only
+/// in `message_start`
+#[derive(Debug, Serialize, Deserialize)]
+struct ClaudeDeltaUsage {
+    #[serde(default)]
+    input_tokens: Option<u64>,
+    output_tokens: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct ClaudeContentDelta {
     #[serde(rename = "type")]
     delta_type: String,
     text: Option<String>,
+    #[serde(default)]
+    thinking: Option<String>,
+}
+
+/// Message content for the Messages API: a string, or image blocks followed by the text
+fn message_content(content: &PromptContent) -> serde_json::Value {
+    match content {
+        PromptContent::Text(text) => json!(text),
+        PromptContent::WithImages { text, images } => {
+            let mut blocks: Vec<_> = images
+                .iter()
+                .map(|image| {
+                    json!({
+                        "type": "image",
+                        "source": {
+                            "type": "base64",
+                            "media_type": image.media_type,
+                            "data": image.base64(),
+                        },
+                    })
+                })
+                .collect();
+            blocks.push(json!({"type": "text", "text": text}));
+            json!(blocks)
+        }
+    }
+}
+
+/// Tool definition for the Messages API
+fn tool_definition(tool: &Tool) -> serde_json::Value {
+    let mut definition = json!({"name": tool.name, "input_schema": tool.parameters});
+    if let Some(description) = &tool.description {
+        definition["description"] = json!(description);
+    }
+    definition
+}
+
+/// Error for an `error` event received mid-stream
+fn stream_error(data: &str) -> anyhow::Error {
+    let message = serde_json::from_str::<serde_json::Value>(data)
+        .ok()
+        .and_then(|event| event["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| data.to_string());
+    AikaError::Server(redact(&format!("Claude stream error: {}", message))).into()
+}
+
+/// Status Anthropic answers with when its API is overloaded
+const OVERLOADED_STATUS: u16 = 529;
+
+/// Error for a request still overloaded once retries ran out, worded as a
+/// temporary condition rather than a failure of the request
+fn overloaded_error(retries: u32) -> anyhow::Error {
+    AikaError::Server(format!(
+        "Claude is overloaded (HTTP 529) and still was after {} retries; try again in a moment, \
+         raise --max-retries or use --fallback with another provider",
+        retries
+    ))
+    .into()
 }
 
 pub struct ClaudeProvider {
     api_key: String,
     base_url: String,
+    api_version: String,
+    beta: Vec<String>,
     model: String,
+    defaults: QueryOptions,
+    agent: ureq::Agent,
+    last_usage: Cell<Option<Usage>>,
+    truncated: Cell<bool>,
+    tool_calls: RefCell<Vec<ToolCall>>,
+    thinking: RefCell<String>,
 }
 
-const DEFAULT_MODEL: &str = "claude-sonnet-4-5-20250929";
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const DEFAULT_API_VERSION: &str = "2023-06-01";
+/// System instruction sent first in JSON mode
+const JSON_MODE_INSTRUCTION: &str =
+    "Respond only with a valid JSON object, without any text or code fences around it.";
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-5-20250929";
+const DEFAULT_TEMPERATURE: f32 = 0.0;
 
 impl ClaudeProvider {
     pub const PROVIDER_NAME: &str = "anthropic";
@@ -80,20 +217,64 @@ impl ClaudeProvider {
                     .and_then(|creds| creds.anthropic_api_key.clone())
                     .ok_or(std::env::VarError::NotPresent)
             })
-            .map_err(|_| anyhow::anyhow!("ANTHROPIC_API_KEY environment variable is not set and no API key found in config"))?;
+            .map_err(|_| AikaError::Auth("ANTHROPIC_API_KEY environment variable is not set and no API key found in config".to_string()))?;
+        Self::with_api_key(config, api_key)
+    }
 
-        let model = config
-            .providers
-            .get("anthropic")
-            .map(|provider| provider.model.clone())
+    /// Build the provider with `api_key` instead of the key from the environment or config
+    pub fn with_api_key(config: &Config, api_key: String) -> anyhow::Result<Self> {
+        register_secret(&api_key);
+
+        let provider_config = config.providers.get(Self::PROVIDER_NAME);
+
+        let model = provider_config
+            .and_then(|provider| provider.model.clone())
             .unwrap_or_else(|| DEFAULT_MODEL.to_string());
 
+        let base_url = provider_config
+            .and_then(|provider| provider.base_url.clone())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        let api_version = provider_config
+            .and_then(|provider| provider.api_version.clone())
+            .unwrap_or_else(|| DEFAULT_API_VERSION.to_string());
+
+        let beta = provider_config
+            .and_then(|provider| provider.anthropic_beta.clone())
+            .unwrap_or_default();
+
+        let defaults = QueryOptions::from_config(provider_config);
+        let agent = agent_for_options(&defaults, &base_url)?;
+
         Ok(Self {
             api_key,
-            base_url: "https://api.anthropic.com".into(),
+            base_url,
+            api_version,
+            beta,
             model,
+            defaults,
+            agent,
+            last_usage: Cell::new(None),
+            truncated: Cell::new(false),
+            tool_calls: RefCell::new(Vec::new()),
+            thinking: RefCell::new(String::new()),
         })
     }
+
+    /// Add the authentication, version and beta headers every API request needs
+    fn with_headers<B>(&self, request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+        let request = request
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", &self.api_version);
+
+        if self.beta.is_empty() {
+            request
+        } else {
+            request.header("anthropic-beta", self.beta.join(","))
+        }
+    }
 }
 
 impl ProviderTrait for ClaudeProvider {
@@ -101,59 +282,187 @@ impl ProviderTrait for ClaudeProvider {
         self.model.clone()
     }
 
+    fn last_usage(&self) -> Option<Usage> {
+        self.last_usage.get()
+    }
+
+    fn last_truncated(&self) -> bool {
+        self.truncated.get()
+    }
+
+    fn last_thinking(&self) -> Option<String> {
+        Some(self.thinking.borrow().clone()).filter(|thinking| !thinking.is_empty())
+    }
+
+    fn last_tool_calls(&self) -> Vec<ToolCall> {
+        self.tool_calls.borrow().clone()
+    }
+
     fn name(&self) -> String {
         ClaudeProvider::PROVIDER_NAME.to_string()
     }
 
-    fn list_models(&self) -> anyhow::Result<()> {
+    fn list_models(&self) -> anyhow::Result<Vec<ModelInfo>> {
         let url = format!("{}/v1/models", self.base_url);
-        let response: ModelsResponse = ureq::get(url)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .call()?
-            .body_mut()
-            .read_json::<ModelsResponse>()?;
-
-        println!("Available Claude models:");
-        for model in response.data {
-            println!("  {} - {}", model.id, model.display_name);
+        let mut response = self
+            .with_headers(self.agent.get(url.as_str()))
+            .call()
+            .map_err(|e| AikaError::Network(redact(&format!("Claude request failed: {}", e))))?;
+
+        if response.status() != 200 {
+            let status = response.status();
+            let error_body = response
+                .body_mut()
+                .read_to_string()
+                .unwrap_or_else(|_| "Failed to read error body".to_string());
+
+            return Err(api_error("Claude", status.as_u16(), &error_body));
         }
 
-        Ok(())
+        let response = response.body_mut().read_json::<ModelsResponse>()?;
+
+        Ok(response
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                id: model.id,
+                display_name: Some(model.display_name),
+            })
+            .collect())
+    }
+
+    fn query(
+        &self,
+        model: &str,
+        prompt: &str,
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<String> {
+        self.query_with_history(
+            model,
+            &[(Role::User, prompt.to_string())],
+            on_chunk,
+            &QueryOptions::default(),
+        )
+    }
+
+    fn query_with_history(
+        &self,
+        model: &str,
+        messages: &[(Role, String)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.query_with_content(model, &text_messages(messages), on_chunk, options)
     }
 
-    fn query(&self, model: &str, prompt: &str, streaming: bool) -> Result<String> {
+    fn query_with_content(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.query_full(model, messages, on_chunk, options)
+            .map(|response| response.text)
+    }
+
+    fn query_full(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<QueryResponse> {
+        let streaming = on_chunk.is_some();
+        let options = options.or(&self.defaults);
+        self.last_usage.set(None);
+        self.truncated.set(false);
+        self.tool_calls.borrow_mut().clear();
+        self.thinking.borrow_mut().clear();
         let url = format!("{}/v1/messages", self.base_url);
-        let query = json!({
+
+        // Claude takes system prompts as a top-level field rather than a message.
+        // It has no JSON response format, so JSON mode is asked for in the system prompt.
+        let system = options
+            .json_mode
+            .then_some(JSON_MODE_INSTRUCTION)
+            .into_iter()
+            .chain(
+                messages
+                    .iter()
+                    .filter(|(role, _)| *role == Role::System)
+                    .map(|(_, content)| content.text()),
+            )
+            .collect::<Vec<&str>>()
+            .join("\n\n");
+
+        let messages = messages
+            .iter()
+            .filter(|(role, _)| *role != Role::System)
+            .map(|(role, content)| json!({"role": role.as_str(), "content": message_content(content)}))
+            .collect::<Vec<_>>();
+
+        let mut query = json!({
             "model": model,
-            "temperature": 0.0,
-            "messages": [{
-                "role": "user",
-                "content": prompt
-            }],
-            "max_tokens": 4096,
+            "temperature": options.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+            "messages": messages,
+            "max_tokens": options.max_tokens.unwrap_or_else(|| default_max_tokens(model)),
             "stream": streaming,
         });
 
-        let mut result = String::new();
+        if !system.is_empty() {
+            query["system"] = json!(system);
+        }
 
-        let config: ureq::config::Config = ureq::Agent::config_builder()
-            .http_status_as_error(false)
-            .build();
+        if options.seed.is_some() {
+            log::debug!("Ignoring seed, which the Anthropic API does not support");
+        }
 
-        let agent: ureq::Agent = config.into();
+        // The Messages API documents no limit on the number of stop
+        // sequences, so unlike OpenAI they are sent without a count check
+        if !options.stop.is_empty() {
+            query["stop_sequences"] = json!(options.stop);
+        }
 
-        let response = agent
-            .post(url)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .send_json(query);
+        if !options.tools.is_empty() {
+            query["tools"] = json!(
+                options
+                    .tools
+                    .iter()
+                    .map(tool_definition)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        let mut stop_reason = None;
+        let mut id = None;
+        let mut response_model = None;
+
+        apply_extra(&mut query, &options.extra);
+
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let agent = select_agent(&self.agent, &self.defaults, &options, &url)?;
+
+        let max_retries = options.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let response = send_with_retry(max_retries, || {
+            self.with_headers(agent.post(url.as_str()))
+                .header("content-type", "application/json")
+                .send_json(&query)
+        });
 
         let mut response = match response {
             Ok(resp) => resp,
+            Err(ureq::Error::Timeout(_)) => {
+                return Err(AikaError::Network(format!(
+                    "Claude request timed out after {:?}",
+                    timeout
+                ))
+                .into());
+            }
             Err(e) => {
-                return Err(anyhow::anyhow!("Claude request failed: {}", e));
+                return Err(
+                    AikaError::Network(redact(&format!("Claude request failed: {}", e))).into(),
+                );
             }
         };
 
@@ -164,71 +473,391 @@ impl ProviderTrait for ClaudeProvider {
                 .body_mut()
                 .read_to_string()
                 .unwrap_or_else(|_| "Failed to read error body".to_string());
+            request_log::record(&self.name(), model, &query, status.as_u16(), &error_body);
 
-            return Err(anyhow::anyhow!(
-                "Claude API error ({}): {}",
-                status,
-                error_body
-            ));
+            if status.as_u16() == OVERLOADED_STATUS {
+                return Err(overloaded_error(max_retries));
+            }
+            return Err(api_error("Claude", status.as_u16(), &error_body));
         }
 
-        if !streaming {
-            let response = response.body_mut().read_json::<ClaudeResponse>()?;
+        let name = self.name();
+        let record = |body: &str| request_log::record(&name, model, &query, 200, body);
+        let result = sse::read_reply(&mut response, on_chunk, &record, |part| {
+            let event = match part {
+                sse::Part::Body(body) => {
+                    let response = serde_json::from_str::<ClaudeResponse>(body)?;
+                    self.last_usage.set(response.usage.map(Usage::from));
+                    stop_reason = response.stop_reason;
+                    id = response.id;
+                    response_model = response.model;
 
-            for item in response.content {
-                if item.content_type == "text" {
-                    result.push_str(&item.text);
+                    let mut text = String::new();
+                    for item in response.content {
+                        match item.content_type.as_str() {
+                            "text" => text.push_str(&item.text),
+                            "thinking" => self
+                                .thinking
+                                .borrow_mut()
+                                .push_str(item.thinking.as_deref().unwrap_or_default()),
+                            "tool_use" => self.tool_calls.borrow_mut().push(ToolCall {
+                                name: item.name.unwrap_or_default(),
+                                arguments: item.input.unwrap_or_default(),
+                            }),
+                            _ => {}
+                        }
+                    }
+                    return Ok(ControlFlow::Continue(text));
                 }
-            }
-        } else {
-            let reader = BufReader::new(response.body_mut().with_config().reader());
+                sse::Part::Event(event) => event,
+            };
 
-            for line in reader.lines() {
-                let line = line?;
-                if line.trim().is_empty() {
-                    continue;
-                }
+            // Errors after the response started, such as overload, arrive as an event
+            if event.event == "error" {
+                return Err(stream_error(&event.data));
+            }
 
-                // Parse SSE format: "data: {...}"
-                if let Some(data) = line.strip_prefix("data: ") {
-                    // Parse JSON response
-                    match serde_json::from_str::<ClaudeStreamEvent>(data) {
-                        Ok(stream_event) => {
-                            match stream_event.data {
-                                ClaudeStreamData::ContentBlockDelta { delta, .. } => {
-                                    if let Some(text) = delta.text {
-                                        // Call the callback with the chunk
-                                        print!("{}", text);
-                                        std::io::stdout().flush().unwrap();
-                                    }
-                                }
-                                _ => {
-                                    // Handle other event types if needed
-                                }
-                            }
+            let mut text = String::new();
+            match serde_json::from_str::<ClaudeStreamEvent>(&event.data) {
+                Ok(stream_event) => match stream_event.data {
+                    ClaudeStreamData::ContentBlockDelta { delta, .. } => {
+                        if let Some(delta_text) = delta.text {
+                            text = delta_text;
                         }
-                        Err(e) => {
-                            // Log parse errors but continue processing
-                            eprintln!("Failed to parse Claude streaming response: {}", e);
+                        if let Some(thinking) = delta.thinking {
+                            self.thinking.borrow_mut().push_str(&thinking);
                         }
                     }
+                    ClaudeStreamData::MessageDelta { delta, usage } => {
+                        stop_reason = delta.stop_reason.or(stop_reason.take());
+                        if let Some(usage) = usage {
+                            let input_tokens = usage.input_tokens.unwrap_or_else(|| {
+                                self.last_usage.get().map_or(0, |usage| usage.input_tokens)
+                            });
+                            self.last_usage.set(Some(Usage {
+                                input_tokens,
+                                output_tokens: usage.output_tokens,
+                            }));
+                        }
+                    }
+                    ClaudeStreamData::MessageStart { message } => {
+                        id = message.id;
+                        response_model = message.model;
+                        self.last_usage.set(message.usage.map(Usage::from));
+                    }
+                    _ => {
+                        // Handle other event types if needed
+                    }
+                },
+                Err(e) => {
+                    // Log parse errors but continue processing
+                    log::warn!("Failed to parse Claude streaming response: {}", e);
                 }
             }
+            Ok(ControlFlow::Continue(text))
+        })?;
+
+        if stop_reason.as_deref() == Some("max_tokens") {
+            self.truncated.set(true);
+            warn_truncated("Claude");
         }
 
-        Ok(result)
+        Ok(QueryResponse {
+            text: result,
+            model: response_model,
+            id,
+            usage: self.last_usage.get(),
+            finish_reason: stop_reason,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::test_config_for;
+    use crate::provider::Image;
     use mockito::ServerGuard;
 
     fn setup_mock_server() -> ServerGuard {
         mockito::Server::new()
     }
 
+    fn test_provider(server: &ServerGuard) -> ClaudeProvider {
+        ClaudeProvider {
+            api_key: "test-key".to_string(),
+            base_url: server.url(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            beta: Vec::new(),
+            model: DEFAULT_MODEL.to_string(),
+            defaults: QueryOptions::default(),
+            agent: agent_for_options(&QueryOptions::default(), "http://localhost").unwrap(),
+            last_usage: Cell::new(None),
+            truncated: Cell::new(false),
+            tool_calls: RefCell::new(Vec::new()),
+            thinking: RefCell::new(String::new()),
+        }
+    }
+
+    fn weather_tool() -> Tool {
+        Tool {
+            name: "get_weather".to_string(),
+            description: Some("Current weather for a city".to_string()),
+            parameters: json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        }
+    }
+
+    #[test]
+    fn test_claude_flags_max_tokens_stop_reason() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "The answer is"}], "stop_reason": "max_tokens"}"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "test", None);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "The answer is");
+        assert!(provider.last_truncated());
+
+        let _complete = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "Done."}], "stop_reason": "end_turn"}"#,
+            )
+            .create();
+        provider.query(DEFAULT_MODEL, "test", None).unwrap();
+        assert!(!provider.last_truncated());
+    }
+
+    #[test]
+    fn test_claude_flags_max_tokens_when_streaming() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"The answer\"}}\n\n\
+                 data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"max_tokens\"},\"usage\":{\"output_tokens\":2}}\n\n\
+                 data: {\"type\":\"message_stop\"}\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}));
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "The answer");
+        assert!(provider.last_truncated());
+    }
+
+    #[test]
+    fn test_claude_stream_skips_comments_and_event_lines() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                ": keepalive\n\n\
+                 event: message_start\n\
+                 data: {\"type\":\"message_start\",\"message\":{\"content\":[],\"usage\":{\"input_tokens\":3,\"output_tokens\":0}}}\n\n\
+                 event: ping\n\
+                 data: {\"type\":\"ping\"}\n\n\
+                 event: content_block_delta\n\
+                 data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n\
+                 : still there\n\
+                 id: 7\n\
+                 event: content_block_delta\n\
+                 data:{\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" there\"}}\n\n\
+                 event: message_stop\n\
+                 data: {\"type\":\"message_stop\"}\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hello there");
+        assert_eq!(chunks, ["Hello", " there"]);
+    }
+
+    #[test]
+    fn test_claude_query_full_reports_metadata() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                "event: message_start\n\
+                 data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"model\":\"claude-sonnet-4-5-20250929\",\"content\":[],\"usage\":{\"input_tokens\":3,\"output_tokens\":0}}}\n\n\
+                 event: content_block_delta\n\
+                 data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n\
+                 event: message_delta\n\
+                 data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":1}}\n\n\
+                 event: message_stop\n\
+                 data: {\"type\":\"message_stop\"}\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let response = provider
+            .query_full(
+                DEFAULT_MODEL,
+                &text_messages(&[(Role::User, "test".to_string())]),
+                Some(&mut |_: &str| {}),
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.text, "Hello");
+        assert_eq!(response.id.as_deref(), Some("msg_01"));
+        assert_eq!(
+            response.model.as_deref(),
+            Some("claude-sonnet-4-5-20250929")
+        );
+        assert_eq!(response.finish_reason.as_deref(), Some("end_turn"));
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                input_tokens: 3,
+                output_tokens: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_claude_stream_joins_multi_line_data() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                "event: content_block_delta\n\
+                 data: {\"type\":\"content_block_delta\",\"index\":0,\n\
+                 data: \"delta\":{\"type\":\"text_delta\",\"text\":\"Split event\"}}\n\n\
+                 event: message_stop\n\
+                 data: {\"type\":\"message_stop\"}\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}));
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Split event");
+    }
+
+    #[test]
+    fn test_claude_sends_configured_version_and_beta_headers() {
+        let mut server = setup_mock_server();
+
+        let models = server
+            .mock("GET", "/v1/models")
+            .match_header("anthropic-version", "2024-10-22")
+            .match_header(
+                "anthropic-beta",
+                "output-128k-2025-02-19,files-api-2025-04-14",
+            )
+            .with_status(200)
+            .with_body(r#"{"data": []}"#)
+            .create();
+        let messages = server
+            .mock("POST", "/v1/messages")
+            .match_header("anthropic-version", "2024-10-22")
+            .match_header(
+                "anthropic-beta",
+                "output-128k-2025-02-19,files-api-2025-04-14",
+            )
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .create();
+
+        let mut config = test_config_for(&server.url());
+        let anthropic = config
+            .providers
+            .get_mut(ClaudeProvider::PROVIDER_NAME)
+            .unwrap();
+        anthropic.api_version = Some("2024-10-22".to_string());
+        anthropic.anthropic_beta = Some(vec![
+            "output-128k-2025-02-19".to_string(),
+            "files-api-2025-04-14".to_string(),
+        ]);
+        let provider = ClaudeProvider::new(&config).unwrap();
+
+        provider.list_models().unwrap();
+        assert_eq!(provider.query(DEFAULT_MODEL, "test", None).unwrap(), "ok");
+
+        models.assert();
+        messages.assert();
+    }
+
+    #[test]
+    fn test_claude_stream_falls_back_to_json_body() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "Whole reply"}], "stop_reason": "end_turn"}"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Whole reply");
+        assert_eq!(chunks, ["Whole reply"]);
+    }
+
+    #[test]
+    fn test_claude_stream_error_event() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                "event: error\n\
+                 data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let err = provider
+            .query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}))
+            .unwrap_err();
+
+        mock.assert();
+        assert_eq!(err.to_string(), "Claude stream error: Overloaded");
+        assert_eq!(crate::error::exit_code(&err), 7);
+    }
+
     #[test]
     fn test_claude_query_success() {
         let mut server = setup_mock_server();
@@ -237,6 +866,10 @@ mod tests {
             .mock("POST", "/v1/messages")
             .match_header("x-api-key", "test-key")
             .match_header("anthropic-version", "2023-06-01")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "model": DEFAULT_MODEL,
+                "messages": [{"role": "user", "content": "test input"}],
+            })))
             .with_status(200)
             .with_body(
                 r#"{
@@ -246,13 +879,9 @@ mod tests {
             )
             .create();
 
-        let provider = ClaudeProvider {
-            api_key: "test-key".to_string(),
-            base_url: server.url(), // Point to mock server
-            model: DEFAULT_MODEL.to_string(),
-        };
+        let provider = test_provider(&server);
 
-        let result = provider.query("test input", DEFAULT_MODEL, false);
+        let result = provider.query(DEFAULT_MODEL, "test input", None);
 
         mock.assert();
         assert!(result.is_ok());
@@ -268,13 +897,10 @@ mod tests {
             .with_body(r#"{"error": {"message": "Invalid API key"}}"#)
             .create();
 
-        let provider = ClaudeProvider {
-            api_key: "bad-key".to_string(),
-            base_url: server.url(),
-            model: DEFAULT_MODEL.to_string(),
-        };
+        let mut provider = test_provider(&server);
+        provider.api_key = "bad-key".to_string();
 
-        let result = provider.query("test", DEFAULT_MODEL, false);
+        let result = provider.query(DEFAULT_MODEL, "test", None);
 
         mock.assert();
         assert!(result.is_err());
@@ -292,15 +918,481 @@ mod tests {
               .with_body("data: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"Hello\"}}\n\ndata: {\"type\":\"message_stop\"}\n\n")
               .create();
 
-        let provider = ClaudeProvider {
-            api_key: "test-key".to_string(),
-            base_url: server.url(),
-            model: DEFAULT_MODEL.to_string(),
+        let provider = test_provider(&server);
+
+        let result = provider.query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}));
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_claude_streaming_accumulates_deltas() {
+        let mut server = setup_mock_server();
+
+        let mock = server.mock("POST", "/v1/messages")
+              .with_status(200)
+              .with_header("content-type", "text/event-stream")
+              .with_body("data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n\
+                          data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\", world\"}}\n\n\
+                          data: {\"type\":\"message_stop\"}\n\n")
+              .create();
+
+        let provider = test_provider(&server);
+
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hello, world");
+        assert_eq!(chunks, ["Hello", ", world"]);
+    }
+
+    #[test]
+    fn test_claude_sends_history_and_system() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "system": "Be terse.",
+                "messages": [
+                    {"role": "user", "content": "Hi"},
+                    {"role": "assistant", "content": "Hello"},
+                    {"role": "user", "content": "How are you?"}
+                ]
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "Fine."}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let messages = vec![
+            (Role::System, "Be terse.".to_string()),
+            (Role::User, "Hi".to_string()),
+            (Role::Assistant, "Hello".to_string()),
+            (Role::User, "How are you?".to_string()),
+        ];
+        let result =
+            provider.query_with_history(DEFAULT_MODEL, &messages, None, &QueryOptions::default());
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Fine.");
+    }
+
+    #[test]
+    fn test_claude_json_mode_prepends_system_instruction() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "system": format!("{}\n\nBe terse.", JSON_MODE_INSTRUCTION)
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "{}"}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let options = QueryOptions {
+            json_mode: true,
+            ..Default::default()
+        };
+        let messages = vec![
+            (Role::System, "Be terse.".to_string()),
+            (Role::User, "Hi".to_string()),
+        ];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_claude_sends_image_blocks() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "messages": [{
+                    "role": "user",
+                    "content": [
+                        {
+                            "type": "image",
+                            "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw=="}
+                        },
+                        {"type": "text", "text": "Describe this"}
+                    ]
+                }]
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "A chart."}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let image = Image {
+            media_type: "image/png",
+            data: b"\x89PNG".to_vec(),
+        };
+        let messages = [(
+            Role::User,
+            PromptContent::WithImages {
+                text: "Describe this".to_string(),
+                images: vec![image],
+            },
+        )];
+        let result =
+            provider.query_with_content(DEFAULT_MODEL, &messages, None, &QueryOptions::default());
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "A chart.");
+    }
+
+    #[test]
+    fn test_claude_keeps_thinking_out_of_reply() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "content": [
+                    {"type": "thinking", "thinking": "Two plus two is four.", "signature": "sig"},
+                    {"type": "text", "text": "4"}
+                  ],
+                  "stop_reason": "end_turn"
+                }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "2+2?", None);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "4");
+        assert_eq!(
+            provider.last_thinking().as_deref(),
+            Some("Two plus two is four.")
+        );
+    }
+
+    #[test]
+    fn test_claude_parses_tool_use_blocks() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "tools": [{
+                    "name": "get_weather",
+                    "description": "Current weather for a city",
+                    "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}}
+                }]
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "content": [
+                    {"type": "text", "text": "Checking."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}
+                  ],
+                  "stop_reason": "tool_use"
+                }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            tools: vec![weather_tool()],
+            ..Default::default()
+        };
+        let result = provider.query_with_history(
+            DEFAULT_MODEL,
+            &[(Role::User, "Weather in Paris?".to_string())],
+            None,
+            &options,
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Checking.");
+        assert_eq!(
+            provider.last_tool_calls(),
+            [ToolCall {
+                name: "get_weather".to_string(),
+                arguments: json!({"city": "Paris"}),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_claude_default_max_tokens_follows_model() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "model": DEFAULT_MODEL,
+                "max_tokens": 64000
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+        provider.query(DEFAULT_MODEL, "Hi", None).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_claude_sends_generation_options() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "temperature": 0.75,
+                "max_tokens": 16000,
+                "stop_sequences": ["</answer>"]
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let options = QueryOptions {
+            temperature: Some(0.75),
+            max_tokens: Some(16000),
+            stop: vec!["</answer>".to_string()],
+            ..Default::default()
         };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
 
-        let result = provider.query("test", DEFAULT_MODEL, true);
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_claude_sends_extra_fields_without_overriding() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "top_p": 0.9,
+                "top_k": 40,
+                "max_tokens": 16000
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let options = QueryOptions {
+            max_tokens: Some(16000),
+            extra: json!({"top_p": 0.9, "top_k": 40, "max_tokens": 1})
+                .as_object()
+                .unwrap()
+                .clone(),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
 
         mock.assert();
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_claude_does_not_send_seed() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_request(|request| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(request.body().unwrap()).unwrap();
+                body.get("seed").is_none()
+            })
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let options = QueryOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_claude_parses_usage() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "content": [{"type": "text", "text": "ok"}],
+                  "usage": {"input_tokens": 12, "output_tokens": 34}
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        assert!(provider.last_usage().is_none());
+
+        provider.query(DEFAULT_MODEL, "Hi", None).unwrap();
+
+        mock.assert();
+        assert_eq!(
+            provider.last_usage(),
+            Some(Usage {
+                input_tokens: 12,
+                output_tokens: 34
+            })
+        );
+    }
+
+    #[test]
+    fn test_claude_retries_rate_limits() {
+        let mut server = setup_mock_server();
+
+        let rate_limited = server
+            .mock("POST", "/v1/messages")
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .with_body(r#"{"error": {"message": "Rate limited"}}"#)
+            .expect(2)
+            .create();
+        let success = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .expect(1)
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "Hi", None);
+
+        rate_limited.assert();
+        success.assert();
+        assert_eq!(result.unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_claude_reports_overload_with_retry_hint() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(529)
+            .with_header("retry-after", "0")
+            .with_body(r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#)
+            .expect(2)
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            max_retries: Some(1),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let err = provider
+            .query_with_history(DEFAULT_MODEL, &messages, None, &options)
+            .unwrap_err();
+
+        mock.assert();
+        let message = err.to_string();
+        assert!(message.contains("overloaded"), "{}", message);
+        assert!(message.contains("try again"), "{}", message);
+        assert_eq!(crate::error::exit_code(&err), 7);
+        assert!(crate::error::is_fallback_error(&err));
+    }
+
+    #[test]
+    fn test_claude_gives_up_after_max_retries() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body("unavailable")
+            .expect(2)
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            max_retries: Some(1),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert!(result.unwrap_err().to_string().contains("503"));
+    }
+
+    #[test]
+    fn test_claude_reports_timeouts() {
+        // A server that accepts connections but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = setup_mock_server();
+        let provider = ClaudeProvider {
+            base_url,
+            ..test_provider(&server)
+        };
+
+        let options = QueryOptions {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("timed out after 100ms"), "{}", error);
+        drop(listener);
+    }
+
+    #[test]
+    fn test_claude_reuses_agent_across_queries() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "ok"}]}"#)
+            .expect(3)
+            .create();
+
+        let provider = test_provider(&server);
+        for _ in 0..3 {
+            assert_eq!(provider.query(DEFAULT_MODEL, "Hi", None).unwrap(), "ok");
+        }
+
+        mock.assert();
+    }
 }
@@ -1,37 +1,220 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Result;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::claude::ClaudeProvider;
+use crate::error::AikaError;
+use crate::input::TruncateStrategy;
+use crate::models_cache::DEFAULT_TTL;
+use crate::output::OUTPUT_STYLES;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Credentials {
     pub anthropic_api_key: Option<String>,
     pub mistral_api_key: Option<String>,
     pub openai_api_key: Option<String>,
+    pub azure_openai_api_key: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Credentials {
+    /// Override keys with the ones set in `other`
+    pub fn merge(&mut self, other: Credentials) {
+        self.anthropic_api_key = other.anthropic_api_key.or(self.anthropic_api_key.take());
+        self.mistral_api_key = other.mistral_api_key.or(self.mistral_api_key.take());
+        self.openai_api_key = other.openai_api_key.or(self.openai_api_key.take());
+        self.azure_openai_api_key = other
+            .azure_openai_api_key
+            .or(self.azure_openai_api_key.take());
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Provider {
-    pub model: String,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub max_retries: Option<u32>,
+    pub timeout_secs: Option<u64>,
+    pub proxy: Option<String>,
+    /// `anthropic-version` header sent to the Anthropic API, or the
+    /// `api-version` query parameter sent to Azure OpenAI
+    pub api_version: Option<String>,
+    /// Beta features enabled through the `anthropic-beta` header
+    pub anthropic_beta: Option<Vec<String>>,
+    /// Azure OpenAI resource endpoint, such as `https://my-resource.openai.azure.com`
+    pub endpoint: Option<String>,
+    /// Azure OpenAI deployment queries are sent to
+    pub deployment: Option<String>,
+    /// Fields added to every request body, such as `top_p`
+    pub extra: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Whether OpenAI streams ask for token usage with `stream_options`;
+    /// on unless turned off for compatible gateways that reject it
+    pub stream_usage: Option<bool>,
+}
+
+impl Provider {
+    /// Override settings with the ones set in `other`
+    pub fn merge(&mut self, other: Provider) {
+        self.model = other.model.or(self.model.take());
+        self.base_url = other.base_url.or(self.base_url.take());
+        self.temperature = other.temperature.or(self.temperature);
+        self.max_tokens = other.max_tokens.or(self.max_tokens);
+        self.max_retries = other.max_retries.or(self.max_retries);
+        self.timeout_secs = other.timeout_secs.or(self.timeout_secs);
+        self.proxy = other.proxy.or(self.proxy.take());
+        self.api_version = other.api_version.or(self.api_version.take());
+        self.anthropic_beta = other.anthropic_beta.or(self.anthropic_beta.take());
+        self.endpoint = other.endpoint.or(self.endpoint.take());
+        self.deployment = other.deployment.or(self.deployment.take());
+        self.extra = other.extra.or(self.extra.take());
+        self.stream_usage = other.stream_usage.or(self.stream_usage);
+    }
+}
+
+/// Named set of overrides applied over the base config with `--profile`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub credentials: Option<Credentials>,
+    #[serde(default)]
+    pub providers: HashMap<String, Provider>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Input {
     pub command: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Prompt {
     pub prompt: String,
+    /// Model used with this prompt unless `--model` is given
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Provider used with this prompt unless `--provider` is given
+    #[serde(default)]
+    pub provider: Option<String>,
+}
+
+/// Step of a `[[pipeline.<name>]]` chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStep {
+    /// Name of a prompt in config, or `inline:<template>`
+    pub prompt: String,
+    /// Model for this step; defaults to the model of the pipeline run
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Contents of a separate credentials file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CredentialsFile {
+    credentials: Credentials,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub credentials: Option<Credentials>,
+    /// TOML or JSON file with a `credentials` table merged over `credentials`
+    #[serde(default)]
+    pub credentials_file: Option<String>,
     pub providers: HashMap<String, Provider>,
     pub inputs: HashMap<String, Input>,
     pub prompts: HashMap<String, Prompt>,
+    #[serde(default)]
+    pub system: HashMap<String, Prompt>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Providers tried in order when the selected one fails
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    /// Provider used when `--provider` is not given
+    #[serde(default)]
+    pub default_provider: Option<String>,
+    /// Short names for model IDs, shared or per provider
+    #[serde(default)]
+    pub model_aliases: HashMap<String, ModelAlias>,
+    /// How long `list-models` reuses a provider's cached models list
+    #[serde(default)]
+    pub models_cache_ttl_secs: Option<u64>,
+    /// Output style used when `--output` is not given
+    #[serde(default)]
+    pub default_output: Option<String>,
+    /// Named chains of prompts run with `aika pipeline`
+    #[serde(default)]
+    pub pipeline: HashMap<String, Vec<PipelineStep>>,
+    /// Longest input sent, in characters, when `--max-input-chars` is not given
+    #[serde(default)]
+    pub max_input_chars: Option<usize>,
+    /// What to do with longer input when `--truncate` is not given
+    #[serde(default)]
+    pub truncate: Option<TruncateStrategy>,
+}
+
+/// Entry of `[model_aliases]`: an alias for every provider, or a table of
+/// aliases that only apply to the provider it is named after
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ModelAlias {
+    Model(String),
+    Provider(HashMap<String, String>),
+}
+
+impl Config {
+    /// API key for `provider` defined in the config file, if any
+    pub fn credential(&self, provider: &str) -> Option<&str> {
+        let credentials = self.credentials.as_ref()?;
+        match provider {
+            "anthropic" => credentials.anthropic_api_key.as_deref(),
+            "mistral" => credentials.mistral_api_key.as_deref(),
+            "openai" => credentials.openai_api_key.as_deref(),
+            "azure" => credentials.azure_openai_api_key.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Model ID for `model` used with `provider`: an alias of the provider's
+    /// namespace, then a shared alias; anything else is already an ID
+    pub fn resolve_model(&self, provider: &str, model: &str) -> String {
+        let scoped = match self.model_aliases.get(provider) {
+            Some(ModelAlias::Provider(aliases)) => aliases.get(model),
+            _ => None,
+        };
+        let shared = match self.model_aliases.get(model) {
+            Some(ModelAlias::Model(id)) => Some(id),
+            _ => None,
+        };
+
+        scoped.or(shared).map_or(model, String::as_str).to_string()
+    }
+
+    /// Age after which a cached models list is fetched again
+    pub fn models_cache_ttl(&self) -> Duration {
+        self.models_cache_ttl_secs
+            .map_or(DEFAULT_TTL, Duration::from_secs)
+    }
+}
+
+/// Environment variable holding the API key for `provider`
+pub fn credential_env_var(provider: &str) -> Option<&'static str> {
+    match provider {
+        "anthropic" => Some("ANTHROPIC_API_KEY"),
+        "mistral" => Some("MISTRAL_API_KEY"),
+        "openai" => Some("OPENAI_API_KEY"),
+        "azure" => Some("AZURE_OPENAI_API_KEY"),
+        _ => None,
+    }
 }
 
 impl Default for Config {
@@ -40,66 +223,655 @@ impl Default for Config {
     }
 }
 
-pub fn load_config(config_file: &str) -> Result<Config> {
-    let default_config_path = dirs::config_dir()
+/// Load variables from `path` (from `--env-file`), or else from a `.env` in
+/// the current directory when there is one. Variables already set in the
+/// environment are left alone.
+pub fn load_env_file(path: Option<&Path>) -> Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(".env").is_file() => Path::new(".env"),
+        None => return Ok(()),
+    };
+
+    for (key, value) in env_file_vars(path, |name| std::env::var_os(name).is_some())? {
+        // SAFETY: called once at startup, before aika starts any other thread
+        unsafe { std::env::set_var(key, value) };
+    }
+    log::debug!("Loaded environment from {:?}", path);
+    Ok(())
+}
+
+/// Variables of the env file at `path` that `is_set` reports as not set yet
+pub fn env_file_vars<F>(path: &Path, is_set: F) -> Result<Vec<(String, String)>>
+where
+    F: Fn(&str) -> bool,
+{
+    let env_error =
+        |e: dotenvy::Error| AikaError::Config(format!("Failed to load env file {:?}: {}", path, e));
+
+    let mut vars = Vec::new();
+    for item in dotenvy::from_path_iter(path).map_err(env_error)? {
+        let (key, value) = item.map_err(env_error)?;
+        if !is_set(&key) {
+            vars.push((key, value));
+        }
+    }
+    Ok(vars)
+}
+
+/// Path of the config file: `config_file` when given, else the default location
+pub fn config_path(config_file: &str) -> Result<PathBuf> {
+    if !config_file.is_empty() {
+        return Ok(config_file.into());
+    }
+
+    Ok(dirs::config_dir()
         .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?
         .join("aika-rs")
-        .join("config.toml");
+        .join("config.toml"))
+}
 
-    let config_path: PathBuf = if !config_file.is_empty() {
-        config_file.into()
+/// Former name of the `[providers.anthropic]` section
+const LEGACY_ANTHROPIC_KEY: &str = "claude";
+
+/// Move settings under the legacy `claude` key to `anthropic`, where the
+/// Anthropic provider reads them. Settings under `anthropic` take precedence.
+fn normalize_provider_keys(providers: &mut HashMap<String, Provider>) {
+    let Some(legacy) = providers.remove(LEGACY_ANTHROPIC_KEY) else {
+        return;
+    };
+    log::warn!(
+        "[providers.{}] is deprecated; rename it to [providers.{}]",
+        LEGACY_ANTHROPIC_KEY,
+        ClaudeProvider::PROVIDER_NAME
+    );
+
+    let mut merged = legacy;
+    if let Some(current) = providers.remove(ClaudeProvider::PROVIDER_NAME) {
+        merged.merge(current);
+    }
+    providers.insert(ClaudeProvider::PROVIDER_NAME.to_string(), merged);
+}
+
+/// Merge the named profile over the base config.
+///
+/// Without an explicit name, the `default` profile is applied when defined.
+pub fn apply_profile(config: &mut Config, profile: Option<&str>) -> Result<()> {
+    let selected = match profile {
+        Some(name) => Some(
+            config
+                .profiles
+                .remove(name)
+                .ok_or_else(|| anyhow::anyhow!("Profile '{}' not found in config", name))?,
+        ),
+        None => config.profiles.remove("default"),
+    };
+
+    let Some(selected) = selected else {
+        return Ok(());
+    };
+
+    if let Some(credentials) = selected.credentials {
+        config
+            .credentials
+            .get_or_insert_with(Credentials::default)
+            .merge(credentials);
+    }
+
+    for (name, provider) in selected.providers {
+        config.providers.entry(name).or_default().merge(provider);
+    }
+
+    Ok(())
+}
+
+/// Read the `credentials` table of a TOML or JSON (by extension) credentials file.
+///
+/// On Unix, a file readable by group or others is accepted with a warning.
+pub fn load_credentials_file(path: &Path) -> Result<Credentials> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read credentials file {:?}: {}", path, e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            log::warn!(
+                "Credentials file {:?} is accessible by other users (mode {:o}); consider chmod 600",
+                path,
+                mode & 0o777
+            );
+        }
+    }
+
+    let file: CredentialsFile = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid credentials file {:?}: {}", path, e))?
+    } else {
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid credentials file {:?}: {}", path, e))?
+    };
+
+    Ok(file.credentials)
+}
+
+/// Field of the `credentials` table holding the API key for `provider`
+pub fn credential_field(provider: &str) -> Option<&'static str> {
+    match provider {
+        "anthropic" => Some("anthropic_api_key"),
+        "mistral" => Some("mistral_api_key"),
+        "openai" => Some("openai_api_key"),
+        "azure" => Some("azure_openai_api_key"),
+        _ => None,
+    }
+}
+
+/// File `aika login` stores keys in: `override_path` (from `--credentials`),
+/// then the `credentials_file` setting, then the config file itself
+pub fn credentials_target(
+    config: &Config,
+    config_path: &Path,
+    override_path: Option<&Path>,
+) -> PathBuf {
+    credentials_file_path(config, override_path, config_path.parent())
+        .unwrap_or_else(|| config_path.to_path_buf())
+}
+
+/// Credentials file to read: `override_path` (from `--credentials`), else the
+/// `credentials_file` setting, which is relative to the config file directory
+fn credentials_file_path(
+    config: &Config,
+    override_path: Option<&Path>,
+    config_dir: Option<&Path>,
+) -> Option<PathBuf> {
+    match (override_path, &config.credentials_file) {
+        (Some(path), _) => Some(path.to_path_buf()),
+        (None, Some(path)) => Some(match config_dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }),
+        (None, None) => None,
+    }
+}
+
+/// Set the API key of `provider` in the `credentials` table of the TOML or
+/// JSON (by extension) file at `path`, creating the file if needed.
+///
+/// The rest of the file, including comments in TOML, is kept as it was. On
+/// Unix the file is made readable by its owner only.
+pub fn save_credential(path: &Path, provider: &str, api_key: &str) -> Result<()> {
+    let field = credential_field(provider)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported provider: {}", provider))?;
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read {:?}: {}", path, e)),
+    };
+    let invalid = |e: &dyn std::fmt::Display| anyhow::anyhow!("Invalid file {:?}: {}", path, e);
+
+    let content = if path.extension().is_some_and(|ext| ext == "json") {
+        let mut file: serde_json::Value = if content.trim().is_empty() {
+            serde_json::json!({})
+        } else {
+            serde_json::from_str(&content).map_err(|e| invalid(&e))?
+        };
+        let credentials = file
+            .as_object_mut()
+            .ok_or_else(|| invalid(&"expected a JSON object"))?
+            .entry("credentials")
+            .or_insert_with(|| serde_json::json!({}));
+        credentials
+            .as_object_mut()
+            .ok_or_else(|| invalid(&"credentials is not an object"))?
+            .insert(field.to_string(), serde_json::json!(api_key));
+        serde_json::to_string_pretty(&file)? + "\n"
     } else {
-        default_config_path
+        let mut file: toml_edit::DocumentMut = content.parse().map_err(|e| invalid(&e))?;
+        let credentials = file
+            .entry("credentials")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| invalid(&"credentials is not a table"))?;
+        credentials[field] = toml_edit::value(api_key);
+        file.to_string()
     };
 
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)
+        .map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", path, e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(())
+}
+
+/// Merge the credentials file picked by `credentials_file_path` over the
+/// inline credentials
+fn merge_credentials_file(
+    config: &mut Config,
+    override_path: Option<&Path>,
+    config_dir: Option<&Path>,
+) -> Result<()> {
+    let Some(path) = credentials_file_path(config, override_path, config_dir) else {
+        return Ok(());
+    };
+
+    let credentials = load_credentials_file(&path)?;
+    config
+        .credentials
+        .get_or_insert_with(Credentials::default)
+        .merge(credentials);
+    Ok(())
+}
+
+pub fn load_config(
+    config_file: &str,
+    profile: Option<&str>,
+    credentials_file: Option<&Path>,
+) -> Result<Config> {
+    let config_path = config_path(config_file)?;
+    load_config_from(
+        &config_path,
+        !config_file.is_empty(),
+        profile,
+        credentials_file,
+    )
+}
+
+/// Load the config at `config_path`. A missing file falls back to the
+/// defaults only when it is the auto-discovered path, not an `explicit` one.
+fn load_config_from(
+    config_path: &Path,
+    explicit: bool,
+    profile: Option<&str>,
+    credentials_file: Option<&Path>,
+) -> Result<Config> {
+    let config_error = |e: anyhow::Error| AikaError::Config(format!("{:#}", e));
+
+    if explicit && !config_path.exists() {
+        return Err(AikaError::Config(format!(
+            "Config file {:?} does not exist; create it with `aika config init`",
+            config_path
+        ))
+        .into());
+    }
+
+    if config_path.is_dir() {
+        return Err(AikaError::Config(format!(
+            "Config path {:?} is a directory, not a file",
+            config_path
+        ))
+        .into());
+    }
+
     if !config_path.exists() {
         // Returning default config if file does not exist
-        eprintln!(
+        log::info!(
             "Config file not found at {:?}, using default configuration.",
             config_path
         );
-        return Ok(get_default_config());
+        let mut config = get_default_config();
+        merge_credentials_file(&mut config, credentials_file, None).map_err(config_error)?;
+        apply_profile(&mut config, profile).map_err(config_error)?;
+        expand_config_env(&mut config, |name| std::env::var(name).ok()).map_err(config_error)?;
+        return Ok(config);
     }
 
-    let config_content = std::fs::read_to_string(&config_path)?;
-    let config: Config = toml::from_str(&config_content)?;
+    let config_content = std::fs::read_to_string(config_path).map_err(|e| {
+        AikaError::Config(match e.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                format!("Permission denied reading config file {:?}", config_path)
+            }
+            _ => format!("Failed to read config file {:?}: {}", config_path, e),
+        })
+    })?;
+    let mut config: Config = toml::from_str(&config_content)
+        .map_err(|e| AikaError::Config(format!("Invalid config file {:?}: {}", config_path, e)))?;
+    normalize_provider_keys(&mut config.providers);
+    for profile in config.profiles.values_mut() {
+        normalize_provider_keys(&mut profile.providers);
+    }
+    if let Some(path) = &mut config.credentials_file {
+        *path = expand_env(path, &|name| std::env::var(name).ok()).map_err(config_error)?;
+    }
+    merge_credentials_file(&mut config, credentials_file, config_path.parent())
+        .map_err(config_error)?;
+    apply_profile(&mut config, profile).map_err(config_error)?;
+    expand_config_env(&mut config, |name| std::env::var(name).ok()).map_err(config_error)?;
+    validate_default_output(&config).map_err(config_error)?;
 
     Ok(config)
 }
 
+/// Built-in `summarize` prompt, also used by `aika summarize` when the config has none
+pub const SUMMARIZE_PROMPT: &str = "Summarize the following content. Start with a one-sentence overview, then list the key points as short bullets. For code, explain what it does and how it is organized.\n\n```\n{input}\n```";
+
 pub fn get_default_config() -> Config {
     let mut providers = HashMap::new();
     providers.insert(
-        "claude".to_string(),
+        ClaudeProvider::PROVIDER_NAME.to_string(),
         Provider {
-            model: "claude-3-5-sonnet-latest".to_string(),
+            model: Some("claude-sonnet-4-5-20250929".to_string()),
+            base_url: None,
+            temperature: None,
+            max_tokens: None,
+            max_retries: None,
+            timeout_secs: None,
+            proxy: None,
+            api_version: None,
+            anthropic_beta: None,
+            endpoint: None,
+            deployment: None,
+            extra: None,
+            stream_usage: None,
         },
     );
 
     let mut inputs = HashMap::new();
-    inputs.insert(
-        "git-diff-cached".to_string(),
-        Input {
-            command: "git diff --cached".to_string(),
-        },
-    );
+    for (name, command) in [
+        ("git-diff-cached", "git diff --cached"),
+        ("git-diff", "git diff"),
+        ("git-diff-all", "git diff HEAD"),
+    ] {
+        inputs.insert(
+            name.to_string(),
+            Input {
+                command: command.to_string(),
+            },
+        );
+    }
 
     let mut prompts = HashMap::new();
     prompts.insert(
         "commit-message".to_string(),
         Prompt {
             prompt: "Generate a concise and descriptive git commit message for the following changes:\n\n```\n{input}\n```".to_string(),
+            model: None,
+            provider: None,
+        },
+    );
+    prompts.insert(
+        "summarize".to_string(),
+        Prompt {
+            prompt: SUMMARIZE_PROMPT.to_string(),
+            model: None,
+            provider: None,
         },
     );
 
     Config {
         credentials: None,
+        credentials_file: None,
         providers,
         inputs,
         prompts,
+        system: HashMap::new(),
+        profiles: HashMap::new(),
+        fallback: Vec::new(),
+        default_provider: None,
+        model_aliases: HashMap::new(),
+        models_cache_ttl_secs: None,
+        default_output: None,
+        max_input_chars: None,
+        truncate: None,
+        pipeline: HashMap::new(),
     }
 }
 
+/// Config for tests against a mock server at `url`: an Anthropic API key and
+/// base URL, with nothing else set up
+#[cfg(any(test, feature = "test-provider"))]
+pub fn test_config_for(url: &str) -> Config {
+    let mut providers = HashMap::new();
+    providers.insert(
+        ClaudeProvider::PROVIDER_NAME.to_string(),
+        Provider {
+            base_url: Some(url.to_string()),
+            ..Default::default()
+        },
+    );
+
+    Config {
+        credentials: Some(Credentials {
+            anthropic_api_key: Some("test-key".to_string()),
+            ..Default::default()
+        }),
+        providers,
+        ..Default::default()
+    }
+}
+
+/// Expand `${VAR}` and `$VAR` references in `value`; `$$` is a literal `$`.
+///
+/// Referencing an undefined variable is an error.
+fn expand_env<F>(value: &str, env: &F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+
+        let name = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                result.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => {
+                            return Err(anyhow::anyhow!(
+                                "Unterminated variable reference in {:?}",
+                                value
+                            ));
+                        }
+                    }
+                }
+                name
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || *c == '_') {
+                        break;
+                    }
+                    name.push(*c);
+                    chars.next();
+                }
+                name
+            }
+            _ => {
+                result.push('$');
+                continue;
+            }
+        };
+
+        let expanded = env(&name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Environment variable {} referenced in config is not set",
+                name
+            )
+        })?;
+        result.push_str(&expanded);
+    }
+
+    Ok(result)
+}
+
+/// Expand environment variables in credentials, base URLs and input commands
+fn expand_config_env<F>(config: &mut Config, env: F) -> Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let expand_opt = |value: &mut Option<String>| -> Result<()> {
+        if let Some(v) = value {
+            *v = expand_env(v, &env)?;
+        }
+        Ok(())
+    };
+
+    if let Some(credentials) = &mut config.credentials {
+        expand_opt(&mut credentials.anthropic_api_key)?;
+        expand_opt(&mut credentials.mistral_api_key)?;
+        expand_opt(&mut credentials.openai_api_key)?;
+        expand_opt(&mut credentials.azure_openai_api_key)?;
+    }
+
+    for provider in config.providers.values_mut() {
+        expand_opt(&mut provider.base_url)?;
+        expand_opt(&mut provider.endpoint)?;
+    }
+
+    for input in config.inputs.values_mut() {
+        input.command = expand_env(&input.command, &env)?;
+    }
+
+    Ok(())
+}
+
+/// Fail when `default_output` is not one of the `--output` styles
+fn validate_default_output(config: &Config) -> Result<()> {
+    match &config.default_output {
+        Some(style) if !OUTPUT_STYLES.contains(&style.as_str()) => Err(anyhow::anyhow!(
+            "Unknown default_output {:?}; expected one of {}",
+            style,
+            OUTPUT_STYLES.join(", ")
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Render a commented config file prefilled with the default configuration
+pub fn config_template() -> String {
+    let config = get_default_config();
+    let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+
+    let mut template = String::from(
+        "# aika configuration file\n\
+         \n\
+         # Providers to try in order when the selected one fails\n\
+         # fallback = [\"openai\", \"mistral\"]\n\
+         \n\
+         # Seconds list-models reuses a provider's cached models (6 hours by default)\n\
+         # models_cache_ttl_secs = 21600\n\
+         \n\
+         # Output style when --output is not given: none, json, wrapped, wrapped-preserve or markdown\n\
+         # default_output = \"wrapped\"\n\
+         \n\
+         # Longest input sent, in characters, and what to do with longer input: error, head or middle\n\
+         # max_input_chars = 100000\n\
+         # truncate = \"middle\"\n\
+         \n\
+         # API keys can also be kept in a separate TOML/JSON file with a [credentials] table\n\
+         # credentials_file = \"credentials.toml\"\n\
+         \n\
+         [credentials]\n\
+         # API keys; the ANTHROPIC_API_KEY, OPENAI_API_KEY, MISTRAL_API_KEY and\n\
+         # AZURE_OPENAI_API_KEY environment variables take precedence over these values.\n\
+         # anthropic_api_key = \"\"\n\
+         # openai_api_key = \"\"\n\
+         # mistral_api_key = \"\"\n\
+         # azure_openai_api_key = \"\"\n",
+    );
+
+    let mut providers: Vec<_> = config.providers.iter().collect();
+    providers.sort_by_key(|(name, _)| *name);
+    for (name, provider) in providers {
+        template.push_str(&format!("\n[providers.{}]\n", name));
+        if let Some(model) = &provider.model {
+            template.push_str(&format!("model = {}\n", quote(model)));
+        }
+        template.push_str(
+            "# base_url = \"\"\n\
+             # temperature = 0.0\n\
+             # max_tokens = 4096\n\
+             # max_retries = 3\n\
+             # timeout_secs = 120\n\
+             # proxy = \"\"\n\
+             # extra = { top_p = 0.9 }\n",
+        );
+        if name == ClaudeProvider::PROVIDER_NAME {
+            template.push_str(
+                "# api_version = \"2023-06-01\"\n\
+                 # anthropic_beta = []\n",
+            );
+        }
+    }
+    template.push_str(
+        "\n# Azure OpenAI deployment, used with --provider azure\n\
+         # [providers.azure]\n\
+         # endpoint = \"https://my-resource.openai.azure.com\"\n\
+         # deployment = \"gpt-4o\"\n\
+         # api_version = \"2024-10-21\"\n",
+    );
+
+    let mut inputs: Vec<_> = config.inputs.iter().collect();
+    inputs.sort_by_key(|(name, _)| *name);
+    for (name, input) in inputs {
+        template.push_str(&format!(
+            "\n[inputs.{}]\ncommand = {}\n",
+            name,
+            quote(&input.command)
+        ));
+    }
+
+    let mut prompts: Vec<_> = config.prompts.iter().collect();
+    prompts.sort_by_key(|(name, _)| *name);
+    for (name, prompt) in prompts {
+        template.push_str(&format!(
+            "\n[prompts.{}]\nprompt = {}\n",
+            name,
+            quote(&prompt.prompt)
+        ));
+    }
+
+    template.push_str(
+        "\n# Profiles override credentials and providers; select one with --profile.\n\
+         # [profiles.work.providers.anthropic]\n\
+         # model = \"\"\n",
+    );
+
+    template
+}
+
+/// Write the config template to `path`, creating parent directories.
+///
+/// An existing file is only replaced when `force` is set.
+pub fn write_config_template(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "Config file already exists at {:?}; use --force to overwrite it",
+            path
+        ));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, config_template())?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,7 +885,7 @@ mod tests {
               [inputs.git-diff-cached]
               command = "git diff --cached"
 
-              [providers.claude]
+              [providers.anthropic]
               model = "claude-3-5-sonnet-latest"
 
               [prompts.custom]
@@ -127,19 +899,555 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_system_prompts() {
+        let toml = r#"
+              [inputs.git-diff-cached]
+              command = "git diff --cached"
+
+              [providers.anthropic]
+              model = "claude-3-5-sonnet-latest"
+
+              [prompts.custom]
+              prompt = "Custom prompt with {input}"
+
+              [system.terse]
+              prompt = "Be terse."
+          "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.system["terse"].prompt, "Be terse.");
+    }
+
     #[test]
     fn test_missing_config_uses_defaults() {
-        //let temp = TempDir::new().unwrap();
-        let config = load_config("non-existent-file").unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("aika-rs").join("config.toml");
+        let config = load_config_from(&path, false, None, None).unwrap();
 
         // Should have default prompts
         assert!(config.prompts.contains_key("commit-message"));
     }
 
+    #[test]
+    fn test_credentials_file_is_expanded_with_or_without_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let credentials = temp.path().join("credentials.toml");
+        std::fs::write(
+            &credentials,
+            "[credentials]\nanthropic_api_key = \"sk-$$literal\"\n",
+        )
+        .unwrap();
+        let with_config = temp.path().join("config.toml");
+        std::fs::write(&with_config, "[inputs]\n[prompts]\n[providers]\n").unwrap();
+        let without_config = temp.path().join("missing").join("config.toml");
+
+        for path in [&with_config, &without_config] {
+            let config = load_config_from(path, false, None, Some(&credentials)).unwrap();
+            assert_eq!(config.credential("anthropic"), Some("sk-$literal"));
+        }
+
+        std::fs::write(
+            &credentials,
+            "[credentials]\nanthropic_api_key = \"${AIKA_TEST_UNSET_KEY}\"\n",
+        )
+        .unwrap();
+        assert!(load_config_from(&without_config, false, None, Some(&credentials)).is_err());
+    }
+
+    #[test]
+    fn test_explicit_missing_config_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("missing.toml");
+
+        let err = load_config(path.to_str().unwrap(), None, None).unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+        assert_eq!(crate::error::exit_code(&err), 2);
+    }
+
+    #[test]
+    fn test_config_path_that_is_a_directory_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+
+        for explicit in [true, false] {
+            let err = load_config_from(temp.path(), explicit, None, None).unwrap_err();
+            assert!(err.to_string().contains("is a directory"), "{}", err);
+            assert_eq!(crate::error::exit_code(&err), 2);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_config_reports_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(&path, "[inputs]\n[prompts]\n[providers]\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Root can read the file regardless of its mode
+        if std::fs::read_to_string(&path).is_ok() {
+            return;
+        }
+        let err = load_config(path.to_str().unwrap(), None, None).unwrap_err();
+        assert!(err.to_string().starts_with("Permission denied"), "{}", err);
+    }
+
     #[test]
     fn test_malformed_toml_returns_error() {
         let bad_toml = "this is { not valid toml";
         let result: Result<Config, _> = toml::from_str(bad_toml);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_write_config_template() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("aika-rs").join("config.toml");
+
+        write_config_template(&path, false).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("[credentials]"));
+
+        let config: Config = toml::from_str(&content).unwrap();
+        assert!(config.prompts.contains_key("commit-message"));
+        assert!(config.inputs.contains_key("git-diff-cached"));
+        assert!(config.inputs.contains_key("git-diff"));
+        assert!(config.credentials.is_some());
+    }
+
+    #[test]
+    fn test_config_template_parses_with_every_setting_uncommented() {
+        // Commented settings are `# key = value` lines and `# [table]` headers
+        let uncommented = config_template()
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(setting)
+                    if setting.starts_with('[')
+                        || setting
+                            .split_once(" = ")
+                            .is_some_and(|(key, _)| !key.contains(' ')) =>
+                {
+                    setting
+                }
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let config: Config = toml::from_str(&uncommented).unwrap();
+        assert_eq!(config.credentials_file.as_deref(), Some("credentials.toml"));
+        assert_eq!(config.fallback, ["openai", "mistral"]);
+        assert!(config.credentials.unwrap().anthropic_api_key.is_some());
+        assert!(config.providers.contains_key("azure"));
+        assert!(config.profiles.contains_key("work"));
+    }
+
+    #[test]
+    fn test_default_config_has_git_diff_inputs() {
+        let config = get_default_config();
+        let command = |name: &str| config.inputs[name].command.as_str();
+
+        assert_eq!(command("git-diff-cached"), "git diff --cached");
+        assert_eq!(command("git-diff"), "git diff");
+        assert_eq!(command("git-diff-all"), "git diff HEAD");
+    }
+
+    #[test]
+    fn test_default_config_has_summarize_prompt() {
+        let config = get_default_config();
+        let prompt = &config.prompts["summarize"].prompt;
+
+        assert!(prompt.contains("{input}"));
+        assert!(prompt.starts_with("Summarize"));
+    }
+
+    #[test]
+    fn test_write_config_template_refuses_overwrite() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(&path, "# existing").unwrap();
+
+        assert!(write_config_template(&path, false).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# existing");
+
+        write_config_template(&path, true).unwrap();
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("[prompts.")
+        );
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let toml = r#"
+              [inputs]
+
+              [prompts]
+
+              [providers.anthropic]
+              modell = "claude-3-5-sonnet-latest"
+          "#;
+
+        let err = toml::from_str::<Config>(toml).unwrap_err().to_string();
+        assert!(err.contains("unknown field `modell`"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_config_reports_path_and_key() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(&path, "[inputs]\n[prompts]\n[providers]\n[credential]\n").unwrap();
+
+        let err = load_config(path.to_str().unwrap(), None, None)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("config.toml"));
+        assert!(err.contains("unknown field `credential`"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_config_validates_default_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+
+        std::fs::write(
+            &path,
+            "default_output = \"wrapped\"\n[inputs]\n[prompts]\n[providers]\n",
+        )
+        .unwrap();
+        let config = load_config(path.to_str().unwrap(), None, None).unwrap();
+        assert_eq!(config.default_output.as_deref(), Some("wrapped"));
+
+        std::fs::write(
+            &path,
+            "default_output = \"fancy\"\n[inputs]\n[prompts]\n[providers]\n",
+        )
+        .unwrap();
+        let err = load_config(path.to_str().unwrap(), None, None).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 2);
+        assert!(
+            err.to_string().contains("Unknown default_output \"fancy\""),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_save_credential_keeps_rest_of_toml() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+        write_config_template(&path, false).unwrap();
+
+        save_credential(&path, "openai", "sk-saved").unwrap();
+        save_credential(&path, "mistral", "first").unwrap();
+        save_credential(&path, "mistral", "second").unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# aika configuration file"));
+        let config = load_config(path.to_str().unwrap(), None, None).unwrap();
+        assert_eq!(config.credential("openai"), Some("sk-saved"));
+        assert_eq!(config.credential("mistral"), Some("second"));
+        assert!(config.prompts.contains_key("commit-message"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_save_credential_creates_json_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("secrets.json");
+
+        save_credential(&path, "anthropic", "sk-ant-saved").unwrap();
+
+        let credentials = load_credentials_file(&path).unwrap();
+        assert_eq!(
+            credentials.anthropic_api_key.as_deref(),
+            Some("sk-ant-saved")
+        );
+        assert!(save_credential(&path, "other", "key").is_err());
+    }
+
+    #[test]
+    fn test_env_file_does_not_override_environment() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(".env");
+        std::fs::write(
+            &path,
+            "AIKA_TEST_ENV_SET=from-file\nAIKA_TEST_ENV_NEW=from-file\n",
+        )
+        .unwrap();
+
+        let vars = env_file_vars(&path, |name| name == "AIKA_TEST_ENV_SET").unwrap();
+        assert_eq!(
+            vars,
+            [("AIKA_TEST_ENV_NEW".to_string(), "from-file".to_string())]
+        );
+
+        let err = load_env_file(Some(&temp.path().join("missing.env"))).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 2);
+    }
+
+    #[test]
+    fn test_credentials_target() {
+        let config_path = Path::new("/home/me/.config/aika-rs/config.toml");
+        let mut config = get_default_config();
+        assert_eq!(credentials_target(&config, config_path, None), config_path);
+
+        config.credentials_file = Some("secrets.toml".to_string());
+        assert_eq!(
+            credentials_target(&config, config_path, None),
+            Path::new("/home/me/.config/aika-rs/secrets.toml")
+        );
+        assert_eq!(
+            credentials_target(&config, config_path, Some(Path::new("keys.json"))),
+            Path::new("keys.json")
+        );
+    }
+
+    #[test]
+    fn test_credentials_file_merged_over_config() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(
+            temp.path().join("secrets.toml"),
+            "[credentials]\nopenai_api_key = \"from-file\"\n",
+        )
+        .unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "credentials_file = \"secrets.toml\"\n[inputs]\n[prompts]\n[providers]\n",
+        )
+        .unwrap();
+
+        let config = load_config(path.to_str().unwrap(), None, None).unwrap();
+        assert_eq!(config.credential("openai"), Some("from-file"));
+        assert_eq!(config.credential("anthropic"), None);
+    }
+
+    #[test]
+    fn test_credentials_flag_overrides_inline_keys() {
+        let temp = tempfile::tempdir().unwrap();
+        let secrets = temp.path().join("secrets.json");
+        std::fs::write(
+            &secrets,
+            r#"{"credentials": {"anthropic_api_key": "from-json"}}"#,
+        )
+        .unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[credentials]\nanthropic_api_key = \"inline\"\nmistral_api_key = \"inline\"\n\
+             [inputs]\n[prompts]\n[providers]\n",
+        )
+        .unwrap();
+
+        let config = load_config(path.to_str().unwrap(), None, Some(&secrets)).unwrap();
+        assert_eq!(config.credential("anthropic"), Some("from-json"));
+        assert_eq!(config.credential("mistral"), Some("inline"));
+    }
+
+    #[test]
+    fn test_legacy_claude_key_is_renamed() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[inputs]\n[prompts]\n\
+             [providers.claude]\nmodel = \"legacy\"\nmax_tokens = 512\n\
+             [providers.anthropic]\nmodel = \"current\"\n",
+        )
+        .unwrap();
+
+        let config = load_config(path.to_str().unwrap(), None, None).unwrap();
+        assert!(!config.providers.contains_key("claude"));
+        let anthropic = &config.providers["anthropic"];
+        assert_eq!(anthropic.model.as_deref(), Some("current"));
+        assert_eq!(anthropic.max_tokens, Some(512));
+    }
+
+    #[test]
+    fn test_resolve_model_aliases() {
+        let config: Config = toml::from_str(
+            r#"
+              [model_aliases]
+              sonnet = "claude-sonnet-4-5-20250929"
+              fast = "claude-haiku-4-5"
+
+              [model_aliases.openai]
+              4o = "gpt-4o"
+              fast = "gpt-5-mini"
+
+              [providers]
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.resolve_model("anthropic", "sonnet"),
+            "claude-sonnet-4-5-20250929"
+        );
+        assert_eq!(config.resolve_model("openai", "4o"), "gpt-4o");
+        assert_eq!(config.resolve_model("openai", "fast"), "gpt-5-mini");
+        assert_eq!(
+            config.resolve_model("anthropic", "fast"),
+            "claude-haiku-4-5"
+        );
+        assert_eq!(config.resolve_model("anthropic", "4o"), "4o");
+        assert_eq!(
+            config.resolve_model("mistral", "mistral-large-latest"),
+            "mistral-large-latest"
+        );
+    }
+
+    #[test]
+    fn test_credential_lookup() {
+        let toml = r#"
+              [credentials]
+              anthropic_api_key = "test-key"
+
+              [inputs]
+
+              [prompts]
+
+              [providers]
+          "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.credential("anthropic"), Some("test-key"));
+        assert_eq!(config.credential("openai"), None);
+        assert_eq!(credential_env_var("mistral"), Some("MISTRAL_API_KEY"));
+    }
+
+    fn test_env(name: &str) -> Option<String> {
+        match name {
+            "AIKA_TEST_KEY" => Some("secret".to_string()),
+            "HOME" => Some("/home/aika".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_env_defined_variables() {
+        assert_eq!(expand_env("${AIKA_TEST_KEY}", &test_env).unwrap(), "secret");
+        assert_eq!(
+            expand_env("$HOME/keys/anthropic", &test_env).unwrap(),
+            "/home/aika/keys/anthropic"
+        );
+        assert_eq!(
+            expand_env("no variables", &test_env).unwrap(),
+            "no variables"
+        );
+    }
+
+    #[test]
+    fn test_expand_env_undefined_variable_errors() {
+        let err = expand_env("${AIKA_UNDEFINED}", &test_env).unwrap_err();
+        assert!(err.to_string().contains("AIKA_UNDEFINED"));
+        assert!(expand_env("${AIKA_TEST_KEY", &test_env).is_err());
+    }
+
+    #[test]
+    fn test_expand_env_escaped_dollar() {
+        assert_eq!(expand_env("price: $$5", &test_env).unwrap(), "price: $5");
+        assert_eq!(expand_env("$$HOME", &test_env).unwrap(), "$HOME");
+        assert_eq!(expand_env("trailing $", &test_env).unwrap(), "trailing $");
+    }
+
+    #[test]
+    fn test_expand_config_env() {
+        let toml = r#"
+              [credentials]
+              anthropic_api_key = "${AIKA_TEST_KEY}"
+
+              [providers.openai]
+              base_url = "https://$AIKA_TEST_KEY.internal/v1"
+
+              [inputs.show]
+              command = "cat $HOME/notes.txt"
+
+              [prompts]
+          "#;
+
+        let mut config: Config = toml::from_str(toml).unwrap();
+        expand_config_env(&mut config, test_env).unwrap();
+
+        assert_eq!(config.credential("anthropic"), Some("secret"));
+        assert_eq!(
+            config.providers["openai"].base_url.as_deref(),
+            Some("https://secret.internal/v1")
+        );
+        assert_eq!(config.inputs["show"].command, "cat /home/aika/notes.txt");
+    }
+
+    const PROFILES_TOML: &str = r#"
+          [credentials]
+          anthropic_api_key = "base-key"
+
+          [providers.anthropic]
+          model = "claude-base"
+          max_tokens = 1024
+
+          [inputs]
+
+          [prompts]
+
+          [profiles.default.providers.anthropic]
+          model = "claude-default"
+
+          [profiles.work.credentials]
+          anthropic_api_key = "work-key"
+
+          [profiles.work.providers.anthropic]
+          model = "claude-work"
+
+          [profiles.work.providers.openai]
+          model = "gpt-work"
+      "#;
+
+    #[test]
+    fn test_apply_named_profile() {
+        let mut config: Config = toml::from_str(PROFILES_TOML).unwrap();
+        apply_profile(&mut config, Some("work")).unwrap();
+
+        assert_eq!(config.credential("anthropic"), Some("work-key"));
+        let anthropic = &config.providers["anthropic"];
+        assert_eq!(anthropic.model.as_deref(), Some("claude-work"));
+        assert_eq!(anthropic.max_tokens, Some(1024));
+        assert_eq!(
+            config.providers["openai"].model.as_deref(),
+            Some("gpt-work")
+        );
+    }
+
+    #[test]
+    fn test_apply_default_profile() {
+        let mut config: Config = toml::from_str(PROFILES_TOML).unwrap();
+        apply_profile(&mut config, None).unwrap();
+
+        assert_eq!(config.credential("anthropic"), Some("base-key"));
+        assert_eq!(
+            config.providers["anthropic"].model.as_deref(),
+            Some("claude-default")
+        );
+    }
+
+    #[test]
+    fn test_apply_unknown_profile_errors() {
+        let mut config: Config = toml::from_str(PROFILES_TOML).unwrap();
+        let err = apply_profile(&mut config, Some("personal")).unwrap_err();
+        assert!(err.to_string().contains("personal"));
+    }
 }
@@ -0,0 +1,130 @@
+//! Conversation carried across runs with `--context-file` and `--append-file`.
+//!
+//! A context file holds one JSON message per line, oldest first:
+//!
+//! ```text
+//! {"role":"user","content":"What is a monad?"}
+//! {"role":"assistant","content":"A monad is ..."}
+//! ```
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::provider::Role;
+
+/// One line of a context file
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ContextMessage {
+    role: Role,
+    content: String,
+}
+
+/// Messages of the context file at `path`, oldest first, to send before the
+/// new prompt. A missing file is an empty context, so the first run of a
+/// conversation needs no setup; blank lines are skipped.
+pub fn load_context(path: &Path) -> Result<Vec<(Role, String)>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::debug!("No context file {:?} yet, starting without one", path);
+            return Ok(Vec::new());
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read context file {:?}", path));
+        }
+    };
+
+    parse_context(&contents).with_context(|| format!("Invalid context file {:?}", path))
+}
+
+fn parse_context(contents: &str) -> Result<Vec<(Role, String)>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let message: ContextMessage =
+                serde_json::from_str(line).with_context(|| format!("line {}", index + 1))?;
+            Ok((message.role, message.content))
+        })
+        .collect()
+}
+
+/// Append `prompt` and the `reply` to it to the context file at `path`,
+/// creating the file if needed
+pub fn append_context(path: &Path, prompt: &str, reply: &str) -> Result<()> {
+    let mut lines = String::new();
+    for (role, content) in [(Role::User, prompt), (Role::Assistant, reply)] {
+        let message = ContextMessage {
+            role,
+            content: content.to_string(),
+        };
+        lines.push_str(&serde_json::to_string(&message)?);
+        lines.push('\n');
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .with_context(|| format!("Failed to append to context file {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_context_reads_messages_in_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("context.jsonl");
+        std::fs::write(
+            &path,
+            "{\"role\":\"user\",\"content\":\"Hi\"}\n\n{\"role\":\"assistant\",\"content\":\"Hello!\"}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_context(&path).unwrap(),
+            [
+                (Role::User, "Hi".to_string()),
+                (Role::Assistant, "Hello!".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_context_is_empty_and_bad_lines_are_errors() {
+        let temp = tempfile::tempdir().unwrap();
+        assert!(
+            load_context(&temp.path().join("missing.jsonl"))
+                .unwrap()
+                .is_empty()
+        );
+
+        let err = parse_context("{\"role\":\"user\",\"content\":\"Hi\"}\nnot json\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+    }
+
+    #[test]
+    fn test_append_context_round_trips() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("context.jsonl");
+
+        append_context(&path, "First question", "First answer").unwrap();
+        append_context(&path, "Second question", "Line one\nline two").unwrap();
+
+        let messages = load_context(&path).unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[2], (Role::User, "Second question".to_string()));
+        assert_eq!(
+            messages[3],
+            (Role::Assistant, "Line one\nline two".to_string())
+        );
+    }
+}
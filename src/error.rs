@@ -0,0 +1,184 @@
+//! Failure classes that map to distinct process exit codes.
+
+use std::fmt;
+
+use crate::output::{OutputStream, should_colorize};
+use crate::redact::redact;
+
+/// Exit code for errors without a more specific class
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Errors callers may want to tell apart, each with its own exit code.
+///
+/// They travel inside `anyhow::Error` like any other error; [`exit_code`]
+/// looks for them in the chain.
+#[derive(Debug)]
+pub enum AikaError {
+    /// The provider rejected the credentials (HTTP 401/403)
+    Auth(String),
+    /// The provider is rate limiting requests (HTTP 429)
+    RateLimit(String),
+    /// The provider could not be reached or did not answer in time
+    Network(String),
+    /// The provider failed to handle the request (HTTP 5xx)
+    Server(String),
+    /// The config file could not be read, parsed or resolved
+    Config(String),
+    /// There was nothing to send to the provider
+    EmptyInput(String),
+    /// Ctrl-C stopped a streamed reply
+    Interrupted(String),
+}
+
+impl AikaError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AikaError::Config(_) => 2,
+            AikaError::Auth(_) => 3,
+            AikaError::RateLimit(_) => 4,
+            AikaError::Network(_) => 5,
+            AikaError::EmptyInput(_) => 6,
+            AikaError::Server(_) => 7,
+            AikaError::Interrupted(_) => 130,
+        }
+    }
+}
+
+impl fmt::Display for AikaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AikaError::Auth(message)
+            | AikaError::RateLimit(message)
+            | AikaError::Network(message)
+            | AikaError::Server(message)
+            | AikaError::Config(message)
+            | AikaError::EmptyInput(message)
+            | AikaError::Interrupted(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for AikaError {}
+
+/// Build the error for a non-success API response, classified by status.
+///
+/// Keys echoed back in `body` are masked.
+pub fn api_error(provider: &str, status: u16, body: &str) -> anyhow::Error {
+    let message = redact(&format!("{} API error ({}): {}", provider, status, body));
+    match status {
+        401 | 403 => AikaError::Auth(message).into(),
+        429 => AikaError::RateLimit(message).into(),
+        500..=599 => AikaError::Server(message).into(),
+        _ => anyhow::anyhow!(message),
+    }
+}
+
+/// Exit code for `error`, from the first [`AikaError`] in its chain
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<AikaError>())
+        .map_or(EXIT_FAILURE, AikaError::exit_code)
+}
+
+/// Whether another provider may succeed where this failure happened: bad or
+/// missing credentials, rate limits, network and server errors, but not
+/// requests the provider rejected as invalid
+pub fn is_fallback_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<AikaError>())
+        .is_some_and(|error| {
+            matches!(
+                error,
+                AikaError::Auth(_)
+                    | AikaError::RateLimit(_)
+                    | AikaError::Network(_)
+                    | AikaError::Server(_)
+            )
+        })
+}
+
+/// Print `error` with its causes to stderr, in red when stderr is colorized
+pub fn report(error: &anyhow::Error) {
+    let color = should_colorize(OutputStream::Stderr);
+
+    let message = redact(&format!("{:#}", error));
+    if color {
+        eprintln!("\x1b[31mError: {}\x1b[0m", message);
+    } else {
+        eprintln!("Error: {}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_per_variant() {
+        let cases = [
+            (AikaError::Config("bad".into()), 2),
+            (AikaError::Auth("bad".into()), 3),
+            (AikaError::RateLimit("bad".into()), 4),
+            (AikaError::Network("bad".into()), 5),
+            (AikaError::EmptyInput("bad".into()), 6),
+            (AikaError::Server("bad".into()), 7),
+            (AikaError::Interrupted("bad".into()), 130),
+        ];
+
+        for (error, code) in cases {
+            assert_eq!(exit_code(&error.into()), code);
+        }
+        assert_eq!(exit_code(&anyhow::anyhow!("other")), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_exit_code_looks_through_context() {
+        let error = anyhow::Error::from(AikaError::Network("unreachable".into()))
+            .context("Failed to query provider");
+        assert_eq!(exit_code(&error), 5);
+    }
+
+    #[test]
+    fn test_api_error_classifies_status() {
+        assert_eq!(exit_code(&api_error("Claude", 401, "")), 3);
+        assert_eq!(exit_code(&api_error("OpenAI", 403, "")), 3);
+        assert_eq!(exit_code(&api_error("Mistral", 429, "")), 4);
+
+        assert_eq!(exit_code(&api_error("Claude", 503, "")), 7);
+
+        let error = api_error("Claude", 400, "bad request");
+        assert_eq!(exit_code(&error), EXIT_FAILURE);
+        assert_eq!(error.to_string(), "Claude API error (400): bad request");
+    }
+
+    #[test]
+    fn test_api_error_masks_keys() {
+        crate::redact::register_secret("configured-key-0123456789");
+
+        let error = api_error(
+            "OpenAI",
+            401,
+            "Incorrect API key provided: sk-proj-abcdefghijklmnop (configured-key-0123456789)",
+        )
+        .context("Failed to query provider");
+        let message = format!("{:#}", error);
+
+        assert_eq!(
+            message,
+            "Failed to query provider: OpenAI API error (401): Incorrect API key provided: **** (****)"
+        );
+    }
+
+    #[test]
+    fn test_is_fallback_error() {
+        assert!(is_fallback_error(&api_error("Claude", 401, "")));
+        assert!(is_fallback_error(&api_error("Claude", 429, "")));
+        assert!(is_fallback_error(&api_error("Claude", 502, "")));
+        assert!(is_fallback_error(&AikaError::Network("down".into()).into()));
+        assert!(!is_fallback_error(&api_error("Claude", 400, "")));
+        assert!(!is_fallback_error(&AikaError::Config("bad".into()).into()));
+        assert!(!is_fallback_error(&anyhow::anyhow!("other")));
+    }
+}
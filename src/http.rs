@@ -0,0 +1,357 @@
+//! HTTP helpers shared by the providers.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ureq::http::{Response, Uri};
+use ureq::{Body, Proxy};
+
+use crate::provider::QueryOptions;
+
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Build an agent that reports HTTP errors as responses and bounds the wait for a reply.
+///
+/// The timeout covers connecting, sending the request and receiving the response
+/// headers, but not reading the body, so long streamed responses are not cut off.
+pub fn build_agent(timeout: Duration, proxy: Option<Proxy>) -> ureq::Agent {
+    ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .proxy(proxy)
+        .timeout_connect(Some(timeout))
+        .timeout_send_request(Some(timeout))
+        .timeout_send_body(Some(timeout))
+        .timeout_recv_response(Some(timeout))
+        .build()
+        .into()
+}
+
+/// Build an agent from the timeout and proxy settings of `options`
+pub fn agent_for_options(options: &QueryOptions, url: &str) -> anyhow::Result<ureq::Agent> {
+    let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let proxy = resolve_proxy(options.proxy.as_deref(), url)?;
+    Ok(build_agent(timeout, proxy))
+}
+
+/// Pick the agent for a request: the provider's shared agent, built from
+/// `defaults`, unless `options` override its timeout or proxy settings.
+pub fn select_agent(
+    shared: &ureq::Agent,
+    defaults: &QueryOptions,
+    options: &QueryOptions,
+    url: &str,
+) -> anyhow::Result<ureq::Agent> {
+    if options.timeout == defaults.timeout && options.proxy == defaults.proxy {
+        Ok(shared.clone())
+    } else {
+        agent_for_options(options, url)
+    }
+}
+
+/// Resolve the proxy to use for requests to `target_url`.
+///
+/// Precedence is the explicit proxy (command line, then config) over the
+/// `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` environment variables. Hosts listed
+/// in `NO_PROXY` are reached directly rather than through a proxy from the
+/// environment.
+pub fn resolve_proxy(explicit: Option<&str>, target_url: &str) -> anyhow::Result<Option<Proxy>> {
+    resolve_proxy_with(explicit, target_url, proxy_env)
+}
+
+/// Proxy environment variable `name`. Tests of code built on the library talk
+/// to local mock servers, so they ignore any proxy set on the machine.
+fn proxy_env(name: &str) -> Option<String> {
+    if cfg!(any(test, feature = "test-provider")) {
+        return None;
+    }
+    std::env::var(name).ok()
+}
+
+fn resolve_proxy_with<F>(
+    explicit: Option<&str>,
+    target_url: &str,
+    env: F,
+) -> anyhow::Result<Option<Proxy>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let env_any = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| env(name))
+            .filter(|v| !v.is_empty())
+    };
+
+    let target: Uri = target_url
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid URL {:?}: {}", target_url, e))?;
+
+    let proxy_url = match explicit {
+        Some(proxy) => Some(proxy.to_string()),
+        None => {
+            if let Some(no_proxy) = env_any(&["NO_PROXY", "no_proxy"])
+                && let Some(host) = target.host()
+                && is_no_proxy(&no_proxy, host)
+            {
+                return Ok(None);
+            }
+
+            if target.scheme_str() == Some("https") {
+                env_any(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"])
+            } else {
+                env_any(&["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"])
+            }
+        }
+    };
+
+    proxy_url
+        .map(|url| {
+            Proxy::new(&url).map_err(|e| anyhow::anyhow!("Invalid proxy URL {:?}: {}", url, e))
+        })
+        .transpose()
+}
+
+/// Whether `host` matches an entry of a comma-separated `NO_PROXY` list
+fn is_no_proxy(no_proxy: &str, host: &str) -> bool {
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
+
+/// Whether a response carries a JSON body, as opposed to an event stream
+pub fn is_json_response(response: &Response<Body>) -> bool {
+    response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim_start().starts_with("application/json"))
+}
+
+/// Whether a response status is worth retrying: rate limits and server errors,
+/// including the 529 Anthropic answers with when overloaded
+pub fn is_retryable(status: u16) -> bool {
+    status == 429 || (500..=599).contains(&status)
+}
+
+/// Delay before the given retry attempt (0-based), honoring `Retry-After` when present
+fn retry_delay(attempt: u32, retry_after: Option<&str>) -> Duration {
+    if let Some(seconds) = retry_after.and_then(|value| value.trim().parse::<u64>().ok()) {
+        return Duration::from_secs(seconds).min(MAX_DELAY);
+    }
+
+    let backoff = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt));
+
+    // Cheap jitter of up to half the backoff, good enough to spread out retries
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let jitter = backoff.mul_f64(f64::from(nanos % 1000) / 2000.0);
+
+    (backoff + jitter).min(MAX_DELAY)
+}
+
+/// Run `send` until it returns a non-retryable status or `max_retries` is exhausted.
+///
+/// The last response is returned as-is so callers keep their own status handling.
+pub fn send_with_retry<F>(max_retries: u32, mut send: F) -> Result<Response<Body>, ureq::Error>
+where
+    F: FnMut() -> Result<Response<Body>, ureq::Error>,
+{
+    let mut attempt = 0;
+    loop {
+        log::debug!("Sending request (attempt {})", attempt + 1);
+        let started = Instant::now();
+        let response = send()?;
+        let status = response.status().as_u16();
+        log::debug!("Received status {} after {:?}", status, started.elapsed());
+
+        if !is_retryable(status) || attempt >= max_retries {
+            return Ok(response);
+        }
+
+        let retry_after = response
+            .headers()
+            .get("retry-after")
+            .and_then(|value| value.to_str().ok());
+        let delay = retry_delay(attempt, retry_after);
+
+        log::debug!(
+            "Request failed with status {}, retrying in {:?} ({}/{})",
+            status,
+            delay,
+            attempt + 1,
+            max_retries
+        );
+
+        std::thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn test_explicit_proxy_is_used_by_agent() {
+        let proxy = resolve_proxy_with(
+            Some("http://proxy.internal:3128"),
+            "https://api.openai.com/v1",
+            env_from(&[("HTTPS_PROXY", "http://env-proxy:8080")]),
+        )
+        .unwrap();
+
+        let agent = build_agent(DEFAULT_TIMEOUT, proxy);
+        let proxy = agent.config().proxy().unwrap();
+        assert_eq!(proxy.host(), "proxy.internal");
+        assert_eq!(proxy.port(), 3128);
+    }
+
+    #[test]
+    fn test_proxy_from_env_by_scheme() {
+        let env = env_from(&[
+            ("HTTPS_PROXY", "http://secure-proxy:8443"),
+            ("HTTP_PROXY", "http://plain-proxy:8080"),
+        ]);
+
+        let https = resolve_proxy_with(None, "https://api.mistral.ai/v1", &env)
+            .unwrap()
+            .unwrap();
+        assert_eq!(https.host(), "secure-proxy");
+
+        let http = resolve_proxy_with(None, "http://localhost:8080", &env)
+            .unwrap()
+            .unwrap();
+        assert_eq!(http.host(), "plain-proxy");
+
+        assert!(
+            resolve_proxy_with(None, "https://api.anthropic.com", env_from(&[]))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_no_proxy_bypasses_proxy() {
+        let env = env_from(&[
+            ("HTTPS_PROXY", "http://proxy:8080"),
+            ("NO_PROXY", "localhost, .internal"),
+        ]);
+
+        assert!(
+            resolve_proxy_with(None, "https://gateway.internal/v1", &env)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            resolve_proxy_with(None, "https://api.openai.com/v1", &env)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_no_proxy_leaves_explicit_proxy_alone() {
+        let env = env_from(&[("NO_PROXY", "localhost, .internal")]);
+
+        let proxy = resolve_proxy_with(Some("http://proxy:3128"), "https://localhost/v1", &env)
+            .unwrap()
+            .unwrap();
+        assert_eq!(proxy.host(), "proxy");
+        assert!(
+            resolve_proxy_with(
+                Some("http://proxy:3128"),
+                "https://gateway.internal/v1",
+                &env
+            )
+            .unwrap()
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn test_tests_ignore_proxy_environment() {
+        assert!(proxy_env("PATH").is_none());
+        assert!(
+            resolve_proxy(None, "https://api.anthropic.com")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_invalid_proxy_url_errors() {
+        let result = resolve_proxy_with(
+            Some("::not a proxy::"),
+            "https://api.openai.com",
+            env_from(&[]),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_select_agent_reuses_shared_agent() {
+        let defaults = QueryOptions {
+            timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let shared = agent_for_options(&defaults, "https://api.anthropic.com").unwrap();
+
+        let same =
+            select_agent(&shared, &defaults, &defaults, "https://api.anthropic.com").unwrap();
+        assert_eq!(
+            same.config().timeouts().connect,
+            Some(Duration::from_secs(30))
+        );
+
+        let overridden = QueryOptions {
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let other =
+            select_agent(&shared, &defaults, &overridden, "https://api.anthropic.com").unwrap();
+        assert_eq!(
+            other.config().timeouts().connect,
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(429));
+        assert!(is_retryable(500));
+        assert!(is_retryable(503));
+        assert!(is_retryable(529));
+        assert!(!is_retryable(400));
+        assert!(!is_retryable(401));
+        assert!(!is_retryable(200));
+    }
+
+    #[test]
+    fn test_retry_delay_honors_retry_after() {
+        assert_eq!(retry_delay(0, Some("2")), Duration::from_secs(2));
+        assert_eq!(retry_delay(0, Some("3600")), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_retry_delay_grows_exponentially() {
+        let first = retry_delay(0, None);
+        let third = retry_delay(2, None);
+        assert!(first >= BASE_DELAY && first <= BASE_DELAY.mul_f64(1.5));
+        assert!(third >= BASE_DELAY * 4);
+    }
+}
@@ -1,89 +1,452 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AikaError;
+use crate::provider::Image;
+use crate::redact::redact;
 
 pub enum Input {
     None,
     Command(Vec<String>),
     Files(Vec<String>),
-    Dir(String),
+    /// Directory walked recursively; when `extensions` is not empty, only
+    /// files with one of these extensions are read. Unless `no_ignore` is set,
+    /// hidden files and paths excluded by `.gitignore`/`.ignore` are skipped.
+    Dir {
+        path: String,
+        extensions: Vec<String>,
+        no_ignore: bool,
+    },
 }
 
 /// Execute a command and return its output as a String
-pub fn get_command_output(cmd: &Vec<&str>, path: &PathBuf, debug: bool) -> anyhow::Result<String> {
-    if debug {
-        eprintln!("Executing command: {:?}", cmd);
-        eprintln!("In directory: {:?}", path);
-    }
-    let output = std::process::Command::new(cmd[0])
-        .args(&cmd[1..])
+pub fn get_command_output(cmd: &Vec<&str>, path: &PathBuf) -> anyhow::Result<String> {
+    log::debug!(
+        "Executing command {} in {:?}",
+        redact(&format!("{:?}", cmd)),
+        path
+    );
+    let Some((program, args)) = cmd.split_first() else {
+        return Err(AikaError::Config(
+            "Empty command; an input needs a command to run".to_string(),
+        )
+        .into());
+    };
+    let output = std::process::Command::new(program)
+        .args(args)
         .current_dir(path)
         .output()
-        .map_err(|e| anyhow::anyhow!("Failed to execute command {:?}: {}", cmd, e))?;
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to execute command {}: {}",
+                redact(&format!("{:?}", cmd)),
+                e
+            )
+        })?;
 
     if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
         return Err(anyhow::anyhow!(
-            "Git command failed with status: {}",
-            output.status
+            "Command {:?} failed with status: {}{}",
+            program,
+            output.status,
+            if stderr.is_empty() {
+                String::new()
+            } else {
+                format!("\n{}", redact(stderr))
+            }
         ));
     }
 
-    let stdout = String::from_utf8(output.stdout)
-        .map_err(|e| anyhow::anyhow!("Failed to parse git output: {}", e))?;
+    // Binary diffs and the like are kept, with replacement characters for invalid bytes
+    let stdout = match String::from_utf8(output.stdout) {
+        Ok(stdout) => stdout,
+        Err(e) => {
+            log::debug!(
+                "Output of {:?} is not valid UTF-8 ({}), replacing invalid bytes",
+                cmd[0],
+                e.utf8_error()
+            );
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    };
 
-    if debug {
-        eprintln!("Command output: {}", stdout);
-    }
+    log::trace!("Command output: {}", redact(&stdout));
 
     Ok(stdout)
 }
 
+/// Most threads reading the files of an input at once
+const MAX_READ_THREADS: usize = 8;
+
+/// Files read before their contents are appended to the input, which bounds
+/// the memory held in flight for large trees
+const READ_BATCH: usize = 64;
+
+/// Read `paths` with `read` on up to `threads` threads and pass each result
+/// to `each`, in the order of `paths` whatever order the reads finish in.
+///
+/// Files are read a batch of [`READ_BATCH`] at a time; an error from `each`
+/// stops before the next batch is read.
+fn for_each_read<T: Send>(
+    paths: &[PathBuf],
+    threads: usize,
+    read: impl Fn(&Path) -> std::io::Result<T> + Sync,
+    mut each: impl FnMut(&Path, std::io::Result<T>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    for batch in paths.chunks(READ_BATCH) {
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, std::io::Result<T>)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.clamp(1, batch.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(path) = batch.get(index) else {
+                                break;
+                            };
+                            results.push((index, read(path)));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+
+        results.sort_by_key(|(index, _)| *index);
+        for ((_, result), path) in results.into_iter().zip(batch) {
+            each(path, result)?;
+        }
+    }
+    Ok(())
+}
+
+/// Threads used to read the files of an input
+fn read_threads() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(MAX_READ_THREADS)
+}
+
 /// Get input based on the specified Input enum variant
-pub fn get_input(input: &Input, path: &PathBuf, debug: bool) -> anyhow::Result<String> {
+pub fn get_input(input: &Input, path: &PathBuf) -> anyhow::Result<String> {
+    read_input_with_threads(input, path, read_threads())
+}
+
+fn read_input_with_threads(
+    input: &Input,
+    path: &PathBuf,
+    threads: usize,
+) -> anyhow::Result<String> {
     match input {
         Input::None => Ok(String::new()),
-        Input::Command(cmd) => {
-            get_command_output(&cmd.iter().map(|s| s.as_str()).collect(), path, debug)
-        }
+        Input::Command(cmd) => get_command_output(&cmd.iter().map(|s| s.as_str()).collect(), path),
         Input::Files(files) => {
+            let paths: Vec<PathBuf> = files.iter().map(|file| path.join(file)).collect();
             let mut contents = String::new();
-            for file in files {
-                let file_path = path.join(file);
-                if debug {
-                    eprintln!("Reading file: {:?}", file_path);
-                }
-                let file_content = std::fs::read_to_string(&file_path)
-                    .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", file_path, e))?;
-                contents.push_str(&file_content);
-                contents.push('\n');
-            }
+            for_each_read(
+                &paths,
+                threads,
+                |file| std::fs::read_to_string(file),
+                |file_path, file_content| {
+                    log::debug!("Reading file: {:?}", file_path);
+                    let file_content = file_content.map_err(|e| {
+                        anyhow::anyhow!("Failed to read file {:?}: {}", file_path, e)
+                    })?;
+                    contents.push_str(&file_content);
+                    contents.push('\n');
+                    Ok(())
+                },
+            )?;
             Ok(contents)
         }
-        Input::Dir(dir) => {
+        Input::Dir {
+            path: dir,
+            extensions,
+            no_ignore,
+        } => {
             let dir_path = path.join(dir);
-            if debug {
-                eprintln!("Reading directory: {:?}", dir_path);
-            }
+            log::debug!("Reading directory: {:?}", dir_path);
+
+            let files = collect_files(&dir_path, extensions, *no_ignore)?;
+
             let mut contents = String::new();
-            for entry in std::fs::read_dir(&dir_path)
-                .map_err(|e| anyhow::anyhow!("Failed to read directory {:?}: {}", dir_path, e))?
-            {
-                let entry =
-                    entry.map_err(|e| anyhow::anyhow!("Failed to read directory entry: {}", e))?;
-                let path = entry.path();
-                if path.is_file() {
-                    if debug {
-                        eprintln!("Reading file: {:?}", path);
-                    }
-                    let file_content = std::fs::read_to_string(&path)
-                        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+            for_each_read(
+                &files,
+                threads,
+                |file| std::fs::read(file),
+                |file, bytes| {
+                    let bytes = bytes
+                        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", file, e))?;
+                    let Ok(file_content) = String::from_utf8(bytes) else {
+                        log::debug!("Skipping non-UTF-8 file: {:?}", file);
+                        return Ok(());
+                    };
+
+                    log::debug!("Reading file: {:?}", file);
+                    let display_path = file.strip_prefix(path).unwrap_or(file);
+                    contents.push_str(&format!("// {}\n", display_path.display()));
                     contents.push_str(&file_content);
                     contents.push('\n');
-                }
-            }
+                    Ok(())
+                },
+            )?;
             Ok(contents)
         }
     }
 }
 
+/// Recursively collect files under `dir` in a deterministic order
+fn collect_files(
+    dir: &Path,
+    extensions: &[String],
+    no_ignore: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!(
+            "Failed to read directory {:?}: not a directory",
+            dir
+        ));
+    }
+
+    let walker = ignore::WalkBuilder::new(dir)
+        .standard_filters(!no_ignore)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = entry.map_err(|e| anyhow::anyhow!("Failed to read directory entry: {}", e))?;
+        let path = entry.path();
+        if path.is_file() && matches_extension(path, extensions) {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+fn matches_extension(path: &Path, extensions: &[String]) -> bool {
+    extensions.is_empty()
+        || path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.iter().any(|allowed| allowed == ext))
+}
+
+/// Parse a `dir:` spec of the form `path[:ext1,ext2]`
+pub fn parse_dir_spec(spec: &str, no_ignore: bool) -> Input {
+    let (path, extensions) = match spec.split_once(':') {
+        Some((path, extensions)) => (
+            path,
+            extensions
+                .split(',')
+                .map(|ext| ext.trim().trim_start_matches('.').to_string())
+                .filter(|ext| !ext.is_empty())
+                .collect(),
+        ),
+        None => (spec, Vec::new()),
+    };
+
+    Input::Dir {
+        path: path.to_string(),
+        extensions,
+        no_ignore,
+    }
+}
+
+/// Expand glob patterns in a `file:` list relative to `path`.
+///
+/// Plain paths are kept as-is; patterns are expanded in sorted order and
+/// duplicates are dropped, keeping the first occurrence.
+pub fn expand_file_patterns(patterns: &[String], path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut files: Vec<String> = Vec::new();
+
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            if !files.contains(pattern) {
+                files.push(pattern.clone());
+            }
+            continue;
+        }
+
+        let full_pattern = path.join(pattern);
+        let matches = glob::glob(&full_pattern.to_string_lossy())
+            .map_err(|e| anyhow::anyhow!("Invalid glob pattern {:?}: {}", pattern, e))?;
+
+        let mut matched = false;
+        for entry in matches {
+            let entry = entry.map_err(|e| anyhow::anyhow!("Failed to read glob match: {}", e))?;
+            if !entry.is_file() {
+                continue;
+            }
+            matched = true;
+
+            let relative = entry
+                .strip_prefix(path)
+                .unwrap_or(&entry)
+                .to_string_lossy()
+                .to_string();
+            if !files.contains(&relative) {
+                files.push(relative);
+            }
+        }
+
+        if !matched {
+            return Err(anyhow::anyhow!(
+                "Pattern {:?} did not match any file",
+                pattern
+            ));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Directory input is read from: `path` when given, else the current directory
+pub fn working_dir(path: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let Some(path) = path else {
+        return Ok(PathBuf::from("."));
+    };
+
+    if !path.exists() {
+        return Err(anyhow::anyhow!(
+            "Working directory {:?} does not exist",
+            path
+        ));
+    }
+    if !path.is_dir() {
+        return Err(anyhow::anyhow!(
+            "Working directory {:?} is not a directory",
+            path
+        ));
+    }
+
+    Ok(path.to_path_buf())
+}
+
+/// Whether assembled input has nothing worth sending, e.g. a diff of a clean index
+pub fn is_empty_input(input: &str) -> bool {
+    input.trim().is_empty()
+}
+
+/// What to do with input longer than `--max-input-chars`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TruncateStrategy {
+    /// Fail without sending anything
+    #[default]
+    Error,
+    /// Keep the beginning and drop the rest
+    Head,
+    /// Keep the beginning and the end, with a marker where the middle was
+    Middle,
+}
+
+/// Byte offset of the `n`th character of `text`, or its length when shorter
+fn char_offset(text: &str, n: usize) -> usize {
+    text.char_indices().nth(n).map_or(text.len(), |(i, _)| i)
+}
+
+/// Cut `input` down to `max_chars` characters with `strategy`.
+///
+/// Shorter input is returned as-is. With [`TruncateStrategy::Middle`] the
+/// elision marker comes on top of the characters kept.
+pub fn limit_input(
+    input: String,
+    max_chars: usize,
+    strategy: TruncateStrategy,
+) -> anyhow::Result<String> {
+    let chars = input.chars().count();
+    if chars <= max_chars {
+        return Ok(input);
+    }
+
+    let omitted = chars - max_chars;
+    let warn = || {
+        log::warn!(
+            "Input is {} characters, dropping {} over the limit of {}",
+            chars,
+            omitted,
+            max_chars
+        )
+    };
+
+    match strategy {
+        TruncateStrategy::Error => Err(anyhow::anyhow!(
+            "Input is {} characters, over --max-input-chars {}; use --truncate head or middle to send part of it",
+            chars,
+            max_chars
+        )),
+        TruncateStrategy::Head => {
+            warn();
+            Ok(input[..char_offset(&input, max_chars)].to_string())
+        }
+        TruncateStrategy::Middle => {
+            warn();
+            let head = char_offset(&input, max_chars.div_ceil(2));
+            let tail = char_offset(&input, chars - max_chars / 2);
+            Ok(format!(
+                "{}\n[... {} characters omitted ...]\n{}",
+                &input[..head],
+                omitted,
+                &input[tail..]
+            ))
+        }
+    }
+}
+
+/// MIME type of an image from its leading bytes, for the formats vision models accept
+pub fn image_media_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Read an image to attach to the prompt, rejecting unsupported formats
+pub fn load_image(path: &Path) -> anyhow::Result<Image> {
+    let data = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read image {:?}: {}", path, e))?;
+    let media_type = image_media_type(&data).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Unsupported image {:?}: expected PNG, JPEG, GIF or WebP",
+            path
+        )
+    })?;
+    Ok(Image { media_type, data })
+}
+
 pub fn from_config(input: &crate::config::Input) -> Input {
     Input::Command(
         input
@@ -93,3 +456,334 @@ pub fn from_config(input: &crate::config::Input) -> Input {
             .collect(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_tree(root: &Path) {
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/nested/lib.rs"), "pub fn lib() {}").unwrap();
+        std::fs::write(root.join("src/Cargo.toml"), "[package]").unwrap();
+        std::fs::write(root.join("src/notes.txt"), "notes").unwrap();
+        std::fs::write(root.join("src/blob.rs"), [0xff, 0xfe, 0x00]).unwrap();
+    }
+
+    #[test]
+    fn test_command_failure_reports_stderr() {
+        let temp = tempfile::tempdir().unwrap();
+        let cmd = vec!["sh", "-c", "echo 'fatal: not a repository' >&2; exit 3"];
+
+        let err = get_command_output(&cmd, &temp.path().to_path_buf()).unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("Command \"sh\" failed"));
+        assert!(message.contains("fatal: not a repository"));
+        assert!(!message.contains("Git"));
+    }
+
+    #[test]
+    fn test_empty_command_is_a_config_error() {
+        let temp = tempfile::tempdir().unwrap();
+
+        let err = get_command_output(&Vec::new(), &temp.path().to_path_buf()).unwrap_err();
+        assert!(err.to_string().starts_with("Empty command"), "{}", err);
+        assert_eq!(crate::error::exit_code(&err), 2);
+
+        let input = from_config(&crate::config::Input {
+            command: "   ".to_string(),
+        });
+        assert!(get_input(&input, &temp.path().to_path_buf()).is_err());
+    }
+
+    #[test]
+    fn test_command_output_with_invalid_utf8_is_decoded_lossily() {
+        let temp = tempfile::tempdir().unwrap();
+        let cmd = vec!["printf", "caf\\303\\251 \\377 ok"];
+
+        let output = get_command_output(&cmd, &temp.path().to_path_buf()).unwrap();
+        assert_eq!(output, "caf\u{e9} \u{fffd} ok");
+    }
+
+    #[test]
+    fn test_command_runs_in_working_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("marker.txt"), "").unwrap();
+
+        let cwd = working_dir(Some(temp.path())).unwrap();
+        let output = get_input(&Input::Command(vec!["ls".to_string()]), &cwd).unwrap();
+        assert!(output.contains("marker.txt"));
+    }
+
+    #[test]
+    fn test_working_dir_validation() {
+        let temp = tempfile::tempdir().unwrap();
+        let file = temp.path().join("file.txt");
+        std::fs::write(&file, "").unwrap();
+
+        assert_eq!(working_dir(None).unwrap(), PathBuf::from("."));
+        assert!(
+            working_dir(Some(&temp.path().join("missing")))
+                .unwrap_err()
+                .to_string()
+                .contains("does not exist")
+        );
+        assert!(
+            working_dir(Some(&file))
+                .unwrap_err()
+                .to_string()
+                .contains("is not a directory")
+        );
+    }
+
+    #[test]
+    fn test_is_empty_input() {
+        assert!(is_empty_input(""));
+        assert!(is_empty_input("  \n\t\n"));
+        assert!(!is_empty_input("diff --git a/x b/x"));
+        assert!(!is_empty_input("\n  x  \n"));
+    }
+
+    #[test]
+    fn test_parse_dir_spec() {
+        let Input::Dir {
+            path, extensions, ..
+        } = parse_dir_spec("src:rs,.toml", false)
+        else {
+            panic!("expected a directory input");
+        };
+        assert_eq!(path, "src");
+        assert_eq!(extensions, vec!["rs", "toml"]);
+
+        let Input::Dir {
+            path, extensions, ..
+        } = parse_dir_spec("src", false)
+        else {
+            panic!("expected a directory input");
+        };
+        assert_eq!(path, "src");
+        assert!(extensions.is_empty());
+    }
+
+    #[test]
+    fn test_dir_input_is_recursive_and_filtered() {
+        let temp = tempfile::tempdir().unwrap();
+        write_tree(temp.path());
+
+        let output =
+            get_input(&parse_dir_spec("src:rs", false), &temp.path().to_path_buf()).unwrap();
+
+        assert_eq!(
+            output,
+            "// src/main.rs\nfn main() {}\n// src/nested/lib.rs\npub fn lib() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_dir_input_without_filter_reads_all_text_files() {
+        let temp = tempfile::tempdir().unwrap();
+        write_tree(temp.path());
+
+        let output = get_input(&parse_dir_spec("src", false), &temp.path().to_path_buf()).unwrap();
+
+        assert!(output.contains("// src/Cargo.toml\n[package]"));
+        assert!(output.contains("// src/notes.txt\nnotes"));
+        assert!(!output.contains("blob.rs"));
+    }
+
+    #[test]
+    fn test_many_files_keep_their_order_whatever_the_threads() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("many");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // More than a batch, with sizes varying so reads finish out of order
+        let names: Vec<String> = (0..READ_BATCH * 3 + 7)
+            .map(|i| format!("file-{:04}.txt", i))
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            let body = format!("contents of {}\n", name).repeat(1 + (i * 37) % 200);
+            std::fs::write(dir.join(name), body).unwrap();
+        }
+
+        let dir_input = Input::Dir {
+            path: "many".to_string(),
+            extensions: Vec::new(),
+            no_ignore: false,
+        };
+        let files_input = Input::Files(
+            names
+                .iter()
+                .rev()
+                .map(|name| format!("many/{}", name))
+                .collect(),
+        );
+        let root = temp.path().to_path_buf();
+
+        let dir_expected = read_input_with_threads(&dir_input, &root, 1).unwrap();
+        let files_expected = read_input_with_threads(&files_input, &root, 1).unwrap();
+        assert!(dir_expected.starts_with("// many/file-0000.txt\n"));
+        assert!(files_expected.starts_with(&format!("contents of {}", names[names.len() - 1])));
+
+        for threads in [2, 4, MAX_READ_THREADS, 32] {
+            for _ in 0..3 {
+                assert_eq!(
+                    read_input_with_threads(&dir_input, &root, threads).unwrap(),
+                    dir_expected
+                );
+                assert_eq!(
+                    read_input_with_threads(&files_input, &root, threads).unwrap(),
+                    files_expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_file_among_many_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("present.txt"), "here").unwrap();
+
+        let input = Input::Files(vec!["present.txt".to_string(), "absent.txt".to_string()]);
+        let err = read_input_with_threads(&input, &temp.path().to_path_buf(), 4).unwrap_err();
+        assert!(err.to_string().contains("absent.txt"), "{}", err);
+    }
+
+    #[test]
+    fn test_dir_input_respects_gitignore() {
+        let temp = tempfile::tempdir().unwrap();
+        write_tree(temp.path());
+        std::fs::create_dir_all(temp.path().join("src/target")).unwrap();
+        std::fs::write(temp.path().join("src/target/build.rs"), "generated").unwrap();
+        std::fs::write(temp.path().join(".gitignore"), "target/\n").unwrap();
+
+        let root = temp.path().to_path_buf();
+
+        let output = get_input(&parse_dir_spec("src:rs", false), &root).unwrap();
+        assert!(!output.contains("generated"));
+        assert!(output.contains("// src/main.rs"));
+
+        let output = get_input(&parse_dir_spec("src:rs", true), &root).unwrap();
+        assert!(output.contains("// src/target/build.rs\ngenerated"));
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_expand_single_star() {
+        let temp = tempfile::tempdir().unwrap();
+        write_tree(temp.path());
+
+        let files = expand_file_patterns(&patterns(&["src/*.rs"]), temp.path()).unwrap();
+        assert_eq!(files, vec!["src/blob.rs", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_expand_double_star_and_dedup() {
+        let temp = tempfile::tempdir().unwrap();
+        write_tree(temp.path());
+
+        let files = expand_file_patterns(
+            &patterns(&["src/main.rs", "src/**/*.rs", "src/nested/lib.rs"]),
+            temp.path(),
+        )
+        .unwrap();
+        assert_eq!(
+            files,
+            vec!["src/main.rs", "src/blob.rs", "src/nested/lib.rs"]
+        );
+    }
+
+    #[test]
+    fn test_expand_plain_paths_are_kept() {
+        let temp = tempfile::tempdir().unwrap();
+
+        let files =
+            expand_file_patterns(&patterns(&["README.md", "src/main.rs"]), temp.path()).unwrap();
+        assert_eq!(files, vec!["README.md", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_expand_unmatched_pattern_errors() {
+        let temp = tempfile::tempdir().unwrap();
+        write_tree(temp.path());
+
+        let result = expand_file_patterns(&patterns(&["src/*.py"]), temp.path());
+        assert!(result.unwrap_err().to_string().contains("did not match"));
+    }
+
+    fn long_input() -> String {
+        (0..100).map(|i| format!("line {:02}\n", i)).collect()
+    }
+
+    #[test]
+    fn test_limit_input_keeps_short_input() {
+        let input = long_input();
+        let limited = limit_input(input.clone(), input.len(), TruncateStrategy::Error).unwrap();
+        assert_eq!(limited, input);
+    }
+
+    #[test]
+    fn test_limit_input_error_strategy_refuses_long_input() {
+        let err = limit_input(long_input(), 100, TruncateStrategy::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Input is 800 characters, over --max-input-chars 100; use --truncate head or middle to send part of it"
+        );
+    }
+
+    #[test]
+    fn test_limit_input_head_strategy_keeps_beginning() {
+        let limited = limit_input(long_input(), 16, TruncateStrategy::Head).unwrap();
+        assert_eq!(limited, "line 00\nline 01\n");
+    }
+
+    #[test]
+    fn test_limit_input_middle_strategy_keeps_both_ends() {
+        let limited = limit_input(long_input(), 16, TruncateStrategy::Middle).unwrap();
+        assert_eq!(
+            limited,
+            "line 00\n\n[... 784 characters omitted ...]\nline 99\n"
+        );
+    }
+
+    #[test]
+    fn test_limit_input_counts_characters_not_bytes() {
+        let input = "é".repeat(10);
+        assert_eq!(
+            limit_input(input.clone(), 3, TruncateStrategy::Head).unwrap(),
+            "ééé"
+        );
+        assert_eq!(
+            limit_input(input, 3, TruncateStrategy::Middle).unwrap(),
+            "éé\n[... 7 characters omitted ...]\né"
+        );
+    }
+
+    #[test]
+    fn test_image_media_type_from_magic_bytes() {
+        assert_eq!(
+            image_media_type(b"\x89PNG\r\n\x1a\n...."),
+            Some("image/png")
+        );
+        assert_eq!(image_media_type(b"\xFF\xD8\xFF\xE0"), Some("image/jpeg"));
+        assert_eq!(image_media_type(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(
+            image_media_type(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(image_media_type(b"%PDF-1.7"), None);
+    }
+
+    #[test]
+    fn test_load_image_rejects_other_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("notes.png");
+        std::fs::write(&path, "not an image").unwrap();
+
+        let err = load_image(&path).unwrap_err().to_string();
+        assert!(err.contains("Unsupported image"), "{}", err);
+    }
+}
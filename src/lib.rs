@@ -0,0 +1,1161 @@
+//! Library behind the `aika` command line tool: config loading, inputs,
+//! output formatting and the Claude, OpenAI and Mistral providers.
+//!
+//! [`run_query`] sends a single prompt the way `aika` does. The functions
+//! next to it run the other commands, [`ask`], [`summarize`], [`run_named_pipeline`]
+//! and [`compare`], and the steps of a query, from [`read_input`] to
+//! [`query_with_fallback`]; the modules give access to the pieces for
+//! anything more involved.
+
+pub mod batch;
+pub mod claude;
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod http;
+pub mod input;
+pub mod mistral;
+#[cfg(any(test, feature = "test-provider"))]
+pub mod mock;
+pub mod models_cache;
+pub mod openai;
+pub mod output;
+pub mod pipeline;
+pub mod provider;
+pub mod redact;
+pub mod repl;
+pub mod request_log;
+pub mod sse;
+pub mod template;
+pub mod tokens;
+pub mod tools;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::claude::ClaudeProvider;
+use crate::config::{Config, PipelineStep, SUMMARIZE_PROMPT};
+use crate::error::{AikaError, is_fallback_error};
+use crate::input::{
+    Input, TruncateStrategy, expand_file_patterns, from_config, get_input, is_empty_input,
+    limit_input, parse_dir_spec,
+};
+use crate::output::StreamEvent;
+use crate::pipeline::{find_pipeline, run_pipeline};
+use crate::provider::{
+    Image, PromptContent, Provider, QueryOptions, QueryResponse, Role, create_provider,
+    text_messages,
+};
+use crate::template::{render_prompt, today};
+
+/// Send `prompt` as a single user message to the provider named
+/// `provider_name`, set up from `config`.
+///
+/// `model` may be an alias from `[model_aliases]`; without one the provider's
+/// default model is used. Options not set in `options` fall back to the
+/// provider's config.
+///
+/// ```
+/// use aika_rs::provider::QueryOptions;
+///
+/// let mut server = mockito::Server::new();
+/// server
+///     .mock("POST", "/v1/messages")
+///     .with_body(r#"{"content": [{"type": "text", "text": "Hello!"}]}"#)
+///     .create();
+///
+/// let config: aika_rs::config::Config = toml::from_str(&format!(
+///     r#"
+///       [credentials]
+///       anthropic_api_key = "test-key"
+///
+///       [providers.anthropic]
+///       base_url = "{}"
+///
+///       [inputs]
+///
+///       [prompts]
+///     "#,
+///     server.url()
+/// ))?;
+///
+/// let response = aika_rs::run_query(
+///     &config,
+///     "anthropic",
+///     Some("claude-sonnet-4-5"),
+///     "Say hello",
+///     &QueryOptions::default(),
+/// )?;
+/// assert_eq!(response.text, "Hello!");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn run_query(
+    config: &Config,
+    provider_name: &str,
+    model: Option<&str>,
+    prompt: &str,
+    options: &QueryOptions,
+) -> anyhow::Result<QueryResponse> {
+    let provider = create_provider(provider_name, config)?;
+    let model = config.resolve_model(
+        &provider.name(),
+        &model.map_or_else(|| provider.model(), str::to_string),
+    );
+    let messages = text_messages(&[(Role::User, prompt.to_string())]);
+    provider.query_full(&model, &messages, None, options)
+}
+
+/// Prompt used when none is selected
+pub const DEFAULT_PROMPT: &str = "commit-message";
+
+/// Template used when the selected prompt is not in config
+pub const FALLBACK_PROMPT: &str = "Generate a concise and descriptive git commit message for the following changes:\n\n```\n{input}\n```";
+
+/// Template of the prompt named `prompt`: an `inline:` template as-is,
+/// otherwise a named config entry, [`DEFAULT_PROMPT`] without a name
+pub fn resolve_prompt_template(config: &Config, prompt: Option<&str>) -> String {
+    let name = prompt.unwrap_or(DEFAULT_PROMPT);
+    if let Some(template) = name.strip_prefix("inline:") {
+        return template.to_string();
+    }
+
+    config
+        .prompts
+        .get(name)
+        .map(|prompt| prompt.prompt.clone())
+        .unwrap_or_else(|| FALLBACK_PROMPT.to_string())
+}
+
+/// Named config prompt selected by `prompt`, the default one without it
+pub fn selected_prompt<'a>(config: &'a Config, prompt: Option<&str>) -> Option<&'a config::Prompt> {
+    config.prompts.get(prompt.unwrap_or(DEFAULT_PROMPT))
+}
+
+/// Model to query: `flag`, then the prompt's `model`, then the
+/// provider's default. A prompt's model is skipped when the prompt names a
+/// provider other than the one in use, as it was chosen for that provider.
+pub fn resolve_query_model(
+    flag: Option<String>,
+    prompt: Option<&config::Prompt>,
+    provider: &dyn Provider,
+) -> String {
+    let prompt_model = prompt
+        .filter(|prompt| {
+            prompt
+                .provider
+                .as_deref()
+                .is_none_or(|name| name == provider.name())
+        })
+        .and_then(|prompt| prompt.model.clone());
+    flag.or(prompt_model).unwrap_or_else(|| provider.model())
+}
+
+/// Messages of a query: the system prompt, the earlier conversation in
+/// `context`, then the prompt with its images
+pub fn query_messages(
+    system: Option<String>,
+    context: Vec<(Role, String)>,
+    prompt: String,
+    images: Vec<Image>,
+) -> Vec<(Role, PromptContent)> {
+    let mut messages = Vec::new();
+    if let Some(system) = system {
+        messages.push((Role::System, PromptContent::Text(system)));
+    }
+    messages.extend(
+        context
+            .into_iter()
+            .map(|(role, content)| (role, PromptContent::Text(content))),
+    );
+    if images.is_empty() {
+        messages.push((Role::User, PromptContent::Text(prompt)));
+    } else {
+        messages.push((
+            Role::User,
+            PromptContent::WithImages {
+                text: prompt,
+                images,
+            },
+        ));
+    }
+    messages
+}
+
+/// Template of a query: the contents of `prompt_file`, else the `prompt` one
+pub fn load_prompt_template(
+    config: &Config,
+    prompt: Option<&str>,
+    prompt_file: Option<&Path>,
+) -> anyhow::Result<String> {
+    match prompt_file {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prompt file {:?}", path)),
+        None => Ok(resolve_prompt_template(config, prompt)),
+    }
+}
+
+/// Resolve the system prompt from either a config entry, literal text or a file
+pub fn resolve_system_prompt(
+    config: &Config,
+    system: Option<&str>,
+    system_file: Option<&Path>,
+) -> anyhow::Result<Option<String>> {
+    if let Some(path) = system_file {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read system prompt file {:?}", path))?;
+        return Ok(Some(content));
+    }
+
+    Ok(system.map(|system| {
+        config
+            .system
+            .get(system)
+            .map(|prompt| prompt.prompt.clone())
+            .unwrap_or_else(|| system.to_string())
+    }))
+}
+
+/// Model ID sent to `provider`: `model`, else the provider's default, with
+/// aliases resolved
+pub fn resolved_model(config: &Config, provider: &dyn Provider, model: Option<&str>) -> String {
+    config.resolve_model(
+        &provider.name(),
+        &model.map_or_else(|| provider.model(), str::to_string),
+    )
+}
+
+/// Send `prompt` as a single user message to `provider`, returning the
+/// model queried with the reply.
+///
+/// Unlike [`run_query`] the provider is set up by the caller, and the reply
+/// is streamed to `on_chunk` when one is given.
+pub fn ask(
+    config: &Config,
+    provider: &dyn Provider,
+    model: Option<&str>,
+    prompt: &str,
+    on_chunk: Option<&mut dyn FnMut(&str)>,
+) -> anyhow::Result<(String, QueryResponse)> {
+    let model = resolved_model(config, provider, model);
+    let messages = text_messages(&[(Role::User, prompt.to_string())]);
+    let response = provider.query_full(&model, &messages, on_chunk, &QueryOptions::default())?;
+    Ok((model, response))
+}
+
+/// Ask `question` of each provider in `providers` concurrently, with its
+/// default model.
+///
+/// Every provider is created and queried on its own thread; the results are
+/// sorted by provider name so the output does not depend on timing.
+pub fn compare(
+    config: &Config,
+    providers: &[String],
+    question: &str,
+) -> Vec<(String, anyhow::Result<String>)> {
+    let mut results: Vec<(String, anyhow::Result<String>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = providers
+            .iter()
+            .map(|name| {
+                let handle = scope.spawn(move || {
+                    let provider = create_provider(name, config)?;
+                    let model = resolved_model(config, provider.as_ref(), None);
+                    log::info!("Querying {} with model {}", name, model);
+                    provider.query(&model, question, None)
+                });
+                (name.clone(), handle)
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|(name, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Query to {} panicked", name)));
+                (name, result)
+            })
+            .collect()
+    });
+
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    results
+}
+
+/// Run `query` against `primary`, then against each fallback provider in turn.
+///
+/// Only failures another provider might not have (see [`is_fallback_error`])
+/// move on to the next one. Fallbacks use their default model. Returns the
+/// provider that answered along with its model and reply.
+pub fn query_with_fallback<T, F>(
+    config: &Config,
+    primary: Box<dyn Provider>,
+    model: &str,
+    fallbacks: &[String],
+    mut query: F,
+) -> anyhow::Result<(Box<dyn Provider>, String, T)>
+where
+    F: FnMut(&dyn Provider, &str) -> anyhow::Result<T>,
+{
+    let mut provider = primary;
+    let mut model = model.to_string();
+    let mut fallbacks = fallbacks.iter();
+
+    loop {
+        log::info!("Querying {} with model {}", provider.name(), model);
+        let mut error = match query(provider.as_ref(), &model) {
+            Ok(response) => return Ok((provider, model, response)),
+            Err(e) if is_fallback_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        loop {
+            let Some(name) = fallbacks.next() else {
+                return Err(error);
+            };
+            log::warn!("{:#}; falling back to {}", error, name);
+
+            match create_provider(name, config) {
+                Ok(next) => {
+                    model = resolved_model(config, next.as_ref(), None);
+                    provider = next;
+                    break;
+                }
+                Err(e) if is_fallback_error(&e) => error = e,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Stream a reply as NDJSON: one `delta` event per chunk, the thinking when
+/// `show_thinking` is set and any tool calls, then a `done` event with the usage
+pub fn query_json_stream(
+    provider: &dyn Provider,
+    model: &str,
+    messages: &[(Role, PromptContent)],
+    options: &QueryOptions,
+    show_thinking: bool,
+    emit: &mut dyn FnMut(&str),
+) -> anyhow::Result<QueryResponse> {
+    let mut on_delta = |text: &str| emit(&StreamEvent::Delta { text }.to_ndjson());
+    let response = provider.query_full(model, messages, Some(&mut on_delta), options)?;
+
+    if show_thinking && let Some(thinking) = provider.last_thinking() {
+        emit(&StreamEvent::Thinking { text: &thinking }.to_ndjson());
+    }
+    for call in provider.last_tool_calls() {
+        emit(
+            &StreamEvent::ToolCall {
+                name: &call.name,
+                arguments: &call.arguments,
+            }
+            .to_ndjson(),
+        );
+    }
+    emit(
+        &StreamEvent::Done {
+            usage: response.usage,
+        }
+        .to_ndjson(),
+    );
+
+    Ok(response)
+}
+
+/// Text of `input`: `file:` patterns, a `dir:` spec, or the name of a
+/// command in config, falling back to the staged diff for unknown names
+pub fn read_input(
+    config: &Config,
+    input: &str,
+    cwd: &PathBuf,
+    no_ignore: bool,
+) -> anyhow::Result<String> {
+    if let Some(input) = input.strip_prefix("file:") {
+        let patterns = input
+            .split(",")
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+        let files = expand_file_patterns(&patterns, cwd)?;
+        get_input(&Input::Files(files), cwd).context("Failed to get input from files")
+    } else if let Some(dir) = input.strip_prefix("dir:") {
+        get_input(&parse_dir_spec(dir, no_ignore), cwd)
+            .context("Failed to get input from directory")
+    } else {
+        let command = match config.inputs.get(input) {
+            Some(command) => command,
+            None => {
+                log::warn!(
+                    "Input '{}' not found in config, using default command.",
+                    input
+                );
+                config.inputs.get("git-diff-cached").ok_or_else(|| {
+                    AikaError::Config(format!(
+                        "Input '{}' not found in config, and there is no git-diff-cached input to use instead",
+                        input
+                    ))
+                })?
+            }
+        };
+
+        get_input(&from_config(command), cwd).context("Failed to get input from config")
+    }
+}
+
+/// Prompt for a summary: the contents of the file or directory at
+/// `path` filled into the `summarize` prompt from config, or the built-in one
+pub fn summarize_prompt(config: &Config, path: &Path, cwd: &PathBuf) -> anyhow::Result<String> {
+    let input = if path.is_dir() {
+        Input::Dir {
+            path: path.to_string_lossy().into_owned(),
+            extensions: Vec::new(),
+            no_ignore: false,
+        }
+    } else {
+        Input::Files(vec![path.to_string_lossy().into_owned()])
+    };
+    let input = get_input(&input, cwd).context("Failed to get input to summarize")?;
+    let input = limit_input_size(input, None, None, config)?;
+    if is_empty_input(&input) {
+        return Err(AikaError::EmptyInput(format!("Nothing to summarize in {:?}", path)).into());
+    }
+
+    let template = config
+        .prompts
+        .get("summarize")
+        .map_or(SUMMARIZE_PROMPT, |prompt| prompt.prompt.as_str());
+    let vars = HashMap::from([("input".to_string(), input), ("date".to_string(), today())]);
+    render_prompt(template, &vars, false)
+}
+
+/// Apply the input size limit in `max_chars` and `truncate`, falling back to
+/// the config
+pub fn limit_input_size(
+    input: String,
+    max_chars: Option<usize>,
+    truncate: Option<TruncateStrategy>,
+    config: &Config,
+) -> anyhow::Result<String> {
+    match max_chars.or(config.max_input_chars) {
+        Some(max_chars) => limit_input(
+            input,
+            max_chars,
+            truncate.or(config.truncate).unwrap_or_default(),
+        ),
+        None => Ok(input),
+    }
+}
+
+/// Provider to use: `flag`, then `default_provider` from config, then Claude
+pub fn resolve_provider<'a>(flag: Option<&'a str>, config: &'a Config) -> &'a str {
+    flag.or(config.default_provider.as_deref())
+        .unwrap_or(ClaudeProvider::PROVIDER_NAME)
+}
+
+/// Fill the query `template` in with `input`, today's date, the `model` and
+/// the name of the `provider`, along with the extra `vars`, which win over
+/// the built-in ones. `strict` is passed on to [`render_prompt`].
+pub fn query_prompt(
+    template: &str,
+    input: String,
+    model: &str,
+    provider: &str,
+    vars: Vec<(String, String)>,
+    strict: bool,
+) -> anyhow::Result<String> {
+    let mut template_vars = HashMap::from([
+        ("input".to_string(), input),
+        ("date".to_string(), today()),
+        ("model".to_string(), model.to_string()),
+        ("provider".to_string(), provider.to_string()),
+    ]);
+    template_vars.extend(vars);
+    render_prompt(template, &template_vars, strict)
+}
+
+/// Summarize the file or directory at `path` with `provider` (see
+/// [`summarize_prompt`]), returning the model queried with the reply.
+///
+/// The model is `model`, then the one of the `summarize` prompt, then the
+/// provider's default.
+pub fn summarize(
+    config: &Config,
+    provider: &dyn Provider,
+    path: &Path,
+    model: Option<String>,
+    cwd: &PathBuf,
+) -> anyhow::Result<(String, QueryResponse)> {
+    let prompt = summarize_prompt(config, path, cwd)?;
+    let model = resolve_query_model(model, selected_prompt(config, Some("summarize")), provider);
+    let model = config.resolve_model(&provider.name(), &model);
+
+    log::info!("Querying {} with model {}", provider.name(), model);
+    let response = provider
+        .query_full(
+            &model,
+            &text_messages(&[(Role::User, prompt)]),
+            None,
+            &QueryOptions::default(),
+        )
+        .context("Failed to query provider")?;
+    Ok((model, response))
+}
+
+/// Run the pipeline called `name` in config on the text of `input` (see
+/// [`read_input`]) and return the reply of its last step.
+///
+/// Steps without a model of their own use `model`, else the provider's
+/// default. `on_step` is called with the index, step and reply of every step
+/// but the last.
+pub fn run_named_pipeline(
+    config: &Config,
+    provider: &dyn Provider,
+    name: &str,
+    input: &str,
+    model: Option<String>,
+    cwd: &PathBuf,
+    on_step: &mut dyn FnMut(usize, &PipelineStep, &str),
+) -> anyhow::Result<String> {
+    let steps = find_pipeline(config, name)?;
+    let input = read_input(config, input, cwd, false)?;
+    let input = limit_input_size(input, None, None, config)?;
+    let default_model = model.unwrap_or_else(|| provider.model());
+
+    let last = steps.len() - 1;
+    run_pipeline(
+        provider,
+        config,
+        steps,
+        &default_model,
+        input,
+        &mut |index, reply| {
+            if index < last {
+                on_step(index, &steps[index], reply);
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config_for;
+    use crate::context::{append_context, load_context};
+    use crate::error::exit_code;
+    use crate::provider::ModelInfo;
+    use std::cell::RefCell;
+
+    /// Model, messages and whether streaming was requested
+    type Call = (String, Vec<(Role, String)>, bool);
+
+    /// Records every query and replies with a fixed text
+    #[derive(Default)]
+    struct RecordingProvider {
+        calls: RefCell<Vec<Call>>,
+    }
+
+    impl Provider for RecordingProvider {
+        fn model(&self) -> String {
+            "default-model".to_string()
+        }
+
+        fn name(&self) -> String {
+            "recording".to_string()
+        }
+
+        fn list_models(&self) -> anyhow::Result<Vec<ModelInfo>> {
+            Ok(Vec::new())
+        }
+
+        fn query(
+            &self,
+            model: &str,
+            prompt: &str,
+            on_chunk: Option<&mut dyn FnMut(&str)>,
+        ) -> anyhow::Result<String> {
+            self.query_with_history(
+                model,
+                &[(Role::User, prompt.to_string())],
+                on_chunk,
+                &QueryOptions::default(),
+            )
+        }
+
+        fn query_with_history(
+            &self,
+            model: &str,
+            messages: &[(Role, String)],
+            on_chunk: Option<&mut dyn FnMut(&str)>,
+            _options: &QueryOptions,
+        ) -> anyhow::Result<String> {
+            let streaming = on_chunk.is_some();
+            if let Some(on_chunk) = on_chunk {
+                on_chunk("reply");
+            }
+            self.calls
+                .borrow_mut()
+                .push((model.to_string(), messages.to_vec(), streaming));
+            Ok("reply".to_string())
+        }
+    }
+
+    #[test]
+    fn test_prompt_model_drives_the_queried_model() {
+        let mut config = Config::default();
+        config.prompts.insert(
+            "quick".to_string(),
+            config::Prompt {
+                prompt: "{input}".to_string(),
+                model: Some("cheap-model".to_string()),
+                provider: None,
+            },
+        );
+        config.prompts.insert(
+            "elsewhere".to_string(),
+            config::Prompt {
+                prompt: "{input}".to_string(),
+                model: Some("gpt-4o-mini".to_string()),
+                provider: Some("openai".to_string()),
+            },
+        );
+        let provider = RecordingProvider::default();
+
+        let model = resolve_query_model(None, selected_prompt(&config, Some("quick")), &provider);
+        provider.query(&model, "Hi", None).unwrap();
+        assert_eq!(provider.calls.borrow()[0].0, "cheap-model");
+
+        // The flag wins over the prompt
+        let prompt = selected_prompt(&config, Some("quick"));
+        assert_eq!(
+            resolve_query_model(Some("flag-model".to_string()), prompt, &provider),
+            "flag-model"
+        );
+        // A prompt without a model, or meant for another provider, keeps the default
+        let prompt = selected_prompt(&config, Some("commit-message"));
+        assert_eq!(
+            resolve_query_model(None, prompt, &provider),
+            "default-model"
+        );
+        let prompt = selected_prompt(&config, Some("elsewhere"));
+        assert_eq!(
+            resolve_query_model(None, prompt, &provider),
+            "default-model"
+        );
+    }
+
+    #[test]
+    fn test_context_file_is_sent_before_the_prompt() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("context.jsonl");
+        std::fs::write(
+            &path,
+            "{\"role\":\"user\",\"content\":\"My name is Ada.\"}\n{\"role\":\"assistant\",\"content\":\"Nice to meet you, Ada.\"}\n",
+        )
+        .unwrap();
+
+        let messages = query_messages(
+            Some("Be brief.".to_string()),
+            load_context(&path).unwrap(),
+            "What is my name?".to_string(),
+            Vec::new(),
+        );
+        let provider = RecordingProvider::default();
+        provider
+            .query_full("model", &messages, None, &QueryOptions::default())
+            .unwrap();
+
+        let calls = provider.calls.borrow();
+        assert_eq!(
+            calls[0].1,
+            [
+                (Role::System, "Be brief.".to_string()),
+                (Role::User, "My name is Ada.".to_string()),
+                (Role::Assistant, "Nice to meet you, Ada.".to_string()),
+                (Role::User, "What is my name?".to_string()),
+            ]
+        );
+
+        // The next run sees this exchange too
+        append_context(&path, "What is my name?", "Ada.").unwrap();
+        assert_eq!(load_context(&path).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_prompt_file_template_is_filled_in() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("release-notes.txt");
+        std::fs::write(&path, "Write release notes for {version}:\n\n{input}\n").unwrap();
+
+        let config = Config::default();
+        let template = load_prompt_template(&config, Some("commit-message"), Some(&path)).unwrap();
+        let vars = HashMap::from([
+            ("input".to_string(), "- fix a crash".to_string()),
+            ("version".to_string(), "1.2.0".to_string()),
+        ]);
+        assert_eq!(
+            render_prompt(&template, &vars, true).unwrap(),
+            "Write release notes for 1.2.0:\n\n- fix a crash\n"
+        );
+
+        let err = load_prompt_template(&config, None, Some(&temp.path().join("missing.txt")))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read prompt file"));
+    }
+
+    #[test]
+    fn test_resolve_system_prompt() {
+        let mut config = Config::default();
+        config.system.insert(
+            "terse".to_string(),
+            config::Prompt {
+                prompt: "Be terse.".to_string(),
+                model: None,
+                provider: None,
+            },
+        );
+
+        let named = resolve_system_prompt(&config, Some("terse"), None).unwrap();
+        assert_eq!(named.as_deref(), Some("Be terse."));
+
+        let literal = resolve_system_prompt(&config, Some("You are a poet."), None).unwrap();
+        assert_eq!(literal.as_deref(), Some("You are a poet."));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system.txt");
+        std::fs::write(&path, "From a file.").unwrap();
+        let from_file = resolve_system_prompt(&config, None, Some(&path)).unwrap();
+        assert_eq!(from_file.as_deref(), Some("From a file."));
+
+        assert!(
+            resolve_system_prompt(&config, None, None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_unknown_input_without_default_is_a_config_error() {
+        let mut config = config::get_default_config();
+        config.inputs.remove("git-diff-cached");
+
+        let err = read_input(&config, "city", &PathBuf::from("."), false).unwrap_err();
+        assert!(err.to_string().contains("'city' not found"), "{}", err);
+        assert_eq!(exit_code(&err), 2);
+    }
+
+    #[test]
+    fn test_query_json_stream_emits_ndjson() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(
+                "data: {\"type\":\"message_start\",\"message\":{\"content\":[],\"usage\":{\"input_tokens\":8,\"output_tokens\":1}}}\n\n\
+                 data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n\
+                 data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\", world\"}}\n\n\
+                 data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":4}}\n\n\
+                 data: {\"type\":\"message_stop\"}\n\n",
+            )
+            .create();
+
+        let config = test_config_for(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let mut lines = Vec::new();
+        let response = query_json_stream(
+            provider.as_ref(),
+            "test-model",
+            &[(Role::User, "Hi".to_string().into())],
+            &QueryOptions::default(),
+            false,
+            &mut |line| lines.push(line.to_string()),
+        )
+        .unwrap();
+
+        mock.assert();
+        assert_eq!(response.text, "Hello, world");
+        assert_eq!(
+            lines,
+            [
+                r#"{"type":"delta","text":"Hello"}"#,
+                r#"{"type":"delta","text":", world"}"#,
+                r#"{"type":"done","usage":{"input_tokens":8,"output_tokens":4}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_json_stream_emits_thinking_and_tool_calls_as_events() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"content": [
+                  {"type": "thinking", "thinking": "Need the weather"},
+                  {"type": "tool_use", "name": "get_weather", "input": {"city": "Paris"}}
+                ]}"#,
+            )
+            .create();
+
+        let config = test_config_for(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let mut lines = Vec::new();
+        query_json_stream(
+            provider.as_ref(),
+            "test-model",
+            &[(Role::User, "Weather in Paris?".to_string().into())],
+            &QueryOptions::default(),
+            true,
+            &mut |line| lines.push(line.to_string()),
+        )
+        .unwrap();
+
+        mock.assert();
+        assert_eq!(
+            lines,
+            [
+                r#"{"type":"thinking","text":"Need the weather"}"#,
+                r#"{"type":"tool_call","name":"get_weather","arguments":{"city":"Paris"}}"#,
+                r#"{"type":"done","usage":null}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summarize_prompt_fills_in_file_or_falls_back() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("notes.md"), "Release on Friday").unwrap();
+        let cwd = temp.path().to_path_buf();
+
+        let config = config::get_default_config();
+        let prompt = summarize_prompt(&config, Path::new("notes.md"), &cwd).unwrap();
+        assert!(prompt.starts_with("Summarize the following content."));
+        assert!(prompt.contains("Release on Friday"));
+
+        // A config file without the prompt still gets the built-in one
+        let config: Config = toml::from_str("[providers]\n[inputs]\n[prompts]\n").unwrap();
+        let prompt = summarize_prompt(&config, temp.path(), &cwd).unwrap();
+        assert!(prompt.contains("// notes.md\nRelease on Friday"));
+
+        std::fs::write(temp.path().join("empty.txt"), "").unwrap();
+        let err = summarize_prompt(&config, Path::new("empty.txt"), &cwd).unwrap_err();
+        assert_eq!(exit_code(&err), 6);
+    }
+
+    #[test]
+    fn test_ask_resolves_model_alias() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({"model": "claude-sonnet-4-5-20250929"}),
+            ))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "Hello"}]}"#)
+            .create();
+
+        let mut config = test_config_for(&server.url());
+        config.model_aliases.insert(
+            "sonnet".to_string(),
+            config::ModelAlias::Model("claude-sonnet-4-5-20250929".to_string()),
+        );
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let (model, response) =
+            ask(&config, provider.as_ref(), Some("sonnet"), "Hi", None).unwrap();
+
+        mock.assert();
+        assert_eq!(model, "claude-sonnet-4-5-20250929");
+        assert_eq!(response.text, "Hello");
+    }
+
+    #[test]
+    fn test_ask_streams_with_default_model() {
+        let provider = RecordingProvider::default();
+        let mut chunks = Vec::new();
+        ask(
+            &Config::default(),
+            &provider,
+            None,
+            "Hi",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(chunks, ["reply"]);
+        let calls = provider.calls.borrow();
+        assert_eq!(calls[0].0, "default-model");
+        assert!(calls[0].2);
+    }
+
+    #[test]
+    fn test_resolve_prompt_template_inline() {
+        let config = config::get_default_config();
+        let template = resolve_prompt_template(&config, Some("inline:Summarize: {input}"));
+        assert_eq!(template, "Summarize: {input}");
+
+        let vars = HashMap::from([("input".to_string(), "the diff".to_string())]);
+        assert_eq!(
+            render_prompt(&template, &vars, true).unwrap(),
+            "Summarize: the diff"
+        );
+    }
+
+    #[test]
+    fn test_resolve_prompt_template_named() {
+        let config = config::get_default_config();
+        assert_eq!(
+            resolve_prompt_template(&config, None),
+            config.prompts["commit-message"].prompt
+        );
+        assert_eq!(
+            resolve_prompt_template(&config, Some("missing")),
+            FALLBACK_PROMPT
+        );
+    }
+
+    #[test]
+    fn test_query_falls_back_on_server_error() {
+        let mut primary = mockito::Server::new();
+        let mut secondary = mockito::Server::new();
+        let failing = primary
+            .mock("POST", "/v1/messages")
+            .with_status(500)
+            .with_body("overloaded")
+            .create();
+        let answering = secondary
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{"id": "chatcmpl-1", "object": "chat.completion", "created": 0, "model": "gpt-test", "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "from openai"}}]}"#,
+            )
+            .create();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+              fallback = ["openai"]
+
+              [credentials]
+              anthropic_api_key = "test-key"
+              openai_api_key = "test-key"
+
+              [providers.anthropic]
+              base_url = "{}"
+              max_retries = 0
+
+              [providers.openai]
+              base_url = "{}"
+              model = "gpt-test"
+
+              [inputs]
+
+              [prompts]
+            "#,
+            primary.url(),
+            secondary.url()
+        ))
+        .unwrap();
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let (provider, model, response) = query_with_fallback(
+            &config,
+            provider,
+            "claude-test",
+            &config.fallback,
+            |p, m| p.query(m, "Hi", None),
+        )
+        .unwrap();
+
+        failing.assert();
+        answering.assert();
+        assert_eq!(provider.name(), "openai");
+        assert_eq!(model, "gpt-test");
+        assert_eq!(response, "from openai");
+    }
+
+    #[test]
+    fn test_query_does_not_fall_back_on_bad_request() {
+        let mut primary = mockito::Server::new();
+        let failing = primary
+            .mock("POST", "/v1/messages")
+            .with_status(400)
+            .with_body("invalid model")
+            .create();
+
+        let config = test_config_for(&primary.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+        let fallback = ["openai".to_string()];
+
+        let mut calls = 0;
+        let result = query_with_fallback(&config, provider, "claude-test", &fallback, |p, m| {
+            calls += 1;
+            p.query(m, "Hi", None)
+        });
+
+        failing.assert();
+        assert_eq!(calls, 1);
+        let Err(error) = result else {
+            panic!("expected the bad request to fail");
+        };
+        assert!(error.to_string().contains("400"));
+    }
+
+    #[test]
+    fn test_compare_collects_every_provider() {
+        let mut anthropic = mockito::Server::new();
+        let mut openai = mockito::Server::new();
+        let claude_mock = anthropic
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "from claude"}]}"#)
+            .create();
+        let openai_mock = openai
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{"id": "chatcmpl-1", "object": "chat.completion", "created": 0, "model": "gpt-test", "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "from openai"}}]}"#,
+            )
+            .create();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+              [credentials]
+              anthropic_api_key = "test-key"
+              openai_api_key = "test-key"
+
+              [providers.anthropic]
+              base_url = "{}"
+
+              [providers.openai]
+              base_url = "{}"
+
+              [inputs]
+
+              [prompts]
+            "#,
+            anthropic.url(),
+            openai.url()
+        ))
+        .unwrap();
+
+        let providers = ["openai".to_string(), "anthropic".to_string()];
+        let results = compare(&config, &providers, "Hi");
+
+        claude_mock.assert();
+        openai_mock.assert();
+        let results: Vec<(&str, &str)> = results
+            .iter()
+            .map(|(name, result)| (name.as_str(), result.as_ref().unwrap().as_str()))
+            .collect();
+        assert_eq!(
+            results,
+            [("anthropic", "from claude"), ("openai", "from openai")]
+        );
+    }
+
+    #[test]
+    fn test_ask_sends_question_verbatim() {
+        let provider = RecordingProvider::default();
+        let (model, response) = ask(
+            &Config::default(),
+            &provider,
+            Some("gpt-5"),
+            "What does {input} mean?",
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(model, "gpt-5");
+        assert_eq!(response.text, "reply");
+        assert_eq!(
+            provider.calls.borrow().as_slice(),
+            [(
+                "gpt-5".to_string(),
+                vec![(Role::User, "What does {input} mean?".to_string())],
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn test_query_prompt_fills_in_builtin_and_extra_vars() {
+        let prompt = query_prompt(
+            "{provider}/{model}: {input} for {version}",
+            "the diff".to_string(),
+            "test-model",
+            "recording",
+            vec![("version".to_string(), "1.2.0".to_string())],
+            true,
+        )
+        .unwrap();
+        assert_eq!(prompt, "recording/test-model: the diff for 1.2.0");
+
+        // Extra vars win over the built-in ones
+        let prompt = query_prompt(
+            "{model}",
+            String::new(),
+            "test-model",
+            "recording",
+            vec![("model".to_string(), "custom".to_string())],
+            true,
+        )
+        .unwrap();
+        assert_eq!(prompt, "custom");
+
+        assert!(query_prompt("{missing}", String::new(), "m", "p", Vec::new(), true).is_err());
+    }
+
+    #[test]
+    fn test_pipeline_reports_every_step_but_the_last() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("notes.md"), "Release on Friday").unwrap();
+        let cwd = temp.path().to_path_buf();
+
+        let config = Config {
+            pipeline: toml::from_str(
+                r#"
+                  [[release]]
+                  prompt = "inline:Summarize: {input}"
+
+                  [[release]]
+                  prompt = "inline:Translate: {input}"
+                  model = "step-model"
+                "#,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+
+        let provider = RecordingProvider::default();
+        let mut steps = Vec::new();
+        let result = run_named_pipeline(
+            &config,
+            &provider,
+            "release",
+            "file:notes.md",
+            None,
+            &cwd,
+            &mut |index, step, reply| steps.push((index, step.prompt.clone(), reply.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(result, "reply");
+        assert_eq!(
+            steps,
+            [(
+                0,
+                "inline:Summarize: {input}".to_string(),
+                "reply".to_string()
+            )]
+        );
+        let calls = provider.calls.borrow();
+        assert_eq!(calls[0].0, "default-model");
+        assert!(calls[0].1[0].1.contains("Release on Friday"));
+        assert_eq!(calls[1].0, "step-model");
+    }
+}
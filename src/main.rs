@@ -1,26 +1,45 @@
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Context;
-use clap::{Parser, Subcommand};
+use clap::builder::PossibleValuesParser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
 
-pub mod config;
-use crate::config::{Provider, load_config};
-
-pub mod provider;
-use crate::provider::create_provider;
-
-pub mod claude;
-pub mod mistral;
-pub mod openai;
-
-pub mod input;
-use crate::input::{Input, from_config, get_input};
-
-pub mod output;
-use crate::output::wrap_text;
-
-pub mod repl;
-use crate::repl::run_repl;
+use aika_rs::batch::{load_batch, run_batch};
+use aika_rs::claude::ClaudeProvider;
+use aika_rs::config::{
+    Config, config_path, credential_env_var, credentials_target, load_config, load_env_file,
+    save_credential, write_config_template,
+};
+use aika_rs::context::{append_context, load_context};
+use aika_rs::error::{AikaError, exit_code, report};
+use aika_rs::http::DEFAULT_TIMEOUT;
+use aika_rs::input::{TruncateStrategy, is_empty_input, load_image, working_dir};
+use aika_rs::mistral::MistralProvider;
+use aika_rs::models_cache::{cache_dir, cached_models};
+use aika_rs::openai::OpenAIProvider;
+use aika_rs::output::{
+    ColorChoice, OutputFile, OutputStream, Spinner, WrapOptions, format_models, format_thinking,
+    highlight_code_blocks, output_width, print_chunk, render_markdown, render_transcript,
+    set_color_choice, should_colorize, spinner_enabled, wrap_text, wrap_text_preserve_breaks,
+};
+use aika_rs::provider::{
+    PROVIDER_NAMES, Provider, QueryOptions, QueryResponse, QueryStats, ReasoningEffort, Role,
+    create_provider, create_provider_with_key, parse_extra_json, request_interrupt, select_models,
+    take_interrupt, timed_query,
+};
+use aika_rs::repl::run_repl;
+use aika_rs::request_log::set_log_file;
+use aika_rs::template::{now, parse_var};
+use aika_rs::tokens::{estimate_input_cost, estimate_message_tokens};
+use aika_rs::tools::load_tools;
+use aika_rs::{
+    ask, compare, limit_input_size, load_prompt_template, query_json_stream, query_messages,
+    query_prompt, query_with_fallback, read_input, resolve_provider, resolve_query_model,
+    resolve_system_prompt, run_named_pipeline, selected_prompt, summarize,
+};
+use aika_rs::{claude, config, mistral, openai, provider};
 
 #[derive(Parser)]
 #[command(name = "aika")]
@@ -30,40 +49,183 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     config: Option<String>,
 
-    #[arg(long, default_value_t = false)]
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with_all = ["verbose", "debug"])]
+    quiet: bool,
+
+    /// Log more details; repeat for more (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log everything; same as -vvv
+    #[arg(long, default_value_t = false, global = true)]
     debug: bool,
 
-    #[arg(short, long, default_value = "anthropic")]
-    provider: String,
+    /// Provider to use; defaults to default_provider in config, then anthropic
+    #[arg(
+        short = 'P',
+        long,
+        global = true,
+        value_parser = PossibleValuesParser::new(PROVIDER_NAMES)
+    )]
+    provider: Option<String>,
+
+    /// Former `-p` short of --provider, still accepted before the subcommand
+    /// for one release; -p is --prompt after `query`
+    #[arg(
+        short = 'p',
+        hide = true,
+        value_parser = PossibleValuesParser::new(PROVIDER_NAMES)
+    )]
+    legacy_provider: Option<String>,
+
+    /// Config profile to apply; defaults to [profiles.default] when defined
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// TOML or JSON file with a [credentials] table; overrides credentials_file in config
+    #[arg(long, global = true, value_name = "PATH")]
+    credentials: Option<PathBuf>,
+
+    /// File of environment variables to load instead of ./.env
+    #[arg(long, global = true, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+
+    /// Append a JSON line with each provider request and response to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// When to color output: auto colors terminals unless NO_COLOR is set
+    #[arg(long, global = true, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    ListModels,
-    Query {
-        /// Input type: prompt to use; if empty, using a generic prompt using git diff --cached
+    /// Manage the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// List the models offered by the provider
+    ListModels {
+        /// Only list models whose ID or name contains this text, ignoring case
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Sort models by ID instead of the provider's order
+        #[arg(long)]
+        sort: bool,
+
+        /// Output style: none or json
+        #[arg(short, long, default_value = "none")]
+        output: String,
+
+        /// Ask the provider again instead of using the cached list
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// List the prompts defined in config
+    ListPrompts {
+        /// Output style: none or json
+        #[arg(short, long, default_value = "none")]
+        output: String,
+    },
+    /// List the inputs defined in config
+    ListInputs {
+        /// Output style: none or json
+        #[arg(short, long, default_value = "none")]
+        output: String,
+    },
+    /// Ask a one-off question without input templating
+    Ask(AskArgs),
+    /// Send the same question to several providers at once
+    Compare {
+        /// Question sent to every provider, as-is
+        question: String,
+
+        /// Providers to compare, comma-separated
+        #[arg(
+            short,
+            long,
+            value_delimiter = ',',
+            default_value = "anthropic,openai,mistral",
+            value_parser = PossibleValuesParser::new(PROVIDER_NAMES)
+        )]
+        providers: Vec<String>,
+    },
+    /// Send each prompt of a file and write the replies as JSON lines
+    Batch {
+        /// One prompt per line, or JSONL objects with a prompt and optional model for .jsonl files
+        #[arg(short, long, value_name = "PATH")]
+        file: PathBuf,
+
+        /// Model for prompts that do not name one; defaults to the provider's model
+        #[arg(short, long)]
+        model: Option<String>,
+
+        /// Write the results to this file instead of stdout
+        #[arg(long, value_name = "PATH")]
+        output_file: Option<PathBuf>,
+
+        /// Overwrite the output file if it already exists
+        #[arg(long, requires = "output_file")]
+        force: bool,
+    },
+    /// Run a pipeline from config, each step's reply becoming the next step's input
+    Pipeline {
+        /// Name of a [pipeline] entry in config
+        name: String,
+
+        /// Input for the first step: a name from config, `file:` patterns or a `dir:` spec
         #[arg(short, long, default_value = "git-diff-cached")]
         input: String,
 
-        /// Model to use, if empty, using default model for the provider
+        /// Model for steps that do not name one; defaults to the provider's model
         #[arg(short, long)]
         model: Option<String>,
 
-        /// Prompt to use; if empty, using a generic prompt
-        #[arg(short, long, default_value = "commit-message")]
-        prompt: Option<String>,
+        /// Also print the reply of every intermediate step
+        #[arg(long)]
+        show_steps: bool,
+    },
+    /// Summarize a file or directory with the summarize prompt
+    Summarize {
+        /// File, or directory walked recursively, to summarize
+        path: PathBuf,
 
-        /// Output style
-        #[arg(short, long, default_value = "none")]
-        output: String,
+        /// Model to use; if empty, using default model for the provider
+        #[arg(short, long)]
+        model: Option<String>,
 
-        /// Enable streaming output
-        #[arg(short, long, default_value_t = false)]
-        stream: bool,
+        /// Output style: none, json, wrapped, wrapped-preserve or markdown (md);
+        /// defaults to default_output in config, then none
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Show the config file, provider, model and credentials aika would use, without querying
+    #[command(alias = "whoami")]
+    Status,
+    /// Prompt for a provider's API key, check it and save it to the credentials
+    Login {
+        /// Provider the key is for
+        #[arg(value_name = "PROVIDER", value_parser = PossibleValuesParser::new(PROVIDER_NAMES))]
+        name: String,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
     },
+    /// Print the names defined in config, one per line, for shell completion
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        kind: CompletionKind,
+    },
+    Query(Box<QueryArgs>),
     Repl {
         /// Model to use in REPL; if empty, using default model for the provider
         #[arg(short, long)]
@@ -71,114 +233,1758 @@ enum Commands {
     },
 }
 
-const DEFAULT_PROMPT: &str = "commit-message";
+#[derive(Args)]
+struct AskArgs {
+    /// Question sent as the prompt, as-is; content piped on stdin is appended
+    /// below it in a fenced block
+    question: Option<String>,
+
+    /// Model to use; if empty, using default model for the provider
+    #[arg(short, long)]
+    model: Option<String>,
+
+    /// Output style: none, json, wrapped, wrapped-preserve or markdown (md);
+    /// defaults to default_output in config, then none
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Enable streaming output
+    #[arg(short, long, default_value_t = false)]
+    stream: bool,
+}
+
+/// Config-defined names offered by `aika __complete`
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum CompletionKind {
+    /// Names accepted by `--prompt`
+    Prompts,
+    /// Names accepted by `--input`
+    Inputs,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Write a commented config template to the config path
+    Init {
+        /// Overwrite an existing config file
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Load and validate the config file without running a query
+    Check,
+}
+
+#[derive(Parser)]
+struct QueryArgs {
+    /// Input type: prompt to use; if empty, using a generic prompt using git diff --cached
+    #[arg(short, long, default_value = "git-diff-cached")]
+    input: String,
+
+    /// Model to use, if empty, using default model for the provider
+    #[arg(short, long)]
+    model: Option<String>,
+
+    /// Prompt to use: a name from config, or `inline:<template>`; if empty, using a generic prompt
+    #[arg(short, long, default_value = "commit-message")]
+    prompt: Option<String>,
+
+    /// Read the prompt template from this file instead, filled in like a --prompt template
+    #[arg(long, value_name = "PATH", conflicts_with = "prompt")]
+    prompt_file: Option<PathBuf>,
+
+    /// Output style: none, json, wrapped, wrapped-preserve or markdown (md);
+    /// defaults to default_output in config, then none
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Wrap width for wrapped output; defaults to the terminal width
+    #[arg(long)]
+    width: Option<usize>,
+
+    /// Also write the response to this file, creating parent directories
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+
+    /// Write the prompt and reply as a Markdown transcript to this file
+    #[arg(long, value_name = "PATH")]
+    transcript: Option<PathBuf>,
+
+    /// Send the conversation in this JSONL file before the prompt; a missing file is an empty conversation
+    #[arg(long, value_name = "PATH")]
+    context_file: Option<PathBuf>,
+
+    /// Append the prompt and reply to this JSONL file, to send with --context-file next time
+    #[arg(long, value_name = "PATH")]
+    append_file: Option<PathBuf>,
+
+    /// Overwrite the output file if it already exists
+    #[arg(long, requires = "output_file")]
+    force: bool,
+
+    /// Enable streaming output
+    #[arg(short, long, default_value_t = false)]
+    stream: bool,
+
+    /// Buffer the streamed reply and print it with highlighted code blocks
+    #[arg(long, requires = "stream")]
+    stream_render: bool,
+
+    /// Print the streamed reply as NDJSON delta events followed by a done event
+    #[arg(long, requires = "stream")]
+    json_stream: bool,
+
+    /// System prompt: name of a [system] entry in config, or literal text
+    #[arg(long, conflicts_with = "system_file")]
+    system: Option<String>,
+
+    /// Read the system prompt from a file
+    #[arg(long)]
+    system_file: Option<PathBuf>,
+
+    /// Include files excluded by .gitignore/.ignore and hidden files in directory input
+    #[arg(long, default_value_t = false)]
+    no_ignore: bool,
+
+    /// Longest input to send, in characters; defaults to max_input_chars in config
+    #[arg(long, value_name = "N")]
+    max_input_chars: Option<usize>,
+
+    /// What to do with input over --max-input-chars (default: error)
+    #[arg(long, value_name = "STRATEGY")]
+    truncate: Option<TruncateStrategy>,
+
+    /// Sampling temperature; overrides the provider config
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    /// Reasoning effort for OpenAI o-series models
+    #[arg(long, value_name = "EFFORT")]
+    reasoning: Option<ReasoningEffort>,
+
+    /// Sampling seed for more reproducible replies (OpenAI and Mistral)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Stop the reply when the model generates this sequence; repeatable
+    #[arg(long = "stop", value_name = "SEQ")]
+    stop: Vec<String>,
+
+    /// JSON object of extra request fields, such as '{"top_p": 0.9}'; fills in
+    /// fields not already set and overrides the provider's extra table
+    #[arg(long, value_name = "JSON", value_parser = parse_extra_json)]
+    extra_json: Option<serde_json::Map<String, serde_json::Value>>,
+
+    /// Maximum number of tokens to generate; overrides the provider config
+    #[arg(long)]
+    max_tokens: Option<u32>,
+
+    /// Number of retries on rate limits and server errors; overrides the provider config
+    #[arg(long)]
+    max_retries: Option<u32>,
+
+    /// Request timeout in seconds (default: 120); overrides the provider config
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// Proxy URL; takes precedence over the provider config and HTTP(S)_PROXY
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Template variable for the prompt, as key=value; may be repeated
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
+
+    /// Fail on prompt placeholders without a value instead of keeping them
+    #[arg(long, default_value_t = false)]
+    strict_vars: bool,
+
+    /// Send the prompt even when the input is empty or whitespace
+    #[arg(long, default_value_t = false)]
+    allow_empty: bool,
+
+    /// Print latency, time to first token and tokens per second to stderr
+    #[arg(long, conflicts_with = "json_stream")]
+    stats: bool,
+
+    /// Print the model's thinking, dimmed, before the answer
+    #[arg(long, conflicts_with = "stream")]
+    show_thinking: bool,
+
+    /// JSON file of tools the model may call; requested calls are printed as JSON
+    #[arg(long, value_name = "PATH", conflicts_with = "stream")]
+    tools: Option<PathBuf>,
+
+    /// Ask the model for a JSON object and fail if the reply is not valid JSON
+    #[arg(long)]
+    json_mode: bool,
+
+    /// With --json-mode, only warn when the reply is not valid JSON
+    #[arg(long, requires = "json_mode")]
+    json_mode_lenient: bool,
+
+    /// Attach an image (PNG, JPEG, GIF or WebP) for vision models; repeatable
+    #[arg(long = "image", value_name = "PATH")]
+    images: Vec<PathBuf>,
+
+    /// Directory to run input commands in and resolve files against
+    #[arg(long, visible_alias = "repo", value_name = "PATH")]
+    cwd: Option<PathBuf>,
+
+    /// Providers to try in order when the main one fails with a credential,
+    /// rate limit, network or server error; overrides `fallback` in config
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "PROVIDER,...",
+        value_parser = PossibleValuesParser::new(PROVIDER_NAMES)
+    )]
+    fallback: Option<Vec<String>>,
+
+    /// Estimate prompt size and cost without calling the provider
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+/// Provider named by the prompt the command uses, for when `--provider` is not given
+fn prompt_provider<'a>(command: Option<&Commands>, config: &'a Config) -> Option<&'a str> {
+    let prompt = match command {
+        Some(Commands::Query(args)) if args.prompt_file.is_some() => None,
+        Some(Commands::Query(args)) => selected_prompt(config, args.prompt.as_deref()),
+        Some(Commands::Summarize { .. }) => selected_prompt(config, Some("summarize")),
+        None => selected_prompt(config, None),
+        _ => None,
+    };
+    prompt.and_then(|prompt| prompt.provider.as_deref())
+}
+
+/// Format a response for the given `--output` style
+///
+/// The `json` style reports the model the provider says answered, falling
+/// back to the requested `model`, along with the reply's ID, usage and
+/// finish reason.
+fn format_response(
+    style: &str,
+    model: &str,
+    response: &QueryResponse,
+    color: bool,
+    width: usize,
+) -> String {
+    let text = response.text.as_str();
+    match style {
+        "json" => serde_json::json!({
+            "model": response.model.as_deref().unwrap_or(model),
+            "response": text,
+            "id": response.id,
+            "usage": response.usage,
+            "finish_reason": response.finish_reason,
+        })
+        .to_string(),
+        "wrapped" => wrap_text(text, width, &WrapOptions::default()),
+        "wrapped-preserve" => wrap_text_preserve_breaks(text, width),
+        "markdown" | "md" => render_markdown(text, color),
+        _ => text.to_string(),
+    }
+}
+
+const PREVIEW_WIDTH: usize = 60;
+
+/// Names of config entries with a one-line preview of their value, sorted by name
+fn config_entries<'a>(entries: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, String)> {
+    let mut entries: Vec<(String, String)> = entries
+        .map(|(name, value)| {
+            let first_line = value.trim().lines().next().unwrap_or("");
+            let mut preview: String = first_line.chars().take(PREVIEW_WIDTH).collect();
+            if preview.len() < value.trim().len() {
+                preview.push_str("...");
+            }
+            (name.to_string(), preview)
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// Format config entries as `name - preview` lines, or a JSON array with `--output json`
+fn format_entries(entries: &[(String, String)], style: &str) -> String {
+    match style {
+        "json" => serde_json::Value::Array(
+            entries
+                .iter()
+                .map(|(name, preview)| serde_json::json!({"name": name, "preview": preview}))
+                .collect(),
+        )
+        .to_string(),
+        _ => entries
+            .iter()
+            .map(|(name, preview)| format!("  {} - {}", name, preview))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Build the prompt of `aika ask` from the question and the content piped on
+/// stdin, if any.
+///
+/// With both, the question comes first, followed by the piped content in a
+/// fenced block. Either one alone is sent as-is.
+fn ask_prompt(question: Option<&str>, piped: Option<&mut dyn Read>) -> anyhow::Result<String> {
+    let mut content = String::new();
+    if let Some(reader) = piped {
+        reader
+            .read_to_string(&mut content)
+            .context("Failed to read stdin")?;
+    }
+    let content = content.trim_end();
+
+    match (question, content.is_empty()) {
+        (Some(question), true) => Ok(question.to_string()),
+        (Some(question), false) => Ok(format!("{}\n\n```\n{}\n```", question, content)),
+        (None, false) => Ok(content.to_string()),
+        (None, true) => {
+            let mut command = Cli::command();
+            command.build();
+            let usage = command
+                .find_subcommand_mut("ask")
+                .map(|ask| ask.render_usage().to_string())
+                .unwrap_or_default();
+            Err(AikaError::EmptyInput(format!(
+                "No question given and nothing piped on stdin\n\n{}",
+                usage
+            ))
+            .into())
+        }
+    }
+}
+
+/// Write a response to the output file, formatted without terminal styling
+fn write_response(
+    file: &mut OutputFile,
+    style: &str,
+    model: &str,
+    response: &QueryResponse,
+    width: usize,
+) -> anyhow::Result<()> {
+    writeln!(
+        file,
+        "{}",
+        format_response(style, model, response, false, width)
+    )
+    .context("Failed to write output file")
+}
+
+/// Whether output style `style` saves the reply text unchanged, so a streamed
+/// reply can be written to the file as it arrives
+fn saved_verbatim(style: &str) -> bool {
+    !matches!(
+        style,
+        "json" | "wrapped" | "wrapped-preserve" | "markdown" | "md"
+    )
+}
+
+/// Print a streamed chunk and save it to `file`, keeping the first write error
+/// since the stream callback cannot fail
+fn save_chunk(chunk: &str, file: Option<&mut OutputFile>, error: &mut Option<std::io::Error>) {
+    print_chunk(chunk);
+    if let Some(file) = file
+        && error.is_none()
+        && let Err(e) = file.write_all(chunk.as_bytes())
+    {
+        *error = Some(e);
+    }
+}
+
+/// Complete the output file: end the chunks saved while streaming, or write
+/// the whole response
+fn finish_output(
+    file: &mut OutputFile,
+    saved_chunks: bool,
+    style: &str,
+    model: &str,
+    response: &QueryResponse,
+    width: usize,
+) -> anyhow::Result<()> {
+    if saved_chunks {
+        writeln!(file)
+            .and_then(|()| file.flush())
+            .context("Failed to write output file")
+    } else {
+        write_response(file, style, model, response, width)
+    }
+}
+
+/// Ask for the API key of provider `name`, check it against the provider and
+/// store it in the credentials file or config
+fn login(config: &Config, cli: &Cli, name: &str) -> anyhow::Result<()> {
+    let api_key = rpassword::prompt_password(format!("{} API key: ", name))
+        .context("Failed to read the API key")?;
+    let api_key = api_key.trim().to_string();
+    if api_key.is_empty() {
+        return Err(AikaError::EmptyInput("No API key entered".to_string()).into());
+    }
+
+    let provider = create_provider_with_key(name, config, api_key.clone())?;
+    verify_api_key(provider.as_ref())?;
+
+    let config_path = config_path(cli.config.as_deref().unwrap_or(""))?;
+    let path = credentials_target(config, &config_path, cli.credentials.as_deref());
+    // A new config file needs the required tables as well as the key
+    if path == config_path && !path.exists() {
+        write_config_template(&path, false)?;
+    }
+    save_credential(&path, name, &api_key)?;
+    println!("Saved the {} API key to {}", name, path.display());
+
+    if let Some(env_var) = credential_env_var(name)
+        && std::env::var(env_var).is_ok()
+    {
+        eprintln!(
+            "Note: {} is set and takes precedence over the saved key",
+            env_var
+        );
+    }
+    Ok(())
+}
+
+/// Check that `provider` accepts its API key by listing its models
+fn verify_api_key(provider: &dyn Provider) -> anyhow::Result<()> {
+    match provider.list_models() {
+        Ok(_) => Ok(()),
+        Err(e) if matches!(e.downcast_ref::<AikaError>(), Some(AikaError::Auth(_))) => {
+            Err(AikaError::Auth(format!(
+                "The {} API key is invalid; it was not saved",
+                provider.name()
+            ))
+            .into())
+        }
+        Err(e) => Err(e.context("Failed to verify the API key")),
+    }
+}
+
+fn run_config_command(command: &ConfigCommands, cli: &Cli) -> anyhow::Result<()> {
+    let config_file = cli.config.as_deref().unwrap_or("");
+    let path = config_path(config_file)?;
+
+    match command {
+        ConfigCommands::Init { force } => {
+            write_config_template(&path, *force)?;
+            println!("Wrote config file to {}", path.display());
+        }
+        ConfigCommands::Check => {
+            let config = load_config(
+                config_file,
+                cli.profile.as_deref(),
+                cli.credentials.as_deref(),
+            )?;
+            println!("Config file {} is valid", path.display());
+
+            let selected = resolve_provider(cli.provider.as_deref(), &config);
+            let mut providers: Vec<&str> = config.providers.keys().map(|k| k.as_str()).collect();
+            if !providers.contains(&selected) {
+                providers.push(selected);
+            }
+            providers.sort();
+
+            for provider in providers {
+                let Some(env_var) = credential_env_var(provider) else {
+                    eprintln!("Warning: unknown provider '{}' in config", provider);
+                    continue;
+                };
+
+                if std::env::var(env_var).is_err() && config.credential(provider).is_none() {
+                    eprintln!(
+                        "Warning: no credentials for provider '{}' (set {} or add it to [credentials])",
+                        provider, env_var
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the API key for `provider` comes from, never the key itself
+fn credential_source(config: &Config, provider: &str) -> String {
+    match credential_env_var(provider) {
+        Some(env_var) if std::env::var(env_var).is_ok() => format!("set ({})", env_var),
+        _ if config.credential(provider).is_some() => "set (config)".to_string(),
+        Some(env_var) => format!("missing (set {} or add it to [credentials])", env_var),
+        None => "missing".to_string(),
+    }
+}
+
+/// Settings `aika status` reports: the config file, the selected provider
+/// with its model, and every provider's credentials, base URL and timeout.
+///
+/// Only the config and the environment are read; no provider is queried.
+fn format_status(config: &Config, config_path: &Path, selected: &str) -> String {
+    let provider_config = |name: &str| config.providers.get(name);
+    let model = |name: &str| {
+        let configured = provider_config(name).and_then(|provider| match name {
+            OpenAIProvider::AZURE_PROVIDER_NAME => provider.deployment.clone(),
+            _ => provider.model.clone(),
+        });
+        let default = match name {
+            ClaudeProvider::PROVIDER_NAME => Some(claude::DEFAULT_MODEL),
+            OpenAIProvider::PROVIDER_NAME => Some(openai::DEFAULT_MODEL),
+            MistralProvider::PROVIDER_NAME => Some(mistral::DEFAULT_MODEL),
+            _ => None,
+        };
+        configured.or(default.map(str::to_string)).map_or_else(
+            || "(not set)".to_string(),
+            |model| config.resolve_model(name, &model),
+        )
+    };
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let mut status = String::new();
+    if config_path.exists() {
+        status.push_str(&format!("Config file: {}\n", config_path.display()));
+    } else {
+        status.push_str(&format!(
+            "Config file: {} (not found, using defaults)\n",
+            config_path.display()
+        ));
+    }
+    status.push_str(&format!("Provider: {}\n", selected));
+    status.push_str(&format!("Model: {}\n", model(selected)));
+
+    for name in PROVIDER_NAMES {
+        let base_url = provider_config(name)
+            .and_then(|provider| match name {
+                OpenAIProvider::AZURE_PROVIDER_NAME => provider.endpoint.clone(),
+                _ => provider.base_url.clone(),
+            })
+            .or(match name {
+                ClaudeProvider::PROVIDER_NAME => Some(claude::DEFAULT_BASE_URL.to_string()),
+                OpenAIProvider::PROVIDER_NAME => Some(openai::DEFAULT_BASE_URL.to_string()),
+                MistralProvider::PROVIDER_NAME => Some(mistral::DEFAULT_BASE_URL.to_string()),
+                _ => None,
+            })
+            .unwrap_or_else(|| "(not set)".to_string());
+        let timeout = provider_config(name)
+            .and_then(|provider| provider.timeout_secs)
+            .unwrap_or(DEFAULT_TIMEOUT.as_secs());
+
+        let marker = if name == selected { " (selected)" } else { "" };
+        status.push_str(&format!("\n{}{}\n", name, marker));
+        status.push_str(&format!(
+            "  Credentials: {}\n",
+            credential_source(config, name)
+        ));
+        status.push_str(&format!("  Model: {}\n", model(name)));
+        status.push_str(&format!("  Base URL: {}\n", base_url));
+        status.push_str(&format!("  Timeout: {}s\n", timeout));
+    }
+    status
+}
+
+fn main() {
+    if let Err(e) = run() {
+        report(&e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// Write the completion script for `shell` to `out`
+fn print_completions(shell: clap_complete::Shell, out: &mut dyn Write) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+}
 
-    let config = match load_config(cli.config.as_deref().unwrap_or("")) {
-        Ok(config) => config,
-        Err(e) => {
-            eprintln!("Warning: Failed to load config file: {}", e);
-            return Err(e);
+/// Sorted names of the prompts or inputs defined in `config`
+fn completion_names(config: &Config, kind: CompletionKind) -> Vec<&str> {
+    let mut names: Vec<&str> = match kind {
+        CompletionKind::Prompts => config.prompts.keys().map(String::as_str).collect(),
+        CompletionKind::Inputs => config.inputs.keys().map(String::as_str).collect(),
+    };
+    names.sort_unstable();
+    names
+}
+
+/// Stop a streamed reply on Ctrl-C instead of killing the process; a second
+/// Ctrl-C exits right away, for a stream that stalled
+fn install_interrupt_handler() -> anyhow::Result<()> {
+    ctrlc::set_handler(|| {
+        if provider::interrupt_flag().load(std::sync::atomic::Ordering::SeqCst) {
+            std::process::exit(AikaError::Interrupted(String::new()).exit_code());
         }
+        request_interrupt();
+    })?;
+    Ok(())
+}
+
+/// Fail with [`AikaError::Interrupted`] if Ctrl-C stopped the reply
+fn check_interrupted() -> anyhow::Result<()> {
+    if take_interrupt() {
+        return Err(AikaError::Interrupted("Generation interrupted".to_string()).into());
+    }
+    Ok(())
+}
+
+/// Log level selected by the verbosity flags; warnings and errors are shown by default
+fn log_level(quiet: bool, verbose: u8, debug: bool) -> log::LevelFilter {
+    if debug {
+        return log::LevelFilter::Trace;
+    }
+
+    match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Info,
+        (false, 2) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    }
+}
+
+/// Fail unless `response` parses as JSON; `lenient` turns the failure into a warning
+fn check_json_reply(response: &str, lenient: bool) -> anyhow::Result<()> {
+    match serde_json::from_str::<serde_json::Value>(response) {
+        Ok(_) => Ok(()),
+        Err(e) if lenient => {
+            log::warn!("Reply is not valid JSON: {}", e);
+            Ok(())
+        }
+        Err(e) => Err(anyhow::anyhow!(
+            "Reply is not valid JSON ({}); use --json-mode-lenient to accept it",
+            e
+        )),
+    }
+}
+
+/// Error message for an empty `input`, pointing at the git input that may
+/// hold the changes instead
+fn empty_input_message(input: &str) -> String {
+    let hint = match input {
+        "git-diff-cached" => {
+            " (is your staging area empty? --input git-diff sends unstaged changes)"
+        }
+        "git-diff" => " (no unstaged changes; --input git-diff-cached sends staged ones)",
+        "git-diff-all" => " (no changes since the last commit)",
+        _ => "",
     };
+    format!("No input to send{}; use --allow-empty to send anyway", hint)
+}
+
+/// Output style to use: the `--output` flag, then `default_output` from config, then raw text
+fn resolve_output(flag: Option<&str>, config: &Config) -> String {
+    flag.or(config.default_output.as_deref())
+        .unwrap_or("none")
+        .to_string()
+}
 
-    let provider = create_provider(&cli.provider, &config)?;
+fn run() -> anyhow::Result<()> {
+    let mut cli = Cli::parse();
+    set_color_choice(cli.color);
+
+    // RUST_LOG, when set, refines the level chosen on the command line
+    env_logger::Builder::new()
+        .filter_level(log_level(cli.quiet, cli.verbose, cli.debug))
+        .format_timestamp(None)
+        .parse_default_env()
+        .init();
+
+    if let Some(provider) = cli.legacy_provider.take() {
+        log::warn!("-p for --provider is deprecated and will be removed; use -P or --provider");
+        cli.provider = cli.provider.or(Some(provider));
+    }
+
+    // Before anything reads credentials from the environment
+    load_env_file(cli.env_file.as_deref())?;
+    set_log_file(cli.log_file.as_deref());
+
+    if let Some(Commands::Config { command }) = &cli.command {
+        return run_config_command(command, &cli);
+    }
+
+    if let Some(Commands::Completions { shell }) = cli.command {
+        print_completions(shell, &mut std::io::stdout());
+        return Ok(());
+    }
+
+    if let Some(Commands::Complete { kind }) = cli.command {
+        // Completion must not fail noisily, so a broken config offers the defaults
+        let config = load_config(
+            cli.config.as_deref().unwrap_or(""),
+            cli.profile.as_deref(),
+            cli.credentials.as_deref(),
+        )
+        .unwrap_or_else(|e| {
+            log::debug!("Completing from default config: {:#}", e);
+            config::get_default_config()
+        });
+        for name in completion_names(&config, kind) {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    let config_file = cli.config.as_deref().unwrap_or("");
+    // Login creates the config file, so it may not exist yet
+    let config = if matches!(cli.command, Some(Commands::Login { .. }))
+        && !config_path(config_file)?.exists()
+    {
+        config::get_default_config()
+    } else {
+        load_config(
+            config_file,
+            cli.profile.as_deref(),
+            cli.credentials.as_deref(),
+        )?
+    };
 
     match &cli.command {
-        Some(Commands::ListModels) => provider.list_models(),
-        Some(Commands::Repl { model }) => run_repl(provider, model.clone(), cli.debug),
-        Some(Commands::Query {
-            stream: _,
-            model: _,
-            prompt: _,
-            input: _,
-            output: _,
-        })
-        | None => {
-            // Use default values when no command is provided
-            let (stream, model, prompt, input, output) = match &cli.command {
-                Some(Commands::Query {
-                    stream,
-                    model,
-                    prompt,
-                    input,
-                    output,
-                }) => (
-                    *stream,
-                    model.clone(),
-                    prompt.clone(),
-                    input.clone(),
-                    output.clone(),
-                ),
-                None => (
-                    false,                            // default stream
-                    None,                             // default model
-                    Some(DEFAULT_PROMPT.to_string()), // default prompt
-                    "git-diff-cached".to_string(),    // default input
-                    "none".to_string(),               // default output
-                ),
-                _ => unreachable!(),
+        Some(Commands::ListPrompts { output }) => {
+            let entries = config_entries(
+                config
+                    .prompts
+                    .iter()
+                    .map(|(name, prompt)| (name.as_str(), prompt.prompt.as_str())),
+            );
+            println!("{}", format_entries(&entries, output));
+            return Ok(());
+        }
+        Some(Commands::ListInputs { output }) => {
+            let entries = config_entries(
+                config
+                    .inputs
+                    .iter()
+                    .map(|(name, input)| (name.as_str(), input.command.as_str())),
+            );
+            println!("{}", format_entries(&entries, output));
+            return Ok(());
+        }
+        Some(Commands::Login { name }) => {
+            return login(&config, &cli, name);
+        }
+        Some(Commands::Status) => {
+            let path = config_path(config_file)?;
+            let selected = resolve_provider(cli.provider.as_deref(), &config);
+            print!("{}", format_status(&config, &path, selected));
+            return Ok(());
+        }
+        Some(Commands::Compare {
+            question,
+            providers,
+        }) => {
+            let results = compare(&config, providers, question);
+            let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+
+            let sections: Vec<String> = results
+                .into_iter()
+                .map(|(name, result)| match result {
+                    Ok(response) => format!("== {} ==\n{}", name, response.trim_end()),
+                    Err(e) => format!("== {} ==\nError: {:#}", name, e),
+                })
+                .collect();
+            println!("{}", sections.join("\n\n"));
+
+            if failed > 0 && failed == providers.len() {
+                return Err(anyhow::anyhow!("All providers failed"));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let provider_name = resolve_provider(
+        cli.provider
+            .as_deref()
+            .or_else(|| prompt_provider(cli.command.as_ref(), &config)),
+        &config,
+    );
+    let provider = create_provider(provider_name, &config)?;
+
+    match cli.command {
+        Some(Commands::Config { .. })
+        | Some(Commands::Completions { .. })
+        | Some(Commands::Complete { .. })
+        | Some(Commands::ListPrompts { .. })
+        | Some(Commands::ListInputs { .. })
+        | Some(Commands::Login { .. })
+        | Some(Commands::Status)
+        | Some(Commands::Compare { .. }) => {
+            unreachable!("handled before loading the provider")
+        }
+        Some(Commands::ListModels {
+            filter,
+            sort,
+            output,
+            refresh,
+        }) => {
+            let models = cached_models(
+                provider.as_ref(),
+                &cache_dir()?,
+                config.models_cache_ttl(),
+                refresh,
+            )?;
+            let models = select_models(models, filter.as_deref(), sort);
+            println!("{}", format_models(&provider.name(), &models, &output));
+            Ok(())
+        }
+        Some(Commands::Repl { model }) => run_repl(&config, provider, model, cli.quiet),
+        Some(Commands::Summarize {
+            path,
+            model,
+            output,
+        }) => {
+            let spinner = Spinner::start(
+                "Waiting for reply",
+                spinner_enabled(cli.quiet, std::io::stdout().is_terminal()),
+            );
+            let reply = summarize(
+                &config,
+                provider.as_ref(),
+                &path,
+                model,
+                &working_dir(None)?,
+            );
+            spinner.stop();
+            let (model, response) = reply?;
+
+            println!(
+                "{}",
+                format_response(
+                    &resolve_output(output.as_deref(), &config),
+                    &model,
+                    &response,
+                    should_colorize(OutputStream::Stdout),
+                    output_width(None),
+                )
+            );
+            Ok(())
+        }
+        Some(Commands::Pipeline {
+            name,
+            input,
+            model,
+            show_steps,
+        }) => {
+            let result = run_named_pipeline(
+                &config,
+                provider.as_ref(),
+                &name,
+                &input,
+                model,
+                &working_dir(None)?,
+                &mut |index, step, reply| {
+                    if show_steps {
+                        println!("== Step {}: {} ==\n{}\n", index + 1, step.prompt, reply);
+                    }
+                },
+            )?;
+            println!("{}", result);
+            Ok(())
+        }
+        Some(Commands::Batch {
+            file,
+            model,
+            output_file,
+            force,
+        }) => {
+            let items = load_batch(&file)?;
+            let mut out: Box<dyn Write> = match output_file {
+                Some(path) => Box::new(OutputFile::new(&path, force)?),
+                None => Box::new(std::io::stdout()),
             };
 
-            let input = if let Some(input) = input.strip_prefix("file:") {
-                let files = &input
-                    .split(",")
-                    .map(|s| s.to_string())
-                    .collect::<Vec<String>>();
-                get_input(&Input::Files(files.clone()), &PathBuf::from("."), cli.debug)
-                    .context("Failed to get input from files")?
-            } else if let Some(dir) = input.strip_prefix("dir:") {
-                get_input(&Input::Dir(dir.to_string()), &PathBuf::from("."), cli.debug)
-                    .context("Failed to get input from directory")?
+            let name = provider.name();
+            let default_model = model.unwrap_or_else(|| provider.model());
+            let summary = run_batch(
+                provider.as_ref(),
+                &default_model,
+                &items,
+                &|model| config.resolve_model(&name, model),
+                &mut out,
+            )?;
+
+            eprintln!(
+                "Batch finished: {} succeeded, {} failed",
+                summary.succeeded, summary.failed
+            );
+            if summary.failed > 0 && summary.succeeded == 0 {
+                return Err(anyhow::anyhow!("All prompts failed"));
+            }
+            Ok(())
+        }
+        Some(Commands::Ask(args)) => {
+            let mut stdin = std::io::stdin();
+            let piped: Option<&mut dyn Read> = if stdin.is_terminal() {
+                None
             } else {
-                let input = config.inputs.get(&input.clone()).unwrap_or_else(|| {
-                    eprintln!(
-                        "Input '{}' not found in config, using default command.",
-                        &input
-                    );
-                    config.inputs.get("git-diff-cached").unwrap()
-                });
+                Some(&mut stdin)
+            };
+            let prompt = ask_prompt(args.question.as_deref(), piped)?;
+
+            if args.stream {
+                install_interrupt_handler()?;
+            }
+            let mut print = print_chunk;
+            let on_chunk: Option<&mut dyn FnMut(&str)> =
+                if args.stream { Some(&mut print) } else { None };
+            let spinner = Spinner::start(
+                "Waiting for reply",
+                !args.stream && spinner_enabled(cli.quiet, std::io::stdout().is_terminal()),
+            );
+            let response = ask(
+                &config,
+                provider.as_ref(),
+                args.model.as_deref(),
+                &prompt,
+                on_chunk,
+            );
+            spinner.stop();
+            let (model, response) = response?;
+
+            if args.stream {
+                println!();
+                check_interrupted()?;
+            } else {
+                println!(
+                    "{}",
+                    format_response(
+                        &resolve_output(args.output.as_deref(), &config),
+                        &model,
+                        &response,
+                        should_colorize(OutputStream::Stdout),
+                        output_width(None),
+                    )
+                );
+            }
 
-                get_input(&from_config(input), &PathBuf::from("."), cli.debug)
-                    .context("Failed to get input from config")?
+            Ok(())
+        }
+        Some(Commands::Query(_)) | None => {
+            // Use default values when no command is provided
+            let args = match cli.command {
+                Some(Commands::Query(args)) => *args,
+                None => QueryArgs::parse_from(["query"]),
+                _ => unreachable!(),
             };
+            let QueryArgs {
+                input,
+                model,
+                prompt,
+                prompt_file,
+                output,
+                stream,
+                stream_render,
+                json_stream,
+                width,
+                output_file,
+                transcript,
+                context_file,
+                append_file,
+                force,
+                system,
+                system_file,
+                no_ignore,
+                max_input_chars,
+                truncate,
+                temperature,
+                reasoning,
+                seed,
+                stop,
+                json_mode,
+                json_mode_lenient,
+                extra_json,
+                max_tokens,
+                max_retries,
+                timeout,
+                proxy,
+                vars,
+                strict_vars,
+                allow_empty,
+                stats,
+                show_thinking,
+                tools,
+                images,
+                cwd,
+                fallback,
+                dry_run,
+            } = args;
+
+            let cwd = working_dir(cwd.as_deref())?;
 
-            let prompt = config.prompts.get(&prompt.clone().unwrap_or(DEFAULT_PROMPT.to_string()))
-                .map(|prompt| prompt.prompt.clone())
-                .unwrap_or_else(|| "Generate a concise and descriptive git commit message for the following changes:\n\n```\n{input}\n```".to_string())
-                .replace("{input}", &input);
+            let input_name = input;
+            let input = read_input(&config, &input_name, &cwd, no_ignore)?;
+            let input = limit_input_size(input, max_input_chars, truncate, &config)?;
 
-            let default_provider = Provider {
-                model: provider.model(),
+            let model = resolve_query_model(
+                model,
+                selected_prompt(&config, prompt.as_deref()).filter(|_| prompt_file.is_none()),
+                provider.as_ref(),
+            );
+            let model = config.resolve_model(&provider.name(), &model);
+            let model = model.as_str();
+
+            let template =
+                load_prompt_template(&config, prompt.as_deref(), prompt_file.as_deref())?;
+
+            // The filled-in prompt is never empty, so look at what goes into {input}
+            if !allow_empty && is_empty_input(&input) {
+                return Err(AikaError::EmptyInput(empty_input_message(&input_name)).into());
+            }
+            let prompt =
+                query_prompt(&template, input, model, &provider.name(), vars, strict_vars)?;
+
+            let system = resolve_system_prompt(&config, system.as_deref(), system_file.as_deref())?;
+
+            let tools = tools
+                .map(|path| load_tools(&path))
+                .transpose()?
+                .unwrap_or_default();
+            let images = images
+                .iter()
+                .map(|path| load_image(path))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let context = context_file
+                .map(|path| load_context(&path))
+                .transpose()?
+                .unwrap_or_default();
+            let messages = query_messages(system, context, prompt, images);
+
+            if dry_run {
+                let tokens = estimate_message_tokens(&messages);
+
+                println!("Model: {}", model);
+                println!("Estimated input tokens: {}", tokens);
+                match estimate_input_cost(model, tokens) {
+                    Some(cost) => println!("Estimated input cost: ${:.4}", cost),
+                    None => println!("Estimated input cost: unknown (no pricing for this model)"),
+                }
+                return Ok(());
+            }
+
+            let options = QueryOptions {
+                temperature,
+                max_tokens,
+                max_retries,
+                timeout: timeout.map(Duration::from_secs),
+                proxy,
+                reasoning_effort: reasoning,
+                tools,
+                seed,
+                stop,
+                json_mode,
+                extra: extra_json.unwrap_or_default(),
             };
 
-            let model = model.as_deref().unwrap_or(default_provider.model.as_str());
-
-            let response = provider.query(model, &prompt, stream);
-            if let Ok(response) = response {
-                if !stream {
-                    match output.as_str() {
-                        "json" => {
-                            let json_output = serde_json::json!({
-                                "model": model,
-                                "response": response,
-                            });
-                            println!("{}", json_output);
-                        }
-                        "wrapped" => {
-                            let wrapped_response = wrap_text(&response, 80);
-                            println!("{}", wrapped_response);
-                        }
-                        _ => println!("{}", &response),
+            let output = resolve_output(output.as_deref(), &config);
+            let width = output_width(width);
+            let mut output_file = output_file
+                .map(|path| OutputFile::new(&path, force))
+                .transpose()?;
+
+            // Highlighting needs the whole reply, so a rendered stream is buffered instead
+            let buffered =
+                stream && stream_render && !json_stream && should_colorize(OutputStream::Stdout);
+            // Whether the reply is printed as it arrives
+            let live = (stream && !buffered) || json_stream;
+            // A streamed reply the file holds verbatim is saved as it arrives,
+            // so a stream that fails partway keeps what was received
+            let save_chunks = stream && !buffered && !json_stream && saved_verbatim(&output);
+            let mut save_error = None;
+            if live {
+                install_interrupt_handler()?;
+            }
+            let fallback = fallback.unwrap_or_else(|| config.fallback.clone());
+            let spinner = Spinner::start(
+                "Waiting for reply",
+                !live && spinner_enabled(cli.quiet, std::io::stdout().is_terminal()),
+            );
+            let mut query_stats = QueryStats::default();
+            let reply =
+                query_with_fallback(&config, provider, model, &fallback, |provider, model| {
+                    if json_stream {
+                        query_json_stream(
+                            provider,
+                            model,
+                            &messages,
+                            &options,
+                            show_thinking,
+                            &mut |line| println!("{}", line),
+                        )
+                    } else {
+                        let mut file = output_file.as_mut().filter(|_| save_chunks);
+                        let mut print =
+                            |chunk: &str| save_chunk(chunk, file.as_deref_mut(), &mut save_error);
+                        let on_chunk: Option<&mut dyn FnMut(&str)> = if stream && !buffered {
+                            Some(&mut print)
+                        } else {
+                            None
+                        };
+                        let (reply, timing) =
+                            timed_query(provider, model, &messages, on_chunk, &options);
+                        query_stats = timing;
+                        reply
                     }
+                });
+            spinner.stop();
+            if let Some(e) = save_error {
+                return Err(e).context("Failed to write output file");
+            }
+            let (provider, model, response) = reply.context("Failed to query provider")?;
+            let model = model.as_str();
+            let usage = response.usage;
+
+            if take_interrupt() {
+                // Keep the partial reply: end the streamed line and save what arrived
+                if !json_stream {
+                    println!();
                 }
-            } else {
-                eprintln!("Error querying provider: {}", response.unwrap_err());
+                if let Some(file) = output_file.as_mut() {
+                    finish_output(file, save_chunks, &output, model, &response, width)?;
+                }
+                return Err(AikaError::Interrupted("Generation interrupted".to_string()).into());
+            }
+
+            if let Some(usage) = usage {
+                log::info!(
+                    "Usage: {} input tokens, {} output tokens",
+                    usage.input_tokens,
+                    usage.output_tokens
+                );
+            }
+
+            if stats {
+                eprintln!("{}", query_stats.summary());
+            }
+
+            // Requested tool calls replace the reply, for the caller to execute
+            let tool_calls = provider.last_tool_calls();
+            if !tool_calls.is_empty() {
+                let calls = serde_json::to_string_pretty(&tool_calls)?;
+                // --json-stream already emitted them as tool_call events
+                if !json_stream {
+                    println!("{}", calls);
+                }
+                if let Some(file) = output_file.as_mut() {
+                    writeln!(file, "{}", calls).context("Failed to write output file")?;
+                }
+                return Ok(());
+            }
+
+            if json_mode {
+                check_json_reply(&response.text, json_mode_lenient)?;
+            }
+
+            // JSON output stays a single document, so thinking is only shown with text
+            // styles; --json-stream emitted it as a thinking event
+            if show_thinking
+                && output != "json"
+                && !json_stream
+                && let Some(thinking) = provider.last_thinking()
+            {
+                println!(
+                    "{}\n",
+                    format_thinking(&thinking, should_colorize(OutputStream::Stdout))
+                );
+            }
+
+            if buffered {
+                println!("{}", highlight_code_blocks(&response.text, true));
+            } else if !stream {
+                println!(
+                    "{}",
+                    format_response(
+                        &output,
+                        model,
+                        &response,
+                        should_colorize(OutputStream::Stdout),
+                        width
+                    )
+                );
+            }
+
+            if let Some(file) = output_file.as_mut() {
+                finish_output(file, save_chunks, &output, model, &response, width)?;
+            }
+
+            if let Some(path) = append_file {
+                let prompt = messages.last().map_or("", |(_, content)| content.text());
+                append_context(&path, prompt, &response.text)?;
+            }
+
+            if let Some(path) = transcript {
+                let mut history = messages
+                    .iter()
+                    .map(|(role, content)| (*role, content.text().to_string()))
+                    .collect::<Vec<_>>();
+                history.push((Role::Assistant, response.text));
+                let transcript = render_transcript(&provider.name(), model, &now(), &history);
+                std::fs::write(&path, transcript)
+                    .with_context(|| format!("Failed to write transcript {:?}", path))?;
             }
 
             Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aika_rs::config::test_config_for;
+    use aika_rs::provider::Usage;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_default_provider_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+              default_provider = "mistral"
+
+              [providers]
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+
+        let cli = Cli::parse_from(["aika", "query"]);
+        assert_eq!(
+            resolve_provider(cli.provider.as_deref(), &config),
+            "mistral"
+        );
+
+        let cli = Cli::parse_from(["aika", "query", "--provider", "openai"]);
+        assert_eq!(resolve_provider(cli.provider.as_deref(), &config), "openai");
+
+        // -P is the short form, as -p is taken by query's --prompt
+        let cli = Cli::parse_from(["aika", "query", "-P", "openai", "-p", "commit-message"]);
+        assert_eq!(cli.provider.as_deref(), Some("openai"));
+
+        // The former -p still works before the subcommand
+        let cli = Cli::parse_from(["aika", "-p", "mistral", "query", "-p", "commit-message"]);
+        assert_eq!(cli.legacy_provider.as_deref(), Some("mistral"));
+        assert_eq!(cli.provider, None);
+
+        let defaults = config::get_default_config();
+        assert_eq!(resolve_provider(None, &defaults), "anthropic");
+    }
+
+    #[test]
+    fn test_color_flag_defaults_to_auto() {
+        assert_eq!(Cli::parse_from(["aika"]).color, ColorChoice::Auto);
+        let cli = Cli::parse_from(["aika", "query", "--color", "never"]);
+        assert_eq!(cli.color, ColorChoice::Never);
+        assert!(Cli::try_parse_from(["aika", "--color", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn test_repl_honors_global_flags() {
+        let cli = Cli::parse_from([
+            "aika",
+            "repl",
+            "--provider",
+            "mistral",
+            "--config",
+            "custom.toml",
+        ]);
+
+        assert_eq!(cli.provider.as_deref(), Some("mistral"));
+        assert_eq!(cli.config.as_deref(), Some("custom.toml"));
+        assert!(matches!(cli.command, Some(Commands::Repl { model: None })));
+    }
+
+    #[test]
+    fn test_query_defaults_without_subcommand() {
+        let args = QueryArgs::parse_from(["query"]);
+        assert_eq!(args.input, "git-diff-cached");
+        assert_eq!(args.prompt.as_deref(), Some(aika_rs::DEFAULT_PROMPT));
+        assert_eq!(args.output, None);
+        assert!(!args.stream);
+    }
+
+    #[test]
+    fn test_prompt_provider_applies_without_flag() {
+        let mut config = Config::default();
+        config.prompts.insert(
+            "elsewhere".to_string(),
+            config::Prompt {
+                prompt: "{input}".to_string(),
+                model: None,
+                provider: Some("mistral".to_string()),
+            },
+        );
+
+        let cli = Cli::parse_from(["aika", "query", "--prompt", "elsewhere"]);
+        assert_eq!(
+            prompt_provider(cli.command.as_ref(), &config),
+            Some("mistral")
+        );
+
+        let cli = Cli::parse_from(["aika", "query", "--prompt", "commit-message"]);
+        assert_eq!(prompt_provider(cli.command.as_ref(), &config), None);
+
+        let cli = Cli::parse_from(["aika", "list-models"]);
+        assert_eq!(prompt_provider(cli.command.as_ref(), &config), None);
+    }
+
+    #[test]
+    fn test_status_lists_selected_provider_and_hides_keys() {
+        let config: Config = toml::from_str(
+            r#"
+              [credentials]
+              anthropic_api_key = "sk-status-secret-0123456789"
+
+              [providers.anthropic]
+              model = "fast"
+              base_url = "https://gateway.internal"
+              timeout_secs = 30
+
+              [model_aliases]
+              fast = "claude-haiku-4-5"
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("config.toml");
+
+        let status = format_status(&config, &path, "anthropic");
+
+        assert!(status.contains("(not found, using defaults)"), "{}", status);
+        assert!(status.contains("Provider: anthropic\nModel: claude-haiku-4-5\n"));
+        assert!(status.contains("\nanthropic (selected)\n  Credentials: set ("));
+        assert!(status.contains("  Base URL: https://gateway.internal\n  Timeout: 30s\n"));
+        assert!(status.contains("\nopenai\n"));
+        assert!(status.contains("  Base URL: https://api.mistral.ai/v1\n  Timeout: 120s\n"));
+        assert!(!status.contains("sk-status-secret"));
+    }
+
+    #[test]
+    fn test_prompt_file_conflicts_with_prompt() {
+        let err = Cli::try_parse_from([
+            "aika",
+            "query",
+            "--prompt",
+            "review",
+            "--prompt-file",
+            "prompt.txt",
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+
+        let cli = Cli::try_parse_from(["aika", "query", "--prompt-file", "prompt.txt"]).unwrap();
+        assert_eq!(
+            prompt_provider(cli.command.as_ref(), &Config::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_write_response_plain_and_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let response = QueryResponse {
+            text: "Hello, world!".to_string(),
+            usage: Some(Usage {
+                input_tokens: 12,
+                output_tokens: 3,
+            }),
+            ..Default::default()
+        };
+
+        let plain = dir.path().join("out/plain.txt");
+        write_response(
+            &mut OutputFile::new(&plain, false).unwrap(),
+            "none",
+            "test-model",
+            &response,
+            80,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&plain).unwrap(), "Hello, world!\n");
+
+        let json = dir.path().join("out/reply.json");
+        write_response(
+            &mut OutputFile::new(&json, false).unwrap(),
+            "json",
+            "test-model",
+            &response,
+            80,
+        )
+        .unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(written["model"], "test-model");
+        assert_eq!(written["response"], "Hello, world!");
+        assert_eq!(written["usage"]["input_tokens"], 12);
+        assert!(written["id"].is_null());
+    }
+
+    #[test]
+    fn test_failed_stream_keeps_partial_reply_in_output_file() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Partial\"}}\n\n\
+                 event: error\n\
+                 data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+            )
+            .create();
+
+        let config = test_config_for(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reply.txt");
+        std::fs::write(&path, "Old reply").unwrap();
+
+        // Nothing is written until the reply starts, so the old contents survive a refused file
+        assert!(OutputFile::new(&path, false).is_err());
+        let mut file = OutputFile::new(&path, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Old reply");
+
+        let mut error = None;
+        let result = provider.query_with_history(
+            "test-model",
+            &[(Role::User, "Hi".to_string())],
+            Some(&mut |chunk| save_chunk(chunk, Some(&mut file), &mut error)),
+            &QueryOptions {
+                max_retries: Some(0),
+                ..Default::default()
+            },
+        );
+
+        mock.assert();
+        assert!(result.is_err());
+        assert!(error.is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Partial");
+    }
+
+    #[test]
+    fn test_json_output_reports_response_metadata() {
+        let response = QueryResponse {
+            text: "Hi".to_string(),
+            model: Some("gpt-5-2025-08-07".to_string()),
+            id: Some("chatcmpl-123".to_string()),
+            usage: None,
+            finish_reason: Some("stop".to_string()),
+        };
+
+        let formatted = format_response("json", "gpt-5", &response, false, 80);
+        let json: serde_json::Value = serde_json::from_str(&formatted).unwrap();
+        assert_eq!(json["model"], "gpt-5-2025-08-07");
+        assert_eq!(json["id"], "chatcmpl-123");
+        assert_eq!(json["finish_reason"], "stop");
+        assert_eq!(json["response"], "Hi");
+    }
+
+    #[test]
+    fn test_wrapped_output_uses_width_override() {
+        let response = "abcde fghij klmno pqrstu vwxyz and some more words to wrap";
+        let width = output_width(Some(24));
+        assert_eq!(width, 24);
+
+        let reply = QueryResponse {
+            text: response.to_string(),
+            ..Default::default()
+        };
+        let formatted = format_response("wrapped", "test-model", &reply, false, width);
+        assert_eq!(formatted, wrap_text(response, 24, &WrapOptions::default()));
+        assert!(formatted.lines().all(|line| line.len() <= 24));
+        assert!(formatted.lines().any(|line| line.len() == 24));
+    }
+
+    #[test]
+    fn test_verify_api_key_rejects_invalid_key() {
+        let mut server = mockito::Server::new();
+        let rejected = server
+            .mock("GET", "/v1/models")
+            .match_header("x-api-key", "bad-key")
+            .with_status(401)
+            .with_body(r#"{"error": {"message": "invalid x-api-key"}}"#)
+            .create();
+        let accepted = server
+            .mock("GET", "/v1/models")
+            .match_header("x-api-key", "good-key")
+            .with_status(200)
+            .with_body(r#"{"data": []}"#)
+            .create();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+              [providers.anthropic]
+              base_url = "{}"
+
+              [inputs]
+
+              [prompts]
+            "#,
+            server.url()
+        ))
+        .unwrap();
+
+        let provider = create_provider_with_key("anthropic", &config, "bad-key".into()).unwrap();
+        let err = verify_api_key(provider.as_ref()).unwrap_err();
+        assert_eq!(exit_code(&err), 3);
+        assert_eq!(
+            err.to_string(),
+            "The anthropic API key is invalid; it was not saved"
+        );
+
+        let provider = create_provider_with_key("anthropic", &config, "good-key".into()).unwrap();
+        assert!(verify_api_key(provider.as_ref()).is_ok());
+
+        rejected.assert();
+        accepted.assert();
+    }
+
+    #[test]
+    fn test_empty_input_message_suggests_other_git_input() {
+        assert!(empty_input_message("git-diff-cached").contains("--input git-diff "));
+        assert!(empty_input_message("git-diff").contains("--input git-diff-cached"));
+        assert_eq!(
+            empty_input_message("file:empty.txt"),
+            "No input to send; use --allow-empty to send anyway"
+        );
+    }
+
+    #[test]
+    fn test_default_output_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+              default_output = "wrapped"
+
+              [providers]
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+        let response = "abcde fghij klmno pqrstu vwxyz and some more words to wrap";
+
+        let args = QueryArgs::parse_from(["query"]);
+        let style = resolve_output(args.output.as_deref(), &config);
+        assert_eq!(style, "wrapped");
+        let reply = QueryResponse {
+            text: response.to_string(),
+            ..Default::default()
+        };
+        let formatted = format_response(&style, "test-model", &reply, false, 24);
+        assert_eq!(formatted, wrap_text(response, 24, &WrapOptions::default()));
+
+        let args = QueryArgs::parse_from(["query", "--output", "none"]);
+        assert_eq!(resolve_output(args.output.as_deref(), &config), "none");
+        assert_eq!(resolve_output(None, &config::get_default_config()), "none");
+    }
+
+    #[test]
+    fn test_input_limit_flags_override_config() {
+        let config: Config = toml::from_str(
+            r#"
+              max_input_chars = 4
+              truncate = "head"
+
+              [providers]
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+        let input = "abcdefgh".to_string();
+
+        let args = QueryArgs::parse_from(["query"]);
+        assert_eq!(
+            limit_input_size(input.clone(), args.max_input_chars, args.truncate, &config).unwrap(),
+            "abcd"
+        );
+
+        let args =
+            QueryArgs::parse_from(["query", "--max-input-chars", "6", "--truncate", "error"]);
+        assert!(
+            limit_input_size(input.clone(), args.max_input_chars, args.truncate, &config).is_err()
+        );
+
+        let unlimited = config::get_default_config();
+        assert_eq!(
+            limit_input_size(input.clone(), None, None, &unlimited).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_ask_sends_question_verbatim() {
+        let cli = Cli::parse_from([
+            "aika",
+            "--provider",
+            "openai",
+            "ask",
+            "What does {input} mean?",
+            "--model",
+            "gpt-5",
+            "--output",
+            "json",
+        ]);
+        assert_eq!(cli.provider.as_deref(), Some("openai"));
+        let Some(Commands::Ask(args)) = cli.command else {
+            panic!("expected the ask command");
+        };
+        assert_eq!(args.model.as_deref(), Some("gpt-5"));
+        assert_eq!(args.output.as_deref(), Some("json"));
+
+        // The question is not a template, so {input} is left as typed
+        let prompt = ask_prompt(args.question.as_deref(), None).unwrap();
+        assert_eq!(prompt, "What does {input} mean?");
+    }
+
+    #[test]
+    fn test_ask_prompt_appends_piped_content() {
+        let mut piped = "diff --git a/x b/x\n+fix\n".as_bytes();
+        let prompt = ask_prompt(Some("review this"), Some(&mut piped)).unwrap();
+        assert_eq!(prompt, "review this\n\n```\ndiff --git a/x b/x\n+fix\n```");
+
+        let mut piped = "just the content\n".as_bytes();
+        assert_eq!(
+            ask_prompt(None, Some(&mut piped)).unwrap(),
+            "just the content"
+        );
+
+        let mut empty = "".as_bytes();
+        assert_eq!(ask_prompt(Some("Hi"), Some(&mut empty)).unwrap(), "Hi");
+    }
+
+    #[test]
+    fn test_ask_prompt_requires_question_or_stdin() {
+        let mut empty = "\n".as_bytes();
+        let err = ask_prompt(None, Some(&mut empty)).unwrap_err();
+        assert_eq!(exit_code(&err), 6);
+        assert!(err.to_string().contains("aika ask"), "{}", err);
+
+        assert!(ask_prompt(None, None).is_err());
+    }
+
+    #[test]
+    fn test_list_default_prompts_and_inputs() {
+        let config = config::get_default_config();
+
+        let prompts = config_entries(
+            config
+                .prompts
+                .iter()
+                .map(|(name, prompt)| (name.as_str(), prompt.prompt.as_str())),
+        );
+        assert!(prompts.iter().any(|(name, _)| name == "commit-message"));
+
+        let inputs = config_entries(
+            config
+                .inputs
+                .iter()
+                .map(|(name, input)| (name.as_str(), input.command.as_str())),
+        );
+        let listed = format_entries(&inputs, "none");
+        assert!(listed.contains("  git-diff-cached - git diff --cached"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_entries(&inputs, "json")).unwrap();
+        assert_eq!(json[0]["name"], "git-diff");
+    }
+
+    #[test]
+    fn test_config_entries_truncates_preview() {
+        let long = "x".repeat(100);
+        let entries = config_entries([("b", "second\nline"), ("a", long.as_str())].into_iter());
+
+        assert_eq!(entries[0].0, "a");
+        assert_eq!(entries[0].1, format!("{}...", "x".repeat(PREVIEW_WIDTH)));
+        assert_eq!(entries[1], ("b".to_string(), "second...".to_string()));
+    }
+
+    #[test]
+    fn test_verbosity_flags_map_to_log_level() {
+        let level = |args: &[&str]| {
+            let cli = Cli::parse_from([&["aika"], args].concat());
+            log_level(cli.quiet, cli.verbose, cli.debug)
+        };
+
+        assert_eq!(level(&[]), log::LevelFilter::Warn);
+        assert_eq!(level(&["-q"]), log::LevelFilter::Error);
+        assert_eq!(level(&["-v"]), log::LevelFilter::Info);
+        assert_eq!(level(&["-vv", "list-models"]), log::LevelFilter::Debug);
+        assert_eq!(level(&["list-models", "-vvvv"]), log::LevelFilter::Trace);
+        assert_eq!(level(&["--debug"]), log::LevelFilter::Trace);
+        assert!(Cli::try_parse_from(["aika", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_check_json_reply() {
+        assert!(check_json_reply(r#"{"ok": true}"#, false).is_ok());
+        assert!(check_json_reply("[1, 2]\n", false).is_ok());
+
+        let err = check_json_reply("Sure! {\"ok\": true}", false).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Reply is not valid JSON"),
+            "{}",
+            err
+        );
+        assert!(check_json_reply("Sure! {\"ok\": true}", true).is_ok());
+
+        assert!(Cli::try_parse_from(["aika", "query", "--json-mode-lenient"]).is_err());
+    }
+
+    #[test]
+    fn test_bash_completions_cover_commands_and_providers() {
+        let mut out = Vec::new();
+        print_completions(clap_complete::Shell::Bash, &mut out);
+        let script = String::from_utf8(out).unwrap();
+
+        assert!(script.contains("aika"));
+        assert!(script.contains("list-models"));
+        assert!(script.contains("mistral"));
+    }
+
+    #[test]
+    fn test_completion_names_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+              [providers]
+
+              [inputs.staged]
+              command = "git diff --cached"
+
+              [prompts.review]
+              prompt = "Review {input}"
+
+              [prompts.changelog]
+              prompt = "Summarize {input}"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            completion_names(&config, CompletionKind::Prompts),
+            ["changelog", "review"]
+        );
+        assert_eq!(
+            completion_names(&config, CompletionKind::Inputs),
+            ["staged"]
+        );
+
+        let defaults = config::get_default_config();
+        assert_eq!(
+            completion_names(&defaults, CompletionKind::Prompts),
+            ["commit-message", "summarize"]
+        );
+    }
+}
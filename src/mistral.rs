@@ -1,14 +1,60 @@
-use std::io::{BufRead as _, BufReader, Write};
+use std::cell::{Cell, RefCell};
+use std::ops::ControlFlow;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::{config::Config, provider::Provider as ProviderTrait};
+use crate::{
+    config::Config,
+    error::{AikaError, api_error},
+    http::{
+        DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT, agent_for_options, select_agent, send_with_retry,
+    },
+    provider::{
+        ModelInfo, PromptContent, Provider as ProviderTrait, QueryOptions, QueryResponse, Role,
+        Usage, apply_extra, default_max_tokens, text_messages, warn_truncated,
+    },
+    redact::{redact, register_secret},
+    request_log, sse,
+    tools::{Tool, ToolCall, parse_arguments},
+};
+
+/// Tool definition for chat completions
+fn tool_definition(tool: &Tool) -> serde_json::Value {
+    let mut function = json!({"name": tool.name, "parameters": tool.parameters});
+    if let Some(description) = &tool.description {
+        function["description"] = json!(description);
+    }
+    json!({"type": "function", "function": function})
+}
+
+/// Message content for chat completions: a string, or a text part followed by
+/// `image_url` parts, given as a plain data URI as Mistral expects
+fn message_content(content: &PromptContent) -> serde_json::Value {
+    match content {
+        PromptContent::Text(text) => json!(text),
+        PromptContent::WithImages { text, images } => {
+            let mut parts = vec![json!({"type": "text", "text": text})];
+            parts.extend(
+                images
+                    .iter()
+                    .map(|image| json!({"type": "image_url", "image_url": image.data_uri()})),
+            );
+            json!(parts)
+        }
+    }
+}
 
 pub struct MistralProvider {
     api_key: String,
+    base_url: String,
     model: String,
+    defaults: QueryOptions,
+    agent: ureq::Agent,
+    last_usage: Cell<Option<Usage>>,
+    truncated: Cell<bool>,
+    tool_calls: RefCell<Vec<ToolCall>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,8 +71,24 @@ struct ModelsResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MistralMessage {
-    content: String,
+    /// Null when the model replied with tool calls only
+    #[serde(default)]
+    content: Option<String>,
     role: String,
+    #[serde(default)]
+    tool_calls: Vec<MistralToolCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MistralToolCall {
+    function: MistralFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MistralFunctionCall {
+    name: String,
+    /// Arguments, either JSON-encoded or as an object
+    arguments: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,6 +105,22 @@ struct MistralResponse {
     object: String,
     created: u64,
     model: String,
+    usage: Option<MistralUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MistralUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl From<MistralUsage> for Usage {
+    fn from(usage: MistralUsage) -> Self {
+        Usage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,35 +143,71 @@ struct MistralStreamResponse {
     object: String,
     created: u64,
     model: String,
+    /// Only set on the final chunk, which Mistral always sends
+    #[serde(default)]
+    usage: Option<MistralUsage>,
 }
 
-const DEFAULT_MODEL: &str = "mistral-large-latest";
+pub const DEFAULT_BASE_URL: &str = "https://api.mistral.ai/v1";
+pub const DEFAULT_MODEL: &str = "mistral-large-latest";
+const DEFAULT_TEMPERATURE: f32 = 0.0;
 
 impl MistralProvider {
     pub const PROVIDER_NAME: &str = "mistral";
 
     pub fn new(config: &Config) -> Result<Self> {
-        let api_key: String = std::env::var("MISTRAL_API_KEY")
-            .or_else(|_| {
+        Self::new_with_env(config, |name| std::env::var(name).ok())
+    }
+
+    /// `new` with environment variables looked up through `env`
+    fn new_with_env<F>(config: &Config, env: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let api_key = env("MISTRAL_API_KEY")
+            .or_else(|| {
                 config
                     .credentials
                     .as_ref()
                     .and_then(|creds| creds.mistral_api_key.clone())
-                    .ok_or(std::env::VarError::NotPresent)
             })
-            .map_err(|_| {
-                anyhow::anyhow!(
-                    "MISTRAL_API_KEY environment variable is not set and no API key found in config"
+            .ok_or_else(|| {
+                AikaError::Auth(
+                    "MISTRAL_API_KEY environment variable is not set and no API key found in config".to_string(),
                 )
             })?;
+        Self::with_api_key(config, api_key)
+    }
+
+    /// Build the provider with `api_key` instead of the key from the environment or config
+    pub fn with_api_key(config: &Config, api_key: String) -> Result<Self> {
+        register_secret(&api_key);
 
-        let model = config
-            .providers
-            .get("mistral")
-            .map(|provider| provider.model.clone())
+        let provider_config = config.providers.get(Self::PROVIDER_NAME);
+
+        let model = provider_config
+            .and_then(|provider| provider.model.clone())
             .unwrap_or_else(|| DEFAULT_MODEL.to_string());
 
-        Ok(Self { api_key, model })
+        let base_url = provider_config
+            .and_then(|provider| provider.base_url.clone())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        let defaults = QueryOptions::from_config(provider_config);
+        let agent = agent_for_options(&defaults, &base_url)?;
+
+        Ok(Self {
+            api_key,
+            base_url,
+            model,
+            defaults,
+            agent,
+            last_usage: Cell::new(None),
+            truncated: Cell::new(false),
+            tool_calls: RefCell::new(Vec::new()),
+        })
     }
 }
 
@@ -102,55 +216,169 @@ impl ProviderTrait for MistralProvider {
         self.model.clone()
     }
 
+    fn last_usage(&self) -> Option<Usage> {
+        self.last_usage.get()
+    }
+
+    fn last_truncated(&self) -> bool {
+        self.truncated.get()
+    }
+
+    fn last_tool_calls(&self) -> Vec<ToolCall> {
+        self.tool_calls.borrow().clone()
+    }
+
     fn name(&self) -> String {
         MistralProvider::PROVIDER_NAME.to_string()
     }
 
-    fn list_models(&self) -> Result<()> {
-        let response: ModelsResponse = ureq::get("https://api.mistral.ai/v1/models")
+    fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let url = format!("{}/models", self.base_url);
+        let mut response = self
+            .agent
+            .get(url.as_str())
             .header("Authorization", &format!("Bearer {}", self.api_key))
-            .call()?
-            .body_mut()
-            .read_json()?;
+            .call()
+            .map_err(|e| AikaError::Network(redact(&format!("Mistral request failed: {}", e))))?;
 
-        println!("Available Mistral models:");
-        for model in response.data {
-            println!("  {}", model.id);
+        if response.status() != 200 {
+            let status = response.status();
+            let error_body = response
+                .body_mut()
+                .read_to_string()
+                .unwrap_or_else(|_| "Failed to read error body".to_string());
+
+            return Err(api_error("Mistral", status.as_u16(), &error_body));
         }
 
-        Ok(())
+        let response = response.body_mut().read_json::<ModelsResponse>()?;
+
+        Ok(response
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                id: model.id,
+                display_name: None,
+            })
+            .collect())
+    }
+
+    fn query(
+        &self,
+        model: &str,
+        prompt: &str,
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<String> {
+        self.query_with_history(
+            model,
+            &[(Role::User, prompt.to_string())],
+            on_chunk,
+            &QueryOptions::default(),
+        )
+    }
+
+    fn query_with_history(
+        &self,
+        model: &str,
+        messages: &[(Role, String)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.query_with_content(model, &text_messages(messages), on_chunk, options)
     }
 
-    fn query(&self, model: &str, prompt: &str, streaming: bool) -> Result<String> {
-        let mut result = String::new();
+    fn query_with_content(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.query_full(model, messages, on_chunk, options)
+            .map(|response| response.text)
+    }
 
-        let query = json!({
+    fn query_full(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<QueryResponse> {
+        let streaming = on_chunk.is_some();
+        let options = options.or(&self.defaults);
+        self.last_usage.set(None);
+        self.truncated.set(false);
+        self.tool_calls.borrow_mut().clear();
+        let url = format!("{}/chat/completions", self.base_url);
+        let mut finish_reason = None;
+        let mut id = None;
+        let mut response_model = None;
+
+        let messages = messages
+            .iter()
+            .map(|(role, content)| json!({"role": role.as_str(), "content": message_content(content)}))
+            .collect::<Vec<_>>();
+
+        let mut query = json!({
             "model": model,
-            "temperature": 0.0,
-            "messages": [{
-                "role": "user",
-                "content": prompt
-            }],
-            "max_tokens": 4096,
+            "temperature": options.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+            "messages": messages,
+            "max_tokens": options.max_tokens.unwrap_or_else(|| default_max_tokens(model)),
             "stream": streaming,
         });
 
-        let config: ureq::config::Config = ureq::Agent::config_builder()
-            .http_status_as_error(false)
-            .build();
+        if let Some(seed) = options.seed {
+            query["random_seed"] = json!(seed);
+        }
 
-        let agent: ureq::Agent = config.into();
+        // Mistral documents no limit on the number of stop sequences, so
+        // unlike OpenAI they are sent without a count check
+        if !options.stop.is_empty() {
+            query["stop"] = json!(options.stop);
+        }
 
-        let response = agent
-            .post("https://api.mistral.ai/v1/chat/completions")
-            .header("Authorization", &format!("Bearer {}", self.api_key))
-            .header("content-type", "application/json")
-            .send_json(query);
+        if options.json_mode {
+            query["response_format"] = json!({"type": "json_object"});
+        }
+
+        if !options.tools.is_empty() {
+            query["tools"] = json!(
+                options
+                    .tools
+                    .iter()
+                    .map(tool_definition)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        apply_extra(&mut query, &options.extra);
+
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let agent = select_agent(&self.agent, &self.defaults, &options, &url)?;
+
+        let max_retries = options.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let response = send_with_retry(max_retries, || {
+            agent
+                .post(url.as_str())
+                .header("Authorization", &format!("Bearer {}", self.api_key))
+                .header("content-type", "application/json")
+                .send_json(&query)
+        });
 
         let mut response = match response {
             Ok(resp) => resp,
+            Err(ureq::Error::Timeout(_)) => {
+                return Err(AikaError::Network(format!(
+                    "Mistral request timed out after {:?}",
+                    timeout
+                ))
+                .into());
+            }
             Err(e) => {
-                return Err(anyhow::anyhow!("Mistral request failed: {}", e));
+                return Err(
+                    AikaError::Network(redact(&format!("Mistral request failed: {}", e))).into(),
+                );
             }
         };
 
@@ -160,51 +388,562 @@ impl ProviderTrait for MistralProvider {
                 .body_mut()
                 .read_to_string()
                 .unwrap_or_else(|_| "Failed to read error body".to_string());
+            request_log::record(&self.name(), model, &query, status.as_u16(), &error_body);
 
-            return Err(anyhow::anyhow!(
-                "Mistral API error ({}): {}",
-                status,
-                error_body
-            ));
+            return Err(api_error("Mistral", status.as_u16(), &error_body));
         }
 
-        if !streaming {
-            let response = response.body_mut().read_json::<MistralResponse>()?;
-            if let Some(response) = response.choices.first() {
-                result.push_str(response.message.content.as_str());
-            } else {
-                println!("No response from Mistral.");
-            }
-        } else {
-            let reader = BufReader::new(response.body_mut().with_config().reader());
-
-            for line in reader.lines() {
-                let line = line?;
-                if line.trim().is_empty() {
-                    continue;
+        let name = self.name();
+        let record = |body: &str| request_log::record(&name, model, &query, 200, body);
+        let result = sse::read_reply(&mut response, on_chunk, &record, |part| {
+            let data = match part {
+                sse::Part::Body(body) => {
+                    let response = serde_json::from_str::<MistralResponse>(body)?;
+                    self.last_usage.set(response.usage.map(Usage::from));
+                    id = Some(response.id);
+                    response_model = Some(response.model);
+                    let Some(response) = response.choices.into_iter().next() else {
+                        return Err(anyhow::anyhow!(
+                            "Mistral returned no choices in its reply to model {}",
+                            model
+                        ));
+                    };
+
+                    self.tool_calls.borrow_mut().extend(
+                        response
+                            .message
+                            .tool_calls
+                            .into_iter()
+                            .map(|call| ToolCall {
+                                name: call.function.name,
+                                arguments: match call.function.arguments {
+                                    serde_json::Value::String(arguments) => {
+                                        parse_arguments(&arguments)
+                                    }
+                                    arguments => arguments,
+                                },
+                            }),
+                    );
+                    finish_reason = Some(response.finish_reason);
+                    return Ok(ControlFlow::Continue(
+                        response.message.content.unwrap_or_default(),
+                    ));
                 }
+                sse::Part::Event(event) => event.data.as_str(),
+            };
+
+            // Check for end of stream
+            if data == "[DONE]" {
+                return Ok(ControlFlow::Break(()));
+            }
 
-                // Parse SSE format: "data: {...}"
-                if let Some(data) = line.strip_prefix("data: ") {
-                    // Parse JSON response
-                    match serde_json::from_str::<MistralStreamResponse>(data) {
-                        Ok(stream_event) => {
-                            if let Some(choice) = stream_event.choices.first()
-                                && let Some(content) = &choice.delta.content
-                            {
-                                print!("{}", content);
-                                std::io::stdout().flush().unwrap();
-                            }
+            // Parse JSON response
+            let mut text = String::new();
+            match serde_json::from_str::<MistralStreamResponse>(data) {
+                Ok(stream_event) => {
+                    id.get_or_insert_with(|| stream_event.id.clone());
+                    response_model.get_or_insert_with(|| stream_event.model.clone());
+                    if let Some(choice) = stream_event.choices.first() {
+                        if let Some(content) = &choice.delta.content {
+                            text.push_str(content);
                         }
-                        Err(e) => {
-                            // Log parse errors but continue processing
-                            eprintln!("Failed to parse Mistral streaming response: {}", e);
+                        if choice.finish_reason.is_some() {
+                            finish_reason = choice.finish_reason.clone();
                         }
                     }
+                    if let Some(usage) = stream_event.usage {
+                        self.last_usage.set(Some(usage.into()));
+                    }
+                }
+                Err(e) => {
+                    // Log parse errors but continue processing
+                    log::warn!("Failed to parse Mistral streaming response: {}", e);
                 }
             }
+            Ok(ControlFlow::Continue(text))
+        })?;
+
+        if finish_reason.as_deref() == Some("length") {
+            self.truncated.set(true);
+            warn_truncated("Mistral");
+        }
+
+        Ok(QueryResponse {
+            text: result,
+            model: response_model,
+            id,
+            usage: self.last_usage.get(),
+            finish_reason,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::env_file_vars;
+    use mockito::ServerGuard;
+
+    fn setup_mock_server() -> ServerGuard {
+        mockito::Server::new()
+    }
+
+    /// Body of a chat completion replying with `content`
+    fn completion_body(content: &str) -> String {
+        json!({
+            "id": "cmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "mistral-large-latest",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": {"role": "assistant", "content": content}
+            }]
+        })
+        .to_string()
+    }
+
+    fn test_provider(server: &ServerGuard) -> MistralProvider {
+        MistralProvider {
+            api_key: "test-key".to_string(),
+            base_url: server.url(),
+            model: DEFAULT_MODEL.to_string(),
+            defaults: QueryOptions::default(),
+            agent: agent_for_options(&QueryOptions::default(), "http://localhost").unwrap(),
+            last_usage: Cell::new(None),
+            truncated: Cell::new(false),
+            tool_calls: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn weather_tool() -> Tool {
+        Tool {
+            name: "get_weather".to_string(),
+            description: Some("Current weather for a city".to_string()),
+            parameters: json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        }
+    }
+
+    #[test]
+    fn test_mistral_new_reads_key_from_env_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(".env");
+        std::fs::write(&path, "MISTRAL_API_KEY=from-dotenv\n").unwrap();
+
+        // The variables load_env_file would set, looked up without touching the environment
+        let vars = env_file_vars(&path, |_| false).unwrap();
+        let env = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let config = Config {
+            credentials: Some(crate::config::Credentials {
+                mistral_api_key: Some("from-config".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let provider = MistralProvider::new_with_env(&config, env).unwrap();
+        assert_eq!(provider.api_key, "from-dotenv");
+
+        let provider = MistralProvider::new_with_env(&config, |_| None).unwrap();
+        assert_eq!(provider.api_key, "from-config");
+    }
+
+    #[test]
+    fn test_mistral_parses_tool_calls() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "get_weather",
+                        "description": "Current weather for a city",
+                        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+                    }
+                }]
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "cmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "test-model",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "tool_calls",
+                    "message": {
+                      "role": "assistant",
+                      "content": null,
+                      "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": {"city": "Paris"}}
+                      }]
+                    }
+                  }]
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            tools: vec![weather_tool()],
+            ..Default::default()
+        };
+        let result = provider.query_with_history(
+            DEFAULT_MODEL,
+            &[(Role::User, "Weather in Paris?".to_string())],
+            None,
+            &options,
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "");
+        assert_eq!(
+            provider.last_tool_calls(),
+            [ToolCall {
+                name: "get_weather".to_string(),
+                arguments: json!({"city": "Paris"}),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mistral_stream_falls_back_to_json_body() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(completion_body("Whole reply"))
+            .create();
+
+        let provider = test_provider(&server);
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Whole reply");
+        assert_eq!(chunks, ["Whole reply"]);
+    }
+
+    #[test]
+    fn test_mistral_streaming_accumulates_deltas() {
+        let mut server = setup_mock_server();
+
+        let chunk = |content: &str| {
+            format!(
+                "data: {{\"id\":\"cmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"mistral-large-latest\",\"choices\":[{{\"index\":0,\"finish_reason\":null,\"delta\":{{\"content\":\"{}\"}}}}]}}\n\n",
+                content
+            )
+        };
+        let body = format!(
+            "{}{}data: {{\"id\":\"cmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"mistral-large-latest\",\"choices\":[],\"usage\":{{\"prompt_tokens\":5,\"completion_tokens\":2,\"total_tokens\":7}}}}\n\n",
+            chunk("Hello"),
+            chunk(", world")
+        );
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(body)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hello, world");
+        assert_eq!(chunks, ["Hello", ", world"]);
+        assert_eq!(
+            provider.last_usage(),
+            Some(Usage {
+                input_tokens: 5,
+                output_tokens: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_mistral_sends_system_message() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "messages": [
+                    {"role": "system", "content": "Be terse."},
+                    {"role": "user", "content": "Hi"}
+                ]
+            })))
+            .with_status(200)
+            .with_body(completion_body("Hello."))
+            .create();
+
+        let provider = test_provider(&server);
+
+        let messages = vec![
+            (Role::System, "Be terse.".to_string()),
+            (Role::User, "Hi".to_string()),
+        ];
+        let result =
+            provider.query_with_history(DEFAULT_MODEL, &messages, None, &QueryOptions::default());
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hello.");
+    }
+
+    #[test]
+    fn test_mistral_flags_length_finish_reason_when_streaming() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                "data: {\"id\":\"cmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"mistral-large-latest\",\"choices\":[{\"index\":0,\"finish_reason\":null,\"delta\":{\"content\":\"The answer\"}}]}\n\n\
+                 data: {\"id\":\"cmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"mistral-large-latest\",\"choices\":[{\"index\":0,\"finish_reason\":\"length\",\"delta\":{\"content\":\" is\"}}]}\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}));
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "The answer is");
+        assert!(provider.last_truncated());
+    }
+
+    #[test]
+    fn test_mistral_uses_config_generation_defaults() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "temperature": 0.25,
+                "max_tokens": 4096
+            })))
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .create();
+
+        let mut provider = test_provider(&server);
+        provider.defaults = QueryOptions {
+            temperature: Some(0.25),
+            ..Default::default()
+        };
+
+        let result = provider.query(DEFAULT_MODEL, "Hi", None);
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_mistral_sends_extra_fields() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "top_p": 0.8,
+                "safe_prompt": true
+            })))
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .create();
+
+        let mut provider = test_provider(&server);
+        provider.defaults = QueryOptions {
+            extra: json!({"top_p": 0.8, "safe_prompt": true})
+                .as_object()
+                .unwrap()
+                .clone(),
+            ..Default::default()
+        };
+
+        let result = provider.query(DEFAULT_MODEL, "Hi", None);
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_mistral_sends_seed_and_stop() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "random_seed": 42,
+                "stop": ["END"]
+            })))
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            seed: Some(42),
+            stop: vec!["END".to_string()],
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_mistral_empty_choices_is_an_error() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "cmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "mistral-large-latest",
+                  "choices": []
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "Hi", None);
+
+        mock.assert();
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Mistral returned no choices in its reply to model mistral-large-latest"
+        );
+    }
+
+    #[test]
+    fn test_mistral_parses_usage() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "cmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "mistral-large-latest",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "ok"}
+                  }],
+                  "usage": {"prompt_tokens": 12, "completion_tokens": 34, "total_tokens": 46}
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        provider.query(DEFAULT_MODEL, "Hi", None).unwrap();
+
+        mock.assert();
+        assert_eq!(
+            provider.last_usage(),
+            Some(Usage {
+                input_tokens: 12,
+                output_tokens: 34
+            })
+        );
+    }
+
+    #[test]
+    fn test_mistral_query_full_reports_metadata() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "cmpl-7",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "mistral-large-2411",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "ok"}
+                  }],
+                  "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let response = provider
+            .query_full(
+                DEFAULT_MODEL,
+                &text_messages(&[(Role::User, "Hi".to_string())]),
+                None,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.id.as_deref(), Some("cmpl-7"));
+        assert_eq!(response.model.as_deref(), Some("mistral-large-2411"));
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                input_tokens: 5,
+                output_tokens: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_mistral_reuses_agent_across_queries() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .expect(3)
+            .create();
+
+        let provider = test_provider(&server);
+        for _ in 0..3 {
+            assert_eq!(provider.query(DEFAULT_MODEL, "Hi", None).unwrap(), "ok");
         }
 
-        Ok(result)
+        mock.assert();
     }
 }
@@ -0,0 +1,151 @@
+//! Offline provider for tests, registered as `mock` with the `test-provider` feature.
+
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::provider::{ModelInfo, Provider, QueryOptions, Role};
+
+/// Provider that answers without the network: with a canned reply when one is
+/// set, otherwise by echoing the last user message back.
+pub struct MockProvider {
+    model: String,
+    response: Option<String>,
+}
+
+impl MockProvider {
+    pub const PROVIDER_NAME: &str = "mock";
+    pub const DEFAULT_MODEL: &str = "mock-model";
+
+    /// Echoing mock, with the default model from `[providers.mock]` if set
+    pub fn new(config: &Config) -> Self {
+        let model = config
+            .providers
+            .get(Self::PROVIDER_NAME)
+            .and_then(|provider| provider.model.clone())
+            .unwrap_or_else(|| Self::DEFAULT_MODEL.to_string());
+
+        MockProvider {
+            model,
+            response: None,
+        }
+    }
+
+    /// Mock that answers every query with `response`
+    pub fn with_response(config: &Config, response: &str) -> Self {
+        MockProvider {
+            response: Some(response.to_string()),
+            ..Self::new(config)
+        }
+    }
+
+    fn reply(&self, prompt: &str, on_chunk: Option<&mut dyn FnMut(&str)>) -> String {
+        let reply = self.response.as_deref().unwrap_or(prompt).to_string();
+        if let Some(on_chunk) = on_chunk {
+            on_chunk(&reply);
+        }
+        reply
+    }
+}
+
+impl Provider for MockProvider {
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    fn name(&self) -> String {
+        Self::PROVIDER_NAME.to_string()
+    }
+
+    fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(["mock-model", "mock-model-large"]
+            .into_iter()
+            .map(|id| ModelInfo {
+                id: id.to_string(),
+                display_name: None,
+            })
+            .collect())
+    }
+
+    fn query(
+        &self,
+        _model: &str,
+        prompt: &str,
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<String> {
+        Ok(self.reply(prompt, on_chunk))
+    }
+
+    fn query_with_history(
+        &self,
+        _model: &str,
+        messages: &[(Role, String)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        _options: &QueryOptions,
+    ) -> Result<String> {
+        let prompt = messages
+            .iter()
+            .rev()
+            .find(|(role, _)| *role == Role::User)
+            .map_or("", |(_, content)| content.as_str());
+        Ok(self.reply(prompt, on_chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_default_config;
+    use crate::provider::{create_provider, text_messages};
+
+    #[test]
+    fn test_mock_provider_through_create_provider() {
+        let config = get_default_config();
+        let provider = create_provider("mock", &config).unwrap();
+
+        assert_eq!(provider.name(), "mock");
+        assert_eq!(provider.model(), MockProvider::DEFAULT_MODEL);
+        let models = provider.list_models().unwrap();
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].id, "mock-model");
+
+        let mut streamed = String::new();
+        let reply = provider
+            .query(
+                "mock-model",
+                "Echo me",
+                Some(&mut |chunk| streamed.push_str(chunk)),
+            )
+            .unwrap();
+        assert_eq!(reply, "Echo me");
+        assert_eq!(streamed, "Echo me");
+
+        let reply = provider
+            .query_with_history(
+                "mock-model",
+                &[
+                    (Role::System, "Be brief".to_string()),
+                    (Role::User, "Last question".to_string()),
+                ],
+                None,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(reply, "Last question");
+    }
+
+    #[test]
+    fn test_mock_provider_canned_response() {
+        let config = get_default_config();
+        let provider = MockProvider::with_response(&config, "Canned");
+
+        let response = provider
+            .query_full(
+                "mock-model",
+                &text_messages(&[(Role::User, "Anything".to_string())]),
+                None,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(response.text, "Canned");
+    }
+}
@@ -0,0 +1,152 @@
+//! Local cache of the models each provider offers, used by `list-models`.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::provider::{ModelInfo, Provider};
+
+/// How long a cached models list is used before asking the provider again
+pub const DEFAULT_TTL: Duration = Duration::from_secs(6 * 3600);
+
+/// Contents of a provider's cache file
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedModels {
+    /// Seconds since the epoch when the list was fetched
+    fetched_at: u64,
+    models: Vec<ModelInfo>,
+}
+
+/// Directory holding one `<provider>.json` models file per provider
+pub fn cache_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?
+        .join("aika-rs")
+        .join("models"))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Cached models of `provider_name` in `dir`, if fetched less than `ttl` ago.
+///
+/// A missing or unreadable cache file counts as expired.
+fn read_cache(dir: &Path, provider_name: &str, ttl: Duration) -> Option<Vec<ModelInfo>> {
+    let path = dir.join(format!("{}.json", provider_name));
+    let contents = std::fs::read_to_string(&path).ok()?;
+    let cached: CachedModels = match serde_json::from_str(&contents) {
+        Ok(cached) => cached,
+        Err(e) => {
+            log::debug!("Ignoring models cache {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let age = unix_secs(SystemTime::now()).saturating_sub(cached.fetched_at);
+    (age < ttl.as_secs()).then_some(cached.models)
+}
+
+fn write_cache(dir: &Path, provider_name: &str, models: &[ModelInfo]) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create models cache directory {:?}", dir))?;
+
+    let path = dir.join(format!("{}.json", provider_name));
+    let cached = CachedModels {
+        fetched_at: unix_secs(SystemTime::now()),
+        models: models.to_vec(),
+    };
+    std::fs::write(&path, serde_json::to_string_pretty(&cached)?)
+        .with_context(|| format!("Failed to write models cache {:?}", path))
+}
+
+/// Models offered by `provider`, from the cache in `dir` while it is younger
+/// than `ttl`, otherwise from the provider. `refresh` skips the cache.
+///
+/// A fresh list is written back to the cache; failing to write it is only
+/// logged, since the list itself is still good.
+pub fn cached_models(
+    provider: &dyn Provider,
+    dir: &Path,
+    ttl: Duration,
+    refresh: bool,
+) -> Result<Vec<ModelInfo>> {
+    let name = provider.name();
+    if !refresh && let Some(models) = read_cache(dir, &name, ttl) {
+        log::debug!("Using cached {} models", name);
+        return Ok(models);
+    }
+
+    let models = provider.list_models()?;
+    if let Err(e) = write_cache(dir, &name, &models) {
+        log::warn!("{:#}", e);
+    }
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config_for;
+    use crate::provider::create_provider;
+
+    fn test_provider(server: &mockito::ServerGuard) -> Box<dyn Provider> {
+        let config = test_config_for(&server.url());
+        create_provider("anthropic", &config).unwrap()
+    }
+
+    #[test]
+    fn test_cached_models_skip_network_within_ttl() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/models")
+            .with_status(200)
+            .with_body(r#"{"data": [{"id": "claude-sonnet-4-5", "display_name": "Claude Sonnet 4.5", "type": "model"}]}"#)
+            .expect(1)
+            .create();
+
+        let temp = tempfile::tempdir().unwrap();
+        let provider = test_provider(&server);
+
+        let first = cached_models(provider.as_ref(), temp.path(), DEFAULT_TTL, false).unwrap();
+        let second = cached_models(provider.as_ref(), temp.path(), DEFAULT_TTL, false).unwrap();
+
+        mock.assert();
+        assert_eq!(first, second);
+        assert_eq!(second[0].id, "claude-sonnet-4-5");
+        assert!(temp.path().join("anthropic.json").exists());
+    }
+
+    #[test]
+    fn test_cached_models_refetch_when_expired_or_refreshed() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/v1/models")
+            .with_status(200)
+            .with_body(r#"{"data": [{"id": "claude-sonnet-4-5", "display_name": "Claude Sonnet 4.5", "type": "model"}]}"#)
+            .expect(3)
+            .create();
+
+        let temp = tempfile::tempdir().unwrap();
+        let provider = test_provider(&server);
+
+        cached_models(provider.as_ref(), temp.path(), DEFAULT_TTL, false).unwrap();
+        cached_models(provider.as_ref(), temp.path(), DEFAULT_TTL, true).unwrap();
+        cached_models(provider.as_ref(), temp.path(), Duration::ZERO, false).unwrap();
+
+        mock.assert();
+    }
+
+    #[test]
+    fn test_corrupt_cache_is_ignored() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("anthropic.json"), "not json").unwrap();
+
+        assert_eq!(read_cache(temp.path(), "anthropic", DEFAULT_TTL), None);
+        assert_eq!(read_cache(temp.path(), "openai", DEFAULT_TTL), None);
+    }
+}
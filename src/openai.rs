@@ -1,10 +1,25 @@
-use std::io::{BufRead as _, BufReader};
+use std::cell::{Cell, RefCell};
+use std::ops::ControlFlow;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::{config::Config, provider::Provider as ProviderTrait};
+use crate::{
+    config::Config,
+    error::{AikaError, api_error},
+    http::{
+        DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT, agent_for_options, select_agent, send_with_retry,
+    },
+    provider::{
+        ModelInfo, PromptContent, Provider as ProviderTrait, QueryOptions, QueryResponse, Role,
+        Usage, apply_extra, check_stop_sequences, default_max_tokens, text_messages,
+        warn_truncated,
+    },
+    redact::{redact, register_secret},
+    request_log, sse,
+    tools::{Tool, ToolCall, parse_arguments},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Model {
@@ -21,8 +36,24 @@ struct ModelsResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct OpenAIMessage {
-    content: String,
+    /// Null when the model replied with tool calls only
+    #[serde(default)]
+    content: Option<String>,
     role: String,
+    #[serde(default)]
+    tool_calls: Vec<OpenAIToolCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIToolCall {
+    function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIFunctionCall {
+    name: String,
+    /// JSON-encoded arguments
+    arguments: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,6 +70,22 @@ struct OpenAIResponse {
     object: String,
     created: u64,
     model: String,
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl From<OpenAIUsage> for Usage {
+    fn from(usage: OpenAIUsage) -> Self {
+        Usage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
 }
 
 // Streaming response structures
@@ -62,40 +109,226 @@ struct OpenAIStreamResponse {
     object: String,
     created: u64,
     model: String,
+    /// Only set on the final chunk, sent when `stream_options.include_usage` is on
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+/// Whether `model` is an o-series reasoning model (`o1`, `o3-mini`, `o4-mini`, ...)
+fn is_reasoning_model(model: &str) -> bool {
+    model
+        .strip_prefix('o')
+        .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Tool definition for chat completions
+fn tool_definition(tool: &Tool) -> serde_json::Value {
+    let mut function = json!({"name": tool.name, "parameters": tool.parameters});
+    if let Some(description) = &tool.description {
+        function["description"] = json!(description);
+    }
+    json!({"type": "function", "function": function})
+}
+
+/// Message content for chat completions: a string, or a text part followed by `image_url` parts
+fn message_content(content: &PromptContent) -> serde_json::Value {
+    match content {
+        PromptContent::Text(text) => json!(text),
+        PromptContent::WithImages { text, images } => {
+            let mut parts = vec![json!({"type": "text", "text": text})];
+            parts.extend(
+                images.iter().map(
+                    |image| json!({"type": "image_url", "image_url": {"url": image.data_uri()}}),
+                ),
+            );
+            json!(parts)
+        }
+    }
+}
+
+/// Azure OpenAI deployment queried instead of the OpenAI API
+struct AzureDeployment {
+    deployment: String,
+    api_version: String,
 }
 
 pub struct OpenAIProvider {
     api_key: String,
+    /// API base URL, or the resource endpoint for Azure
+    base_url: String,
     model: String,
+    defaults: QueryOptions,
+    agent: ureq::Agent,
+    azure: Option<AzureDeployment>,
+    /// Whether streams ask for usage with `stream_options`
+    stream_usage: bool,
+    last_usage: Cell<Option<Usage>>,
+    truncated: Cell<bool>,
+    tool_calls: RefCell<Vec<ToolCall>>,
 }
 
-const DEFAULT_MODEL: &str = "gpt-5";
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+pub const DEFAULT_MODEL: &str = "gpt-5";
+/// Chat completions reject more stop sequences than this
+const MAX_STOP_SEQUENCES: usize = 4;
+const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
 
 impl OpenAIProvider {
     pub const PROVIDER_NAME: &str = "openai";
+    pub const AZURE_PROVIDER_NAME: &str = "azure";
 
     pub fn new(config: &Config) -> Result<Self> {
-        let api_key: String = std::env::var("OPENAI_API_KEY")
-            .or_else(|_| {
+        Self::new_with_env(config, |name| std::env::var(name).ok())
+    }
+
+    /// `new` with environment variables looked up through `env`
+    fn new_with_env<F>(config: &Config, env: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let api_key = env("OPENAI_API_KEY")
+            .or_else(|| {
                 config
                     .credentials
                     .as_ref()
                     .and_then(|creds| creds.openai_api_key.clone())
-                    .ok_or(std::env::VarError::NotPresent)
             })
-            .map_err(|_| {
-                anyhow::anyhow!(
+            .ok_or_else(|| {
+                AikaError::Auth(
                     "OPENAI_API_KEY environment variable is not set and no API key found in config"
+                        .to_string(),
                 )
             })?;
+        Self::with_api_key(config, api_key)
+    }
 
-        let model = config
-            .providers
-            .get("openai")
-            .map(|provider| provider.model.clone())
+    /// Build the provider with `api_key` instead of the key from the environment or config
+    pub fn with_api_key(config: &Config, api_key: String) -> Result<Self> {
+        register_secret(&api_key);
+
+        let provider_config = config.providers.get(Self::PROVIDER_NAME);
+
+        let model = provider_config
+            .and_then(|provider| provider.model.clone())
             .unwrap_or_else(|| DEFAULT_MODEL.to_string());
 
-        Ok(Self { api_key, model })
+        let base_url = provider_config
+            .and_then(|provider| provider.base_url.clone())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        Self::build(provider_config, api_key, base_url, model, None)
+    }
+
+    /// Build the provider for the Azure OpenAI deployment set up in `[providers.azure]`
+    pub fn azure(config: &Config) -> Result<Self> {
+        let api_key = std::env::var("AZURE_OPENAI_API_KEY")
+            .ok()
+            .or_else(|| {
+                config
+                    .credential(Self::AZURE_PROVIDER_NAME)
+                    .map(str::to_string)
+            })
+            .ok_or_else(|| {
+                AikaError::Auth(
+                    "AZURE_OPENAI_API_KEY environment variable is not set and no API key found in config"
+                        .to_string(),
+                )
+            })?;
+        Self::azure_with_api_key(config, api_key)
+    }
+
+    /// Like [`OpenAIProvider::azure`], with `api_key` instead of the key from the environment or config
+    pub fn azure_with_api_key(config: &Config, api_key: String) -> Result<Self> {
+        register_secret(&api_key);
+
+        let provider_config = config.providers.get(Self::AZURE_PROVIDER_NAME);
+        let required = |value: Option<&String>, key: &str| {
+            value.cloned().ok_or_else(|| {
+                AikaError::Config(format!("[providers.azure] needs {} to be set", key))
+            })
+        };
+
+        let endpoint = required(
+            provider_config.and_then(|p| p.endpoint.as_ref()),
+            "endpoint",
+        )?
+        .trim_end_matches('/')
+        .to_string();
+        let deployment = required(
+            provider_config.and_then(|p| p.deployment.as_ref()),
+            "deployment",
+        )?;
+        let api_version = provider_config
+            .and_then(|provider| provider.api_version.clone())
+            .unwrap_or_else(|| DEFAULT_AZURE_API_VERSION.to_string());
+
+        // The deployment decides the model; the name is only sent along
+        let model = provider_config
+            .and_then(|provider| provider.model.clone())
+            .unwrap_or_else(|| deployment.clone());
+
+        let azure = AzureDeployment {
+            deployment,
+            api_version,
+        };
+        Self::build(provider_config, api_key, endpoint, model, Some(azure))
+    }
+
+    fn build(
+        provider_config: Option<&crate::config::Provider>,
+        api_key: String,
+        base_url: String,
+        model: String,
+        azure: Option<AzureDeployment>,
+    ) -> Result<Self> {
+        let defaults = QueryOptions::from_config(provider_config);
+        let agent = agent_for_options(&defaults, &base_url)?;
+        let stream_usage = provider_config
+            .and_then(|provider| provider.stream_usage)
+            .unwrap_or(true);
+
+        Ok(Self {
+            api_key,
+            base_url,
+            model,
+            defaults,
+            agent,
+            azure,
+            stream_usage,
+            last_usage: Cell::new(None),
+            truncated: Cell::new(false),
+            tool_calls: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Name used in errors and warnings
+    fn label(&self) -> &'static str {
+        if self.azure.is_some() {
+            "Azure OpenAI"
+        } else {
+            "OpenAI"
+        }
+    }
+
+    fn chat_url(&self) -> String {
+        match &self.azure {
+            Some(azure) => format!(
+                "{}/openai/deployments/{}/chat/completions?api-version={}",
+                self.base_url, azure.deployment, azure.api_version
+            ),
+            None => format!("{}/chat/completions", self.base_url),
+        }
+    }
+
+    /// Add the authentication header: `api-key` for Azure, a bearer token otherwise
+    fn with_auth<B>(&self, request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+        if self.azure.is_some() {
+            request.header("api-key", &self.api_key)
+        } else {
+            request.header("Authorization", &format!("Bearer {}", self.api_key))
+        }
     }
 }
 
@@ -104,56 +337,207 @@ impl ProviderTrait for OpenAIProvider {
         self.model.clone()
     }
 
+    fn last_usage(&self) -> Option<Usage> {
+        self.last_usage.get()
+    }
+
+    fn last_truncated(&self) -> bool {
+        self.truncated.get()
+    }
+
+    fn last_tool_calls(&self) -> Vec<ToolCall> {
+        self.tool_calls.borrow().clone()
+    }
+
     fn name(&self) -> String {
-        OpenAIProvider::PROVIDER_NAME.to_string()
+        if self.azure.is_some() {
+            OpenAIProvider::AZURE_PROVIDER_NAME.to_string()
+        } else {
+            OpenAIProvider::PROVIDER_NAME.to_string()
+        }
     }
 
-    fn list_models(&self) -> Result<()> {
-        let models = ureq::get("https://api.openai.com/v1/models")
+    fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        // Azure queries go to a single deployment, so that is the one model offered
+        if let Some(azure) = &self.azure {
+            return Ok(vec![ModelInfo {
+                id: azure.deployment.clone(),
+                display_name: None,
+            }]);
+        }
+
+        let url = format!("{}/models", self.base_url);
+        let mut response = self
+            .agent
+            .get(url.as_str())
             .header("Authorization", &format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
-            .call()?
-            .body_mut()
-            .read_json::<ModelsResponse>()?;
-
-        println!("Available OpenAI GPT models:");
-        for model in models.data {
-            if model.id.starts_with("gpt-") && !model.id.contains("instruct") {
-                println!("  {}", model.id);
-            }
+            .call()
+            .map_err(|e| AikaError::Network(redact(&format!("OpenAI request failed: {}", e))))?;
+
+        if response.status() != 200 {
+            let status = response.status();
+            let error_body = response
+                .body_mut()
+                .read_to_string()
+                .unwrap_or_else(|_| "Failed to read error body".to_string());
+
+            return Err(api_error("OpenAI", status.as_u16(), &error_body));
         }
 
-        Ok(())
+        let models = response.body_mut().read_json::<ModelsResponse>()?;
+
+        Ok(models
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                id: model.id,
+                display_name: None,
+            })
+            .collect())
+    }
+
+    fn query(
+        &self,
+        model: &str,
+        prompt: &str,
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<String> {
+        self.query_with_history(
+            model,
+            &[(Role::User, prompt.to_string())],
+            on_chunk,
+            &QueryOptions::default(),
+        )
+    }
+
+    fn query_with_history(
+        &self,
+        model: &str,
+        messages: &[(Role, String)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.query_with_content(model, &text_messages(messages), on_chunk, options)
+    }
+
+    fn query_with_content(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        self.query_full(model, messages, on_chunk, options)
+            .map(|response| response.text)
     }
 
-    fn query(&self, model: &str, prompt: &str, streaming: bool) -> Result<String> {
-        let mut result = String::new();
+    fn query_full(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<QueryResponse> {
+        let streaming = on_chunk.is_some();
+        let options = options.or(&self.defaults);
+        self.last_usage.set(None);
+        self.truncated.set(false);
+        self.tool_calls.borrow_mut().clear();
+        let url = self.chat_url();
+        let mut finish_reason = None;
+        let mut id = None;
+        let mut response_model = None;
 
-        let query = json!({
+        let messages = messages
+            .iter()
+            .map(|(role, content)| json!({"role": role.as_str(), "content": message_content(content)}))
+            .collect::<Vec<_>>();
+
+        let mut query = json!({
             "model": model,
-            "messages": [
-                {"role": "user", "content": prompt}
-            ],
-            "max_completion_tokens": 4096,
+            "messages": messages,
+            "max_completion_tokens": options.max_tokens.unwrap_or_else(|| default_max_tokens(model)),
             "stream": streaming,
         });
+        if streaming && self.stream_usage {
+            // Usage is only reported in a stream when asked for, in a last chunk without choices
+            query["stream_options"] = json!({"include_usage": true});
+        }
+
+        if is_reasoning_model(model) {
+            // Reasoning models reject sampling parameters with a 400
+            if options.temperature.is_some() {
+                log::debug!("Not sending temperature to reasoning model {}", model);
+            }
+            if let Some(effort) = options.reasoning_effort {
+                query["reasoning_effort"] = json!(effort.as_str());
+            }
+        } else {
+            // Newer OpenAI models only accept their default temperature, so only send it when asked
+            if let Some(temperature) = options.temperature {
+                query["temperature"] = json!(temperature);
+            }
+            if options.reasoning_effort.is_some() {
+                log::debug!(
+                    "Ignoring reasoning effort for non-reasoning model {}",
+                    model
+                );
+            }
+        }
 
-        let config = ureq::Agent::config_builder()
-            .http_status_as_error(false)
-            .build();
+        if let Some(seed) = options.seed {
+            query["seed"] = json!(seed);
+        }
 
-        let agent: ureq::Agent = config.into();
+        if !options.stop.is_empty() {
+            check_stop_sequences(self.label(), &options.stop, MAX_STOP_SEQUENCES)?;
+            query["stop"] = json!(options.stop);
+        }
 
-        let response = agent
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", &format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .send_json(query);
+        if options.json_mode {
+            query["response_format"] = json!({"type": "json_object"});
+        }
+
+        if !options.tools.is_empty() {
+            query["tools"] = json!(
+                options
+                    .tools
+                    .iter()
+                    .map(tool_definition)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        apply_extra(&mut query, &options.extra);
+
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let agent = select_agent(&self.agent, &self.defaults, &options, &url)?;
+
+        let max_retries = options.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let response = send_with_retry(max_retries, || {
+            self.with_auth(agent.post(url.as_str()))
+                .header("Content-Type", "application/json")
+                .send_json(&query)
+        });
 
         let mut response = match response {
             Ok(resp) => resp,
+            Err(ureq::Error::Timeout(_)) => {
+                return Err(AikaError::Network(format!(
+                    "{} request timed out after {:?}",
+                    self.label(),
+                    timeout
+                ))
+                .into());
+            }
             Err(e) => {
-                return Err(anyhow::anyhow!("OpenAI request failed: {}", e));
+                return Err(AikaError::Network(redact(&format!(
+                    "{} request failed: {}",
+                    self.label(),
+                    e
+                )))
+                .into());
             }
         };
 
@@ -164,56 +548,939 @@ impl ProviderTrait for OpenAIProvider {
                 .body_mut()
                 .read_to_string()
                 .unwrap_or_else(|_| "Failed to read error body".to_string());
+            request_log::record(&self.name(), model, &query, status.as_u16(), &error_body);
 
-            return Err(anyhow::anyhow!(
-                "OpenAI API error ({}): {}",
-                status,
-                error_body
-            ));
+            return Err(api_error(self.label(), status.as_u16(), &error_body));
         }
 
-        if streaming {
-            let reader = BufReader::new(response.body_mut().with_config().reader());
+        let name = self.name();
+        let record = |body: &str| request_log::record(&name, model, &query, 200, body);
+        let result = sse::read_reply(&mut response, on_chunk, &record, |part| {
+            let data = match part {
+                sse::Part::Body(body) => {
+                    let response = serde_json::from_str::<OpenAIResponse>(body)?;
+                    self.last_usage.set(response.usage.map(Usage::from));
+                    id = Some(response.id);
+                    response_model = Some(response.model);
 
-            for line in reader.lines() {
-                let line = line?;
-                if line.trim().is_empty() {
-                    continue;
+                    let mut text = String::new();
+                    for item in response.choices {
+                        if item.message.role == "assistant" {
+                            text.push_str(item.message.content.as_deref().unwrap_or_default());
+                            self.tool_calls.borrow_mut().extend(
+                                item.message.tool_calls.into_iter().map(|call| ToolCall {
+                                    name: call.function.name,
+                                    arguments: parse_arguments(&call.function.arguments),
+                                }),
+                            );
+                            finish_reason = Some(item.finish_reason);
+                        }
+                    }
+                    return Ok(ControlFlow::Continue(text));
                 }
+                sse::Part::Event(event) => event.data.as_str(),
+            };
 
-                // Parse SSE format: "data: {...}"
-                if let Some(data) = line.strip_prefix("data: ") {
-                    // Check for end of stream
-                    if data == "[DONE]" {
-                        break;
-                    }
+            // Check for end of stream
+            if data == "[DONE]" {
+                return Ok(ControlFlow::Break(()));
+            }
 
-                    // Parse JSON response
-                    match serde_json::from_str::<OpenAIStreamResponse>(data) {
-                        Ok(stream_response) => {
-                            if let Some(choice) = stream_response.choices.first()
-                                && let Some(content) = &choice.delta.content
-                            {
-                                print!("{}", content.as_str());
-                            }
+            // Parse JSON response
+            let mut text = String::new();
+            match serde_json::from_str::<OpenAIStreamResponse>(data) {
+                Ok(stream_response) => {
+                    // Azure starts with a content filter chunk that has no ID or model
+                    if id.is_none() && !stream_response.id.is_empty() {
+                        id = Some(stream_response.id.clone());
+                    }
+                    if response_model.is_none() && !stream_response.model.is_empty() {
+                        response_model = Some(stream_response.model.clone());
+                    }
+                    if let Some(choice) = stream_response.choices.first() {
+                        if let Some(content) = &choice.delta.content {
+                            text.push_str(content);
                         }
-                        Err(e) => {
-                            // Log parse errors but continue processing
-                            eprintln!("Failed to parse streaming response: {}", e);
+                        if choice.finish_reason.is_some() {
+                            finish_reason = choice.finish_reason.clone();
                         }
                     }
+                    if let Some(usage) = stream_response.usage {
+                        self.last_usage.set(Some(usage.into()));
+                    }
                 }
-            }
-        } else {
-            let response = response.body_mut().read_json::<OpenAIResponse>()?;
-
-            for item in response.choices {
-                if item.message.role == "assistant" {
-                    result.push_str(item.message.content.as_str());
+                Err(e) => {
+                    // Log parse errors but continue processing
+                    log::warn!("Failed to parse streaming response: {}", e);
                 }
             }
+            Ok(ControlFlow::Continue(text))
+        })?;
+
+        if finish_reason.as_deref() == Some("length") {
+            self.truncated.set(true);
+            warn_truncated(self.label());
+        }
+
+        Ok(QueryResponse {
+            text: result,
+            model: response_model,
+            id,
+            usage: self.last_usage.get(),
+            finish_reason,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Credentials;
+    use crate::provider::{Image, ReasoningEffort};
+    use mockito::ServerGuard;
+
+    fn setup_mock_server() -> ServerGuard {
+        mockito::Server::new()
+    }
+
+    /// Body of a chat completion replying with `content`
+    fn completion_body(content: &str) -> String {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-5",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": {"role": "assistant", "content": content}
+            }]
+        })
+        .to_string()
+    }
+
+    fn test_provider(server: &ServerGuard) -> OpenAIProvider {
+        OpenAIProvider {
+            api_key: "test-key".to_string(),
+            base_url: server.url(),
+            model: DEFAULT_MODEL.to_string(),
+            defaults: QueryOptions::default(),
+            agent: agent_for_options(&QueryOptions::default(), "http://localhost").unwrap(),
+            last_usage: Cell::new(None),
+            truncated: Cell::new(false),
+            azure: None,
+            stream_usage: true,
+            tool_calls: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn weather_tool() -> Tool {
+        Tool {
+            name: "get_weather".to_string(),
+            description: Some("Current weather for a city".to_string()),
+            parameters: json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        }
+    }
+
+    #[test]
+    fn test_openai_new_reads_key_from_config() {
+        let config = Config {
+            credentials: Some(Credentials {
+                anthropic_api_key: None,
+                openai_api_key: Some("test-openai-key".to_string()),
+                mistral_api_key: None,
+                azure_openai_api_key: None,
+            }),
+            ..Default::default()
+        };
+
+        let provider = OpenAIProvider::new_with_env(&config, |_| None).unwrap();
+        assert_eq!(provider.api_key, "test-openai-key");
+        assert_eq!(provider.model, DEFAULT_MODEL);
+        assert_eq!(provider.base_url, DEFAULT_BASE_URL);
+
+        let env = |name: &str| (name == "OPENAI_API_KEY").then(|| "from-env".to_string());
+        let provider = OpenAIProvider::new_with_env(&config, env).unwrap();
+        assert_eq!(provider.api_key, "from-env");
+
+        let err = OpenAIProvider::new_with_env(&Config::default(), |_| None)
+            .err()
+            .unwrap();
+        assert_eq!(crate::error::exit_code(&err), 3);
+    }
+
+    #[test]
+    fn test_openai_base_url_from_config() {
+        let toml = r#"
+              [credentials]
+              openai_api_key = "test-openai-key"
+
+              [providers.openai]
+              base_url = "https://gateway.internal/v1"
+
+              [inputs]
+
+              [prompts]
+          "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        let provider = OpenAIProvider::new(&config).unwrap();
+        assert_eq!(provider.base_url, "https://gateway.internal/v1");
+        assert_eq!(provider.model, DEFAULT_MODEL);
+    }
+
+    #[test]
+    fn test_openai_flags_length_finish_reason() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "chatcmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "gpt-5",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "length",
+                    "message": {"role": "assistant", "content": "The answer is"}
+                  }]
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "test input", None);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "The answer is");
+        assert!(provider.last_truncated());
+    }
+
+    #[test]
+    fn test_openai_query_full_reports_metadata() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "chatcmpl-42",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "gpt-5-2025-08-07",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "Hi"}
+                  }]
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let response = provider
+            .query_full(
+                DEFAULT_MODEL,
+                &text_messages(&[(Role::User, "test input".to_string())]),
+                None,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.text, "Hi");
+        assert_eq!(response.id.as_deref(), Some("chatcmpl-42"));
+        assert_eq!(response.model.as_deref(), Some("gpt-5-2025-08-07"));
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+    }
+
+    fn azure_config(endpoint: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+              [providers.azure]
+              endpoint = "{}/"
+              deployment = "gpt-4o-prod"
+
+              [inputs]
+
+              [prompts]
+            "#,
+            endpoint
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_azure_sends_deployment_url_and_api_key_header() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/openai/deployments/gpt-4o-prod/chat/completions")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "api-version".to_string(),
+                DEFAULT_AZURE_API_VERSION.to_string(),
+            ))
+            .match_header("api-key", "azure-key")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "chatcmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "gpt-4o-2024-11-20",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "Hello from Azure"}
+                  }]
+              }"#,
+            )
+            .create();
+
+        let config = azure_config(&server.url());
+        let provider =
+            OpenAIProvider::azure_with_api_key(&config, "azure-key".to_string()).unwrap();
+        assert_eq!(provider.name(), "azure");
+        assert_eq!(provider.model(), "gpt-4o-prod");
+
+        let result = provider.query(&provider.model(), "Hi", None);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hello from Azure");
+    }
+
+    #[test]
+    fn test_azure_requires_endpoint_and_deployment() {
+        let config: Config = toml::from_str(
+            r#"
+              [providers.azure]
+              endpoint = "https://my-resource.openai.azure.com"
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+
+        let err = OpenAIProvider::azure_with_api_key(&config, "azure-key".to_string())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "[providers.azure] needs deployment to be set"
+        );
+        assert_eq!(crate::error::exit_code(&err), 2);
+
+        let err = OpenAIProvider::azure_with_api_key(&Config::default(), "azure-key".to_string())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("endpoint"), "{}", err);
+    }
+
+    #[test]
+    fn test_openai_merges_config_and_query_extra_fields() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "top_p": 0.9,
+                "frequency_penalty": 0.2
+            })))
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .create();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+              [providers.openai]
+              base_url = "{}"
+
+              [providers.openai.extra]
+              top_p = 0.5
+              frequency_penalty = 0.2
+
+              [inputs]
+
+              [prompts]
+            "#,
+            server.url()
+        ))
+        .unwrap();
+        let provider = OpenAIProvider::with_api_key(&config, "test-key".to_string()).unwrap();
+
+        let options = QueryOptions {
+            extra: crate::provider::parse_extra_json(r#"{"top_p": 0.9}"#).unwrap(),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_openai_stream_skips_keepalive_comments() {
+        let mut server = setup_mock_server();
+
+        let chunk = |content: &str| {
+            format!(
+                "data: {{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"gpt-5\",\"choices\":[{{\"index\":0,\"finish_reason\":null,\"delta\":{{\"content\":\"{}\"}}}}]}}\n\n",
+                content
+            )
+        };
+        let body = format!(
+            ": PROCESSING\n\n{}: keepalive\n\nevent: delta\n{}data: [DONE]\n\n",
+            chunk("Hi"),
+            chunk(" you")
+        );
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(body)
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}));
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hi you");
+    }
+
+    #[test]
+    fn test_openai_stream_reports_usage() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "stream": true,
+                "stream_options": {"include_usage": true}
+            })))
+            .with_status(200)
+            .with_body(
+                "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"gpt-5\",\"choices\":[{\"index\":0,\"finish_reason\":\"stop\",\"delta\":{\"content\":\"Hi\"}}]}\n\n\
+                 data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"gpt-5\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":1,\"total_tokens\":10}}\n\n\
+                 data: [DONE]\n\n",
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let response = provider
+            .query_full(
+                DEFAULT_MODEL,
+                &text_messages(&[(Role::User, "test".to_string())]),
+                Some(&mut |_: &str| {}),
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.text, "Hi");
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                input_tokens: 9,
+                output_tokens: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_openai_stream_usage_can_be_turned_off() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_request(|request| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(request.body().unwrap()).unwrap();
+                body["stream"] == true && body.get("stream_options").is_none()
+            })
+            .with_status(200)
+            .with_body(
+                "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"gpt-5\",\"choices\":[{\"index\":0,\"finish_reason\":\"stop\",\"delta\":{\"content\":\"Hi\"}}]}\n\n\
+                 data: [DONE]\n\n",
+            )
+            .create();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+              [credentials]
+              openai_api_key = "test-key"
+
+              [providers.openai]
+              base_url = "{}"
+              stream_usage = false
+
+              [inputs]
+
+              [prompts]
+            "#,
+            server.url()
+        ))
+        .unwrap();
+        let provider = OpenAIProvider::new_with_env(&config, |_| None).unwrap();
+        let result = provider.query(DEFAULT_MODEL, "test", Some(&mut |_: &str| {}));
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hi");
+        assert!(provider.last_usage().is_none());
+    }
+
+    #[test]
+    fn test_openai_stream_falls_back_to_json_body() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(completion_body("Whole reply"))
+            .create();
+
+        let provider = test_provider(&server);
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Whole reply");
+        assert_eq!(chunks, ["Whole reply"]);
+    }
+
+    #[test]
+    fn test_openai_query_returns_content() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_header("authorization", "Bearer test-key")
+            .with_status(200)
+            .with_body(completion_body("fix: correct typo in readme"))
+            .create();
+
+        let provider = test_provider(&server);
+
+        let result = provider.query(DEFAULT_MODEL, "test input", None);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "fix: correct typo in readme");
+    }
+
+    #[test]
+    fn test_openai_streaming_accumulates_deltas() {
+        let mut server = setup_mock_server();
+
+        let chunk = |content: &str| {
+            format!(
+                "data: {{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1700000000,\"model\":\"gpt-5\",\"choices\":[{{\"index\":0,\"finish_reason\":null,\"delta\":{{\"content\":\"{}\"}}}}]}}\n\n",
+                content
+            )
+        };
+        let body = format!("{}{}data: [DONE]\n\n", chunk("Hello"), chunk(", world"));
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(body)
+            .create();
+
+        let provider = test_provider(&server);
+
+        let mut chunks = Vec::new();
+        let result = provider.query(
+            DEFAULT_MODEL,
+            "test",
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Hello, world");
+        assert_eq!(chunks, ["Hello", ", world"]);
+    }
+
+    #[test]
+    fn test_openai_sends_full_history() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "messages": [
+                    {"role": "system", "content": "Be terse."},
+                    {"role": "user", "content": "Hi"},
+                    {"role": "assistant", "content": "Hello"},
+                    {"role": "user", "content": "How are you?"}
+                ]
+            })))
+            .with_status(200)
+            .with_body(completion_body("Fine."))
+            .create();
+
+        let provider = test_provider(&server);
+
+        let messages = vec![
+            (Role::System, "Be terse.".to_string()),
+            (Role::User, "Hi".to_string()),
+            (Role::Assistant, "Hello".to_string()),
+            (Role::User, "How are you?".to_string()),
+        ];
+        let result =
+            provider.query_with_history(DEFAULT_MODEL, &messages, None, &QueryOptions::default());
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "Fine.");
+    }
+
+    #[test]
+    fn test_openai_sends_image_data_uri() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "messages": [{
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "Describe this"},
+                        {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,/9j/"}}
+                    ]
+                }]
+            })))
+            .with_status(200)
+            .with_body(completion_body("A photo."))
+            .create();
+
+        let provider = test_provider(&server);
+
+        let image = Image {
+            media_type: "image/jpeg",
+            data: vec![0xFF, 0xD8, 0xFF],
+        };
+        let messages = [(
+            Role::User,
+            PromptContent::WithImages {
+                text: "Describe this".to_string(),
+                images: vec![image],
+            },
+        )];
+        let result =
+            provider.query_with_content(DEFAULT_MODEL, &messages, None, &QueryOptions::default());
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "A photo.");
+    }
+
+    #[test]
+    fn test_openai_parses_tool_calls() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "get_weather",
+                        "description": "Current weather for a city",
+                        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+                    }
+                }]
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "cmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "test-model",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "tool_calls",
+                    "message": {
+                      "role": "assistant",
+                      "content": null,
+                      "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                      }]
+                    }
+                  }]
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            tools: vec![weather_tool()],
+            ..Default::default()
+        };
+        let result = provider.query_with_history(
+            DEFAULT_MODEL,
+            &[(Role::User, "Weather in Paris?".to_string())],
+            None,
+            &options,
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap(), "");
+        assert_eq!(
+            provider.last_tool_calls(),
+            [ToolCall {
+                name: "get_weather".to_string(),
+                arguments: json!({"city": "Paris"}),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_openai_sends_generation_options() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "temperature": 0.5,
+                "max_completion_tokens": 8192,
+                "seed": 42,
+                "stop": ["\n\n", "END"]
+            })))
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .create();
+
+        let mut provider = test_provider(&server);
+        provider.defaults = QueryOptions {
+            temperature: Some(0.5),
+            max_tokens: Some(1024),
+            ..Default::default()
+        };
+
+        let options = QueryOptions {
+            max_tokens: Some(8192),
+            seed: Some(42),
+            stop: vec!["\n\n".to_string(), "END".to_string()],
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_openai_json_mode_sets_response_format() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "response_format": {"type": "json_object"}
+            })))
+            .with_status(200)
+            .with_body(completion_body(r#"{"ok": true}"#))
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            json_mode: true,
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let result = provider.query_with_history(DEFAULT_MODEL, &messages, None, &options);
+
+        mock.assert();
+        assert_eq!(result.unwrap(), r#"{"ok": true}"#);
+    }
+
+    #[test]
+    fn test_openai_rejects_too_many_stop_sequences() {
+        let mut server = setup_mock_server();
+        let mock = server.mock("POST", "/chat/completions").expect(0).create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            stop: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let messages = vec![(Role::User, "Hi".to_string())];
+        let err = provider
+            .query_with_history(DEFAULT_MODEL, &messages, None, &options)
+            .unwrap_err();
+
+        mock.assert();
+        assert_eq!(
+            err.to_string(),
+            "OpenAI accepts at most 4 stop sequences, got 5"
+        );
+    }
+
+    #[test]
+    fn test_is_reasoning_model() {
+        assert!(is_reasoning_model("o1"));
+        assert!(is_reasoning_model("o3-mini"));
+        assert!(is_reasoning_model("o4-mini-2025-04-16"));
+        assert!(!is_reasoning_model("gpt-4o"));
+        assert!(!is_reasoning_model("omni-moderation-latest"));
+    }
+
+    #[test]
+    fn test_openai_reasoning_model_body() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "model": "o3-mini",
+                "reasoning_effort": "high"
+            })))
+            .match_request(|request| {
+                let body: serde_json::Value =
+                    serde_json::from_slice(request.body().unwrap()).unwrap();
+                body.get("temperature").is_none()
+            })
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "chatcmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "o3-mini",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "ok"}
+                  }]
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let options = QueryOptions {
+            temperature: Some(0.5),
+            reasoning_effort: Some(ReasoningEffort::High),
+            ..Default::default()
+        };
+        let result = provider.query_with_history(
+            "o3-mini",
+            &[(Role::User, "Hi".to_string())],
+            None,
+            &options,
+        );
+
+        mock.assert();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_openai_parses_usage() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "id": "cmpl-1",
+                  "object": "chat.completion",
+                  "created": 1700000000,
+                  "model": "gpt-5",
+                  "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "ok"}
+                  }],
+                  "usage": {"prompt_tokens": 12, "completion_tokens": 34, "total_tokens": 46}
+              }"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        provider.query(DEFAULT_MODEL, "Hi", None).unwrap();
+
+        mock.assert();
+        assert_eq!(
+            provider.last_usage(),
+            Some(Usage {
+                input_tokens: 12,
+                output_tokens: 34
+            })
+        );
+    }
+
+    #[test]
+    fn test_openai_lists_all_models() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("GET", "/models")
+            .with_status(200)
+            .with_body(
+                r#"{"data": [
+                  {"id": "gpt-4o", "object": "model", "created": 1, "owned_by": "openai"},
+                  {"id": "o3-mini", "object": "model", "created": 2, "owned_by": "openai"},
+                  {"id": "whisper-1", "object": "model", "created": 3, "owned_by": "openai"}
+                ]}"#,
+            )
+            .create();
+
+        let provider = test_provider(&server);
+        let models = provider.list_models().unwrap();
+
+        mock.assert();
+        let ids: Vec<&str> = models.iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(ids, ["gpt-4o", "o3-mini", "whisper-1"]);
+        assert!(models.iter().all(|model| model.display_name.is_none()));
+    }
+
+    #[test]
+    fn test_openai_does_not_retry_auth_errors() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(401)
+            .with_body(r#"{"error": {"message": "Invalid API key"}}"#)
+            .expect(1)
+            .create();
+
+        let provider = test_provider(&server);
+        let result = provider.query(DEFAULT_MODEL, "Hi", None);
+
+        mock.assert();
+        assert!(result.unwrap_err().to_string().contains("401"));
+    }
+
+    #[test]
+    fn test_openai_reuses_agent_across_queries() {
+        let mut server = setup_mock_server();
+
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_body(completion_body("ok"))
+            .expect(3)
+            .create();
+
+        let provider = test_provider(&server);
+        for _ in 0..3 {
+            assert_eq!(provider.query(DEFAULT_MODEL, "Hi", None).unwrap(), "ok");
         }
 
-        Ok(result)
+        mock.assert();
     }
 }
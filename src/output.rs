@@ -1,40 +1,103 @@
-pub fn wrap_paragraph(paragraph: &str, width: usize) -> String {
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::{LinesWithEndings, as_24_bit_terminal_escaped};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::provider::{ModelInfo, Role, Usage};
+
+const BOLD: &str = "\x1b[1m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Styles accepted by `--output` and `default_output`
+pub const OUTPUT_STYLES: [&str; 6] = [
+    "none",
+    "json",
+    "wrapped",
+    "wrapped-preserve",
+    "markdown",
+    "md",
+];
+
+/// Wrap width used when the terminal width is unknown
+pub const DEFAULT_WIDTH: usize = 80;
+
+const THEME: &str = "base16-ocean.dark";
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// How [`wrap_text`] and [`wrap_paragraph`] break lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WrapOptions {
+    /// End each piece of a word split across lines with `-`. Widths under 2
+    /// leave no room for it, so words are then split without one.
+    pub hyphenate: bool,
+}
+
+/// Wrap a paragraph at `width` display columns, reflowing its whitespace.
+///
+/// Words wider than `width` are split on character boundaries.
+pub fn wrap_paragraph(paragraph: &str, width: usize, options: &WrapOptions) -> String {
     let mut result = Vec::new();
     let mut current_line = String::new();
+    let mut current_width = 0;
 
     for word in paragraph.split_whitespace() {
-        let potential_length = if current_line.is_empty() {
-            word.len()
+        let word_width = word.width();
+        let potential_width = if current_line.is_empty() {
+            word_width
         } else {
-            current_line.len() + 1 + word.len() // +1 for the space
+            current_width + 1 + word_width // +1 for the space
         };
 
-        if potential_length <= width {
+        if potential_width <= width {
             // Word fits on current line
             if !current_line.is_empty() {
                 current_line.push(' ');
             }
             current_line.push_str(word);
+            current_width = potential_width;
         } else {
             // Word doesn't fit, start a new line
             if !current_line.is_empty() {
-                result.push(current_line.clone());
-                current_line.clear();
+                result.push(std::mem::take(&mut current_line));
+                current_width = 0;
             }
 
             // Handle words longer than the width
-            if word.len() > width {
-                // Split the word
-                let mut remaining = word;
-                while remaining.len() > width {
-                    result.push(remaining[..width].to_string());
-                    remaining = &remaining[width..];
-                }
-                if !remaining.is_empty() {
-                    current_line = remaining.to_string();
+            if word_width > width {
+                // Split the word between characters, keeping a column for the hyphen
+                let hyphenate = options.hyphenate && width > 1;
+                let budget = if hyphenate { width - 1 } else { width };
+                for c in word.chars() {
+                    let char_width = c.width().unwrap_or(0);
+                    if current_width + char_width > budget && !current_line.is_empty() {
+                        if hyphenate {
+                            current_line.push('-');
+                        }
+                        result.push(std::mem::take(&mut current_line));
+                        current_width = 0;
+                    }
+                    current_line.push(c);
+                    current_width += char_width;
                 }
             } else {
                 current_line = word.to_string();
+                current_width = word_width;
             }
         }
     }
@@ -46,21 +109,529 @@ pub fn wrap_paragraph(paragraph: &str, width: usize) -> String {
     result.join("\n")
 }
 
-pub fn wrap_text(text: &str, width: usize) -> String {
+/// Wrap text at `width` display columns, reflowing each paragraph.
+///
+/// Paragraphs are separated by one or more blank lines (lines holding only
+/// whitespace count as blank), and each run of them becomes a single blank
+/// line in the output. Leading and trailing blank lines are dropped, so the
+/// result never starts or ends with a newline.
+pub fn wrap_text(text: &str, width: usize, options: &WrapOptions) -> String {
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut paragraphs = Vec::new();
+    let mut current = Vec::new();
+
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(wrap_paragraph(&current.join(" "), width, options));
+                current.clear();
+            }
+        } else {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(wrap_paragraph(&current.join(" "), width, options));
+    }
+
+    paragraphs.join("\n\n")
+}
+
+/// Wrap overlong lines while keeping the existing line breaks.
+///
+/// Unlike [`wrap_text`], single newlines are not reflowed, so lists and other
+/// deliberately broken lines survive. Wrapped lines keep the line's indentation.
+pub fn wrap_text_preserve_breaks(text: &str, width: usize) -> String {
     if width == 0 {
         return String::new();
     }
 
+    text.split('\n')
+        .map(|line| {
+            let content = line.trim_start();
+            let indent = &line[..line.len() - content.len()];
+            if content.is_empty() {
+                return String::new();
+            }
+
+            wrap_paragraph(
+                content,
+                width.saturating_sub(indent.width()).max(1),
+                &WrapOptions::default(),
+            )
+            .lines()
+            .map(|wrapped| format!("{}{}", indent, wrapped))
+            .collect::<Vec<_>>()
+            .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Width to wrap output at: `explicit` if given, else the terminal's width,
+/// else [`DEFAULT_WIDTH`] when stdout is not a terminal
+pub fn output_width(explicit: Option<usize>) -> usize {
+    explicit
+        .or_else(|| {
+            terminal_size::terminal_size_of(std::io::stdout())
+                .map(|(terminal_size::Width(width), _)| usize::from(width))
+        })
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Backend-independent event emitted by `--json-stream`, one per NDJSON line
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StreamEvent<'a> {
+    /// A chunk of the reply as it arrived
+    Delta { text: &'a str },
+    /// The model's thinking, with `--show-thinking`
+    Thinking { text: &'a str },
+    /// A tool call the model asked for instead of replying
+    #[serde(rename = "tool_call")]
+    ToolCall {
+        name: &'a str,
+        arguments: &'a serde_json::Value,
+    },
+    /// End of the reply, with token usage when the provider reported it
+    Done { usage: Option<Usage> },
+}
+
+impl StreamEvent<'_> {
+    pub fn to_ndjson(&self) -> String {
+        serde_json::to_string(self).expect("stream events always serialize")
+    }
+}
+
+/// Stream sink that prints each chunk to stdout as soon as it arrives
+pub fn print_chunk(chunk: &str) {
+    print!("{}", chunk);
+    let _ = std::io::stdout().flush();
+}
+
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether to show a spinner: not `--quiet`, and stdout is a terminal so
+/// interactive use is likely
+pub fn spinner_enabled(quiet: bool, stdout_is_tty: bool) -> bool {
+    !quiet && stdout_is_tty && std::io::stderr().is_terminal()
+}
+
+/// Spinner with the elapsed time, drawn on stderr while a reply is awaited.
+///
+/// It runs on its own thread until [`Spinner::stop`] or drop, which clear the line.
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start drawing `message`, or do nothing when not `enabled`
+    pub fn start(message: &str, enabled: bool) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        if !enabled {
+            return Spinner { stop, handle: None };
+        }
+
+        let message = message.to_string();
+        let stopped = Arc::clone(&stop);
+        let handle = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut stderr = std::io::stderr();
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let _ = write!(
+                    stderr,
+                    "\r{} {} {:.1}s",
+                    frame,
+                    message,
+                    started.elapsed().as_secs_f32()
+                );
+                let _ = stderr.flush();
+                std::thread::park_timeout(SPINNER_INTERVAL);
+            }
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        });
+
+        Spinner {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Whether the spinner is being drawn
+    pub fn is_active(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Stop the spinner and clear its line
+    pub fn stop(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// When to write styled output, from `--color`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Style output written to a terminal unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Stream that styled output is written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+static COLOR_CHOICE: RwLock<ColorChoice> = RwLock::new(ColorChoice::Auto);
+
+/// Set the `--color` choice that [`should_colorize`] applies
+pub fn set_color_choice(choice: ColorChoice) {
+    *COLOR_CHOICE.write().unwrap_or_else(|e| e.into_inner()) = choice;
+}
+
+/// Whether styled output should be written to `stream`, following `--color`,
+/// then `NO_COLOR` and whether the stream is a terminal
+pub fn should_colorize(stream: OutputStream) -> bool {
+    let choice = *COLOR_CHOICE.read().unwrap_or_else(|e| e.into_inner());
+    let is_tty = match stream {
+        OutputStream::Stdout => std::io::stdout().is_terminal(),
+        OutputStream::Stderr => std::io::stderr().is_terminal(),
+    };
+    should_color(choice, std::env::var("NO_COLOR").ok().as_deref(), is_tty)
+}
+
+/// Whether to style a stream, given the `--color` choice, the `NO_COLOR`
+/// value and whether it is a terminal. An explicit choice wins over both.
+pub fn should_color(choice: ColorChoice, no_color: Option<&str>, is_tty: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_tty && no_color.is_none_or(str::is_empty),
+    }
+}
+
+/// Format a list of models: a JSON array for the `json` style, otherwise
+/// one line per model under a heading naming the provider
+pub fn format_models(provider: &str, models: &[ModelInfo], style: &str) -> String {
+    if style == "json" {
+        return serde_json::to_string(models).unwrap_or_default();
+    }
+
+    let mut lines = vec![format!("Available {} models:", provider)];
+    lines.extend(models.iter().map(|model| match &model.display_name {
+        Some(name) => format!("  {} - {}", model.id, name),
+        None => format!("  {}", model.id),
+    }));
+    lines.join("\n")
+}
+
+/// Format a model's thinking to print before its answer: each line is
+/// prefixed with `> ` and, with `color`, dimmed
+pub fn format_thinking(thinking: &str, color: bool) -> String {
+    thinking
+        .trim()
+        .lines()
+        .map(|line| {
+            if color {
+                format!("{}> {}{}", DIM, line, RESET)
+            } else {
+                format!("> {}", line).trim_end().to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render a conversation as a Markdown document, with a header naming the
+/// provider, model and time, then a `## User`/`## Assistant` section per
+/// message. Messages are copied verbatim, so their code fences survive.
+pub fn render_transcript(
+    provider: &str,
+    model: &str,
+    timestamp: &str,
+    history: &[(Role, String)],
+) -> String {
+    let mut transcript = format!(
+        "# aika transcript\n\n- Provider: {}\n- Model: {}\n- Date: {}\n",
+        provider, model, timestamp
+    );
+
+    for (role, content) in history {
+        let heading = match role {
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+            Role::System => "System",
+        };
+        transcript.push_str(&format!("\n## {}\n\n{}\n", heading, content.trim_end()));
+    }
+
+    transcript
+}
+
+/// Render Markdown for the terminal with basic ANSI styling.
+///
+/// Without `color` the text is returned unchanged, so piped output stays plain.
+pub fn render_markdown(text: &str, color: bool) -> String {
+    if !color {
+        return text.to_string();
+    }
+
     let mut result = Vec::new();
+    // Language and accumulated source of the fenced block being read
+    let mut code_block: Option<(String, String)> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+
+        if let Some((lang, mut code)) = code_block.take() {
+            if trimmed.starts_with("```") {
+                result.extend(
+                    highlight_code(&code, &lang)
+                        .lines()
+                        .map(|line| format!("    {}", line)),
+                );
+            } else {
+                code.push_str(line);
+                code.push('\n');
+                code_block = Some((lang, code));
+            }
+            continue;
+        }
+
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            let lang = lang.trim();
+            if !lang.is_empty() {
+                result.push(format!("{}{}{}", DIM, lang, RESET));
+            }
+            code_block = Some((lang.to_string(), String::new()));
+            continue;
+        }
+
+        let heading_level = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&heading_level) && trimmed[heading_level..].starts_with(' ') {
+            let heading = trimmed[heading_level..].trim();
+            result.push(format!(
+                "{}{}{}{}",
+                BOLD,
+                UNDERLINE,
+                render_inline(heading),
+                RESET
+            ));
+            continue;
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some(item) = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "))
+            .or_else(|| trimmed.strip_prefix("+ "))
+        {
+            result.push(format!("{}• {}", indent, render_inline(item)));
+            continue;
+        }
+
+        result.push(render_inline(line));
+    }
+
+    if let Some((lang, code)) = code_block {
+        result.extend(
+            highlight_code(&code, &lang)
+                .lines()
+                .map(|line| format!("    {}", line)),
+        );
+    }
+
+    result.join("\n")
+}
+
+/// Syntax-highlight fenced code blocks, leaving the rest of the text untouched.
+///
+/// Used to re-render a buffered streamed reply; without `color` the text is
+/// returned unchanged.
+pub fn highlight_code_blocks(text: &str, color: bool) -> String {
+    if !color {
+        return text.to_string();
+    }
+
+    let mut result = String::new();
+    let mut code_block: Option<(String, String)> = None;
+
+    for line in LinesWithEndings::from(text) {
+        let trimmed = line.trim();
+
+        match code_block.take() {
+            Some((lang, mut code)) => {
+                if trimmed.starts_with("```") {
+                    result.push_str(&highlight_code(&code, &lang));
+                    result.push_str(line);
+                } else {
+                    code.push_str(line);
+                    code_block = Some((lang, code));
+                }
+            }
+            None => {
+                if let Some(lang) = trimmed.strip_prefix("```") {
+                    code_block = Some((lang.trim().to_string(), String::new()));
+                }
+                result.push_str(line);
+            }
+        }
+    }
+
+    if let Some((lang, code)) = code_block {
+        result.push_str(&highlight_code(&code, &lang));
+    }
+
+    result
+}
+
+/// Highlight `code` for a 24-bit color terminal, resetting the style at the end of every line.
+///
+/// Unknown languages fall back to plain text.
+fn highlight_code(code: &str, lang: &str) -> String {
+    let syntax = SYNTAXES
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, &THEMES.themes[THEME]);
+    let mut result = String::new();
+
+    for line in LinesWithEndings::from(code) {
+        match highlighter.highlight_line(line, &SYNTAXES) {
+            Ok(ranges) => {
+                let escaped = as_24_bit_terminal_escaped(&ranges, false);
+                result.push_str(escaped.trim_end_matches('\n'));
+                result.push_str(RESET);
+                if line.ends_with('\n') {
+                    result.push('\n');
+                }
+            }
+            Err(_) => result.push_str(line),
+        }
+    }
+
+    result
+}
+
+/// Style `**bold**`, `*italic*` and `` `code` `` spans within a line
+fn render_inline(line: &str) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+
+    while !rest.is_empty() {
+        let (marker, style) = if rest.starts_with("**") {
+            ("**", BOLD)
+        } else if rest.starts_with('`') {
+            ("`", CYAN)
+        } else if rest.starts_with('*') {
+            ("*", ITALIC)
+        } else {
+            let c = rest.chars().next().unwrap();
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+
+        let after = &rest[marker.len()..];
+        match after.find(marker) {
+            Some(end) if end > 0 && !after.starts_with(char::is_whitespace) => {
+                result.push_str(style);
+                result.push_str(&after[..end]);
+                result.push_str(RESET);
+                rest = &after[end + marker.len()..];
+            }
+            _ => {
+                result.push_str(marker);
+                rest = after;
+            }
+        }
+    }
 
-    let paragraphs: Vec<&str> = text.split("\n\n").collect();
+    result
+}
 
-    for paragraph in paragraphs {
-        let wrapped_paragraph = wrap_paragraph(paragraph, width);
-        result.push(wrapped_paragraph);
+/// File a response is saved to, created (with its parent directories) on the
+/// first write, so a query that fails before replying leaves an existing file
+/// as it was.
+pub struct OutputFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl OutputFile {
+    /// Output file at `path`; an existing file is only replaced when `force` is set
+    pub fn new(path: &Path, force: bool) -> anyhow::Result<Self> {
+        if path.exists() && !force {
+            return Err(anyhow::anyhow!(
+                "Output file already exists at {:?}; use --force to overwrite it",
+                path
+            ));
+        }
+
+        Ok(OutputFile {
+            path: path.to_path_buf(),
+            file: None,
+        })
     }
 
-    result.join("\n\n")
+    fn open(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)
+                .map_err(|e| {
+                    std::io::Error::new(
+                        e.kind(),
+                        format!("Failed to create output file {:?}: {}", self.path, e),
+                    )
+                })?;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().expect("output file was just opened"))
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.open()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -70,7 +641,7 @@ mod tests {
     #[test]
     fn test_wrap_text() {
         let text = "This is a sample text that should be wrapped at a specific width";
-        let wrapped = wrap_text(text, 20);
+        let wrapped = wrap_text(text, 20, &WrapOptions::default());
         let expected = "This is a sample\ntext that should be\nwrapped at a\nspecific width";
         assert_eq!(wrapped, expected);
     }
@@ -78,38 +649,91 @@ mod tests {
     #[test]
     fn test_wrap_text_short() {
         let text = "Short";
-        let wrapped = wrap_text(text, 20);
+        let wrapped = wrap_text(text, 20, &WrapOptions::default());
         assert_eq!(wrapped, "Short");
     }
 
     #[test]
     fn test_wrap_text_long_word() {
         let text = "Supercalifragilisticexpialidocious word";
-        let wrapped = wrap_text(text, 10);
+        let wrapped = wrap_text(text, 10, &WrapOptions::default());
         let expected = "Supercalif\nragilistic\nexpialidoc\nious word";
         assert_eq!(wrapped, expected);
     }
 
+    #[test]
+    fn test_wrap_text_long_word_hyphenated() {
+        let text = "Supercalifragilisticexpialidocious word";
+        let options = WrapOptions { hyphenate: true };
+        let wrapped = wrap_text(text, 10, &options);
+        let expected = "Supercali-\nfragilist-\nicexpiali-\ndocious\nword";
+        assert_eq!(wrapped, expected);
+        assert!(wrapped.lines().all(|line| line.width() <= 10));
+
+        // No room for a hyphen in a single column
+        assert_eq!(wrap_text("abc", 1, &options), "a\nb\nc");
+        // Words that fit are never hyphenated
+        assert_eq!(wrap_text("short words", 5, &options), "short\nwords");
+    }
+
     #[test]
     fn test_wrap_text_zero_width() {
         let text = "Some text";
-        let wrapped = wrap_text(text, 0);
+        let wrapped = wrap_text(text, 0, &WrapOptions::default());
         assert_eq!(wrapped, "");
     }
 
     #[test]
     fn test_wrap_text_with_spaces() {
         let text = "Some text\n\nwith multiple\n\nnew lines";
-        let wrapped = wrap_text(text, 10);
+        let wrapped = wrap_text(text, 10, &WrapOptions::default());
         let expected = "Some text\n\nwith\nmultiple\n\nnew lines";
         assert_eq!(wrapped, expected);
     }
 
+    #[test]
+    fn test_wrap_text_collapses_runs_of_blank_lines() {
+        let text = "First\n\n\nSecond\n\n\n\n\nThird";
+        assert_eq!(
+            wrap_text(text, 20, &WrapOptions::default()),
+            "First\n\nSecond\n\nThird"
+        );
+
+        // Whitespace-only lines separate paragraphs too
+        let text = "First\n  \n\t\nSecond";
+        assert_eq!(
+            wrap_text(text, 20, &WrapOptions::default()),
+            "First\n\nSecond"
+        );
+    }
+
+    #[test]
+    fn test_wrap_text_drops_leading_and_trailing_blank_lines() {
+        assert_eq!(
+            wrap_text("\n\n\nHello there\n\n", 20, &WrapOptions::default()),
+            "Hello there"
+        );
+        assert_eq!(
+            wrap_text("\nOne\n\nTwo\n", 20, &WrapOptions::default()),
+            "One\n\nTwo"
+        );
+        assert_eq!(wrap_text("\n\n \n", 20, &WrapOptions::default()), "");
+    }
+
+    #[test]
+    fn test_wrap_text_reflows_single_newlines() {
+        let text = "A line\nbroken\r\nearly";
+        assert_eq!(
+            wrap_text(text, 20, &WrapOptions::default()),
+            "A line broken early"
+        );
+    }
+
     #[test]
     fn test_wrap_paragraph() {
         let paragraph =
             "This is a test paragraph to check the wrapping functionality.\nThis is a second line.";
-        let wrapped = wrap_paragraph(paragraph, 72);
+        let wrapped = wrap_paragraph(paragraph, 72, &WrapOptions::default());
         let expected =
             "This is a test paragraph to check the wrapping functionality. This is a\nsecond line.";
         assert_eq!(wrapped, expected);
@@ -121,11 +745,215 @@ mod tests {
         \n\
         The change moves git configuration from legacy format (userName, userEmail, \
         extraConfig) to the new consolidated settings format while preserving the same functionality.";
-        let wrapped = wrap_text(text, 72);
+        let wrapped = wrap_text(text, 72, &WrapOptions::default());
         let expected = "refactor: migrate git config to new settings format\n\n\
         The change moves git configuration from legacy format (userName,\n\
         userEmail, extraConfig) to the new consolidated settings format while\n\
         preserving the same functionality.";
         assert_eq!(wrapped, expected);
     }
+
+    #[test]
+    fn test_wrap_text_accented_latin() {
+        let text = "café crème brûlée à la française";
+        let wrapped = wrap_text(text, 12, &WrapOptions::default());
+        assert_eq!(wrapped, "café crème\nbrûlée à la\nfrançaise");
+    }
+
+    #[test]
+    fn test_wrap_text_cjk_counts_columns() {
+        // Each CJK character takes two columns
+        let text = "你好世界 日本語のテキスト";
+        let wrapped = wrap_text(text, 10, &WrapOptions::default());
+        assert_eq!(wrapped, "你好世界\n日本語のテ\nキスト");
+        assert!(wrapped.lines().all(|line| line.width() <= 10));
+    }
+
+    #[test]
+    fn test_wrap_text_long_emoji_word() {
+        let text = "🎉🎉🎉🎉🎉🎉 done";
+        let wrapped = wrap_text(text, 5, &WrapOptions::default());
+        assert_eq!(wrapped, "🎉🎉\n🎉🎉\n🎉🎉\ndone");
+        assert!(wrapped.lines().all(|line| line.width() <= 5));
+    }
+
+    #[test]
+    fn test_wrap_text_multibyte_long_word_no_panic() {
+        let text = "ééééééééééééé";
+        let wrapped = wrap_text(text, 4, &WrapOptions::default());
+        assert_eq!(wrapped, "éééé\néééé\néééé\né");
+    }
+
+    #[test]
+    fn test_wrap_text_preserve_breaks_keeps_list() {
+        let text = "Changes:\n- add a flag\n- fix the parser so that it handles very long input lines\n\nDone.";
+        let wrapped = wrap_text_preserve_breaks(text, 30);
+        let expected = "Changes:\n- add a flag\n- fix the parser so that it\nhandles very long input lines\n\nDone.";
+        assert_eq!(wrapped, expected);
+    }
+
+    #[test]
+    fn test_wrap_text_preserve_breaks_keeps_indent() {
+        let text = "  - nested item that is long enough to wrap";
+        let wrapped = wrap_text_preserve_breaks(text, 20);
+        assert_eq!(wrapped, "  - nested item that\n  is long enough to\n  wrap");
+        assert!(wrapped.lines().all(|line| line.len() <= 20));
+    }
+
+    #[test]
+    fn test_wrap_text_preserve_breaks_zero_width() {
+        assert_eq!(wrap_text_preserve_breaks("Some text", 0), "");
+    }
+
+    const MARKDOWN: &str = "# Title\n\nSome **bold** text.\n\n- item\n\n```rust\nfn main() {}\n```";
+
+    #[test]
+    fn test_render_transcript() {
+        let history = [
+            (Role::User, "Write hello world in Rust".to_string()),
+            (
+                Role::Assistant,
+                "Here:\n\n```rust\nfn main() {\n    println!(\"hello\");\n}\n```\n".to_string(),
+            ),
+            (Role::User, "Thanks!".to_string()),
+            (Role::Assistant, "You're welcome.".to_string()),
+        ];
+
+        let transcript = render_transcript(
+            "anthropic",
+            "claude-sonnet-4-5",
+            "2026-01-01 13:05 UTC",
+            &history,
+        );
+
+        assert_eq!(
+            transcript,
+            "# aika transcript\n\n\
+             - Provider: anthropic\n\
+             - Model: claude-sonnet-4-5\n\
+             - Date: 2026-01-01 13:05 UTC\n\
+             \n## User\n\nWrite hello world in Rust\n\
+             \n## Assistant\n\nHere:\n\n```rust\nfn main() {\n    println!(\"hello\");\n}\n```\n\
+             \n## User\n\nThanks!\n\
+             \n## Assistant\n\nYou're welcome.\n"
+        );
+    }
+
+    #[test]
+    fn test_format_models() {
+        let models = [
+            ModelInfo {
+                id: "claude-sonnet-4-5".to_string(),
+                display_name: Some("Claude Sonnet 4.5".to_string()),
+            },
+            ModelInfo {
+                id: "gpt-5".to_string(),
+                display_name: None,
+            },
+        ];
+
+        assert_eq!(
+            format_models("anthropic", &models, "none"),
+            "Available anthropic models:\n  claude-sonnet-4-5 - Claude Sonnet 4.5\n  gpt-5"
+        );
+        assert_eq!(
+            format_models("anthropic", &models[1..], "json"),
+            r#"[{"id":"gpt-5","display_name":null}]"#
+        );
+    }
+
+    #[test]
+    fn test_format_thinking() {
+        let thinking = "The user wants a summary.\n\nStart with the title.\n";
+        assert_eq!(
+            format_thinking(thinking, false),
+            "> The user wants a summary.\n>\n> Start with the title."
+        );
+        assert_eq!(format_thinking("Short.", true), "\x1b[2m> Short.\x1b[0m");
+    }
+
+    #[test]
+    fn test_should_color() {
+        assert!(should_color(ColorChoice::Auto, None, true));
+        assert!(should_color(ColorChoice::Auto, Some(""), true));
+        assert!(!should_color(ColorChoice::Auto, Some("1"), true));
+        assert!(!should_color(ColorChoice::Auto, None, false));
+
+        for (no_color, is_tty) in [(None, true), (None, false), (Some("1"), true)] {
+            assert!(should_color(ColorChoice::Always, no_color, is_tty));
+            assert!(!should_color(ColorChoice::Never, no_color, is_tty));
+        }
+    }
+
+    #[test]
+    fn test_spinner_suppressed_without_tty() {
+        assert!(!spinner_enabled(false, false));
+        assert!(!spinner_enabled(true, true));
+
+        let spinner = Spinner::start("Waiting", spinner_enabled(false, false));
+        assert!(!spinner.is_active());
+        spinner.stop();
+    }
+
+    #[test]
+    fn test_render_markdown_plain_fallback() {
+        assert_eq!(render_markdown(MARKDOWN, false), MARKDOWN);
+    }
+
+    #[test]
+    fn test_render_markdown_styles() {
+        let rendered = render_markdown(MARKDOWN, true);
+        assert!(rendered.contains("\x1b[1m\x1b[4mTitle\x1b[0m"));
+        assert!(rendered.contains("Some \x1b[1mbold\x1b[0m text."));
+        assert!(rendered.contains("• item"));
+        assert!(rendered.contains("    \x1b[38;2;"));
+        assert!(rendered.contains("main"));
+        assert!(!rendered.contains("```"));
+    }
+
+    #[test]
+    fn test_render_inline_unmatched_marker() {
+        assert_eq!(render_inline("2 * 3 = 6"), "2 * 3 = 6");
+    }
+
+    const RUST_REPLY: &str =
+        "Here you go:\n\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\nDone.\n";
+
+    #[test]
+    fn test_highlight_code_blocks() {
+        let highlighted = highlight_code_blocks(RUST_REPLY, true);
+        assert!(highlighted.starts_with("Here you go:\n\n```rust\n"));
+        assert!(highlighted.ends_with("\x1b[0m\n```\nDone.\n"));
+        assert!(highlighted.contains("\x1b[38;2;"));
+
+        let code_lines: Vec<&str> = highlighted.lines().skip(3).take(3).collect();
+        assert!(code_lines.iter().all(|line| line.ends_with("\x1b[0m")));
+    }
+
+    #[test]
+    fn test_highlight_code_blocks_plain_fallback() {
+        assert_eq!(highlight_code_blocks(RUST_REPLY, false), RUST_REPLY);
+        assert_eq!(
+            highlight_code_blocks("no code here\n", true),
+            "no code here\n"
+        );
+    }
+
+    #[test]
+    fn test_output_file_requires_force_and_opens_on_first_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/reply.txt");
+
+        let mut file = OutputFile::new(&path, false).unwrap();
+        assert!(!path.exists());
+        write!(file, "First").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "First");
+
+        assert!(OutputFile::new(&path, false).is_err());
+        let mut file = OutputFile::new(&path, true).unwrap();
+        file.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "First");
+        write!(file, "Second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Second");
+    }
 }
@@ -0,0 +1,174 @@
+//! Running a chain of prompts with `aika pipeline`, each reply becoming the
+//! next step's `{input}`.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+
+use crate::config::{Config, PipelineStep};
+use crate::provider::Provider;
+use crate::template::render_prompt;
+
+/// Steps of the pipeline called `name` in config
+pub fn find_pipeline<'a>(config: &'a Config, name: &str) -> Result<&'a [PipelineStep]> {
+    let steps = config.pipeline.get(name).ok_or_else(|| {
+        let mut names: Vec<&str> = config.pipeline.keys().map(String::as_str).collect();
+        names.sort();
+        anyhow::anyhow!(
+            "Unknown pipeline {:?}; defined pipelines: {}",
+            name,
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        )
+    })?;
+
+    if steps.is_empty() {
+        return Err(anyhow::anyhow!("Pipeline {:?} has no steps", name));
+    }
+    Ok(steps)
+}
+
+/// Template of a step: an `inline:` template or a prompt from config
+fn step_template(config: &Config, prompt: &str) -> Result<String> {
+    if let Some(template) = prompt.strip_prefix("inline:") {
+        return Ok(template.to_string());
+    }
+
+    config
+        .prompts
+        .get(prompt)
+        .map(|prompt| prompt.prompt.clone())
+        .ok_or_else(|| anyhow::anyhow!("Unknown prompt {:?}", prompt))
+}
+
+/// Send each step to `provider` in turn, starting with `input`, and return
+/// the reply of the last step. `on_step` is called with the index and reply
+/// of every step as it completes.
+pub fn run_pipeline(
+    provider: &dyn Provider,
+    config: &Config,
+    steps: &[PipelineStep],
+    default_model: &str,
+    input: String,
+    on_step: &mut dyn FnMut(usize, &str),
+) -> Result<String> {
+    let name = provider.name();
+    let mut current = input;
+
+    for (index, step) in steps.iter().enumerate() {
+        let context = || format!("Pipeline step {} ({})", index + 1, step.prompt);
+
+        let template = step_template(config, &step.prompt).with_context(context)?;
+        let vars = HashMap::from([("input".to_string(), current)]);
+        let prompt = render_prompt(&template, &vars, false).with_context(context)?;
+
+        let model = config.resolve_model(&name, step.model.as_deref().unwrap_or(default_model));
+        log::debug!("Running pipeline step {} with {}", index + 1, model);
+        current = provider
+            .query(&model, &prompt, None)
+            .with_context(context)?;
+
+        on_step(index, &current);
+    }
+
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Prompt, test_config_for};
+    use crate::provider::create_provider;
+
+    fn test_config(server_url: &str) -> Config {
+        let mut config = test_config_for(server_url);
+        config.prompts.insert(
+            "summarize".to_string(),
+            Prompt {
+                prompt: "Summarize: {input}".to_string(),
+                model: None,
+                provider: None,
+            },
+        );
+        config.pipeline = toml::from_str(
+            r#"
+              empty = []
+
+              [[release]]
+              prompt = "summarize"
+
+              [[release]]
+              prompt = "inline:Write release notes from: {input}"
+              model = "claude-notes"
+            "#,
+        )
+        .unwrap();
+        config
+    }
+
+    #[test]
+    fn test_run_pipeline_feeds_each_reply_to_the_next_step() {
+        let mut server = mockito::Server::new();
+        let first = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "claude-default",
+                "messages": [{"role": "user", "content": "Summarize: the diff"}]
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "A summary"}]}"#)
+            .create();
+        let second = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "claude-notes",
+                "messages": [{"role": "user", "content": "Write release notes from: A summary"}]
+            })))
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "Release notes"}]}"#)
+            .create();
+
+        let config = test_config(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+        let steps = find_pipeline(&config, "release").unwrap();
+
+        let mut replies = Vec::new();
+        let result = run_pipeline(
+            provider.as_ref(),
+            &config,
+            steps,
+            "claude-default",
+            "the diff".to_string(),
+            &mut |index, reply| replies.push((index, reply.to_string())),
+        )
+        .unwrap();
+
+        first.assert();
+        second.assert();
+        assert_eq!(result, "Release notes");
+        assert_eq!(
+            replies,
+            [
+                (0, "A summary".to_string()),
+                (1, "Release notes".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_pipeline_errors() {
+        let config = test_config("http://localhost");
+
+        let err = find_pipeline(&config, "missing").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown pipeline \"missing\"; defined pipelines: empty, release"
+        );
+
+        let err = find_pipeline(&config, "empty").unwrap_err();
+        assert_eq!(err.to_string(), "Pipeline \"empty\" has no steps");
+    }
+}
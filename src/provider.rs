@@ -7,39 +7,574 @@
 //! # Example
 //!
 //! ```rust
-//! use crate::provider::Provider;
+//! use aika_rs::provider::{ModelInfo, Provider};
+//! use anyhow::Result;
+//!
+//! struct MyAIProvider;
 //!
 //! impl Provider for MyAIProvider {
-//!     fn list_models(&self) -> Result<()> {
+//!     fn model(&self) -> String {
+//!         "my-model".to_string()
+//!     }
+//!
+//!     fn name(&self) -> String {
+//!         "my-ai".to_string()
+//!     }
+//!
+//!     fn list_models(&self) -> Result<Vec<ModelInfo>> {
 //!         // Implementation to fetch available models
-//!         Ok(())
+//!         Ok(Vec::new())
 //!     }
 //!
-//!     fn query(&self, message: &str, model: &str, streaming: bool) -> Result<()> {
-//!         // Implementation to send a message and get response
-//!         Ok(())
+//!     fn query(
+//!         &self,
+//!         model: &str,
+//!         prompt: &str,
+//!         on_chunk: Option<&mut dyn FnMut(&str)>,
+//!     ) -> Result<String> {
+//!         // Implementation to send the prompt and return the response
+//!         Ok(String::new())
 //!     }
 //! }
 //! ```
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
 
 use crate::claude::ClaudeProvider;
-use crate::config::Config;
+use crate::config::{self, Config};
+use crate::mistral::MistralProvider;
+use crate::openai::OpenAIProvider;
+use crate::tools::{Tool, ToolCall};
+
+/// Author of a message in a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+        }
+    }
+}
+
+/// Image attached to a prompt, with its MIME type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub media_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Image bytes as standard base64
+    pub fn base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(&self.data)
+    }
+
+    /// Image as a `data:` URI
+    pub fn data_uri(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.base64())
+    }
+}
+
+/// Content of a message: plain text, or text with images for vision models
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptContent {
+    Text(String),
+    WithImages { text: String, images: Vec<Image> },
+}
+
+impl PromptContent {
+    pub fn text(&self) -> &str {
+        match self {
+            PromptContent::Text(text) | PromptContent::WithImages { text, .. } => text,
+        }
+    }
+
+    pub fn images(&self) -> &[Image] {
+        match self {
+            PromptContent::Text(_) => &[],
+            PromptContent::WithImages { images, .. } => images,
+        }
+    }
+}
+
+impl From<String> for PromptContent {
+    fn from(text: String) -> Self {
+        PromptContent::Text(text)
+    }
+}
+
+/// Wrap plain text messages as [`PromptContent`]
+pub fn text_messages(messages: &[(Role, String)]) -> Vec<(Role, PromptContent)> {
+    messages
+        .iter()
+        .map(|(role, text)| (*role, PromptContent::Text(text.clone())))
+        .collect()
+}
+
+/// A model offered by a provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Human-readable name, for providers that report one
+    pub display_name: Option<String>,
+}
+
+/// Models whose ID or display name contains `filter` (ignoring case),
+/// sorted by ID when `sort` is set and in the provider's order otherwise
+pub fn select_models(models: Vec<ModelInfo>, filter: Option<&str>, sort: bool) -> Vec<ModelInfo> {
+    let filter = filter.map(str::to_lowercase);
+    let mut models: Vec<ModelInfo> = models
+        .into_iter()
+        .filter(|model| {
+            filter.as_deref().is_none_or(|filter| {
+                model.id.to_lowercase().contains(filter)
+                    || model
+                        .display_name
+                        .as_deref()
+                        .is_some_and(|name| name.to_lowercase().contains(filter))
+            })
+        })
+        .collect();
+
+    if sort {
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+    models
+}
+
+/// Token counts reported by a provider for a single request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// A reply with the metadata the provider reported alongside it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueryResponse {
+    pub text: String,
+    /// Model that actually answered, which may be more specific than the one requested
+    pub model: Option<String>,
+    /// Provider's ID for the reply
+    pub id: Option<String>,
+    pub usage: Option<Usage>,
+    /// Why generation stopped, in the provider's own terms (`end_turn`, `stop`, `length`, ...)
+    pub finish_reason: Option<String>,
+}
+
+/// How much effort reasoning models spend thinking before they answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+}
+
+/// Per-query settings; unset fields fall back to provider defaults
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryOptions {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub max_retries: Option<u32>,
+    pub timeout: Option<Duration>,
+    pub proxy: Option<String>,
+    /// Reasoning effort, for providers with reasoning models
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Tools the model may call instead of replying with text
+    pub tools: Vec<Tool>,
+    /// Sampling seed, for providers that support more deterministic output
+    pub seed: Option<u64>,
+    /// Sequences that end the reply when the model generates them
+    pub stop: Vec<String>,
+    /// Ask the model to reply with a JSON object
+    pub json_mode: bool,
+    /// Fields added to the request body where it does not already set them
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl QueryOptions {
+    /// Build the provider-level defaults from its config section
+    pub fn from_config(provider: Option<&config::Provider>) -> Self {
+        Self {
+            temperature: provider.and_then(|provider| provider.temperature),
+            max_tokens: provider.and_then(|provider| provider.max_tokens),
+            max_retries: provider.and_then(|provider| provider.max_retries),
+            timeout: provider
+                .and_then(|provider| provider.timeout_secs)
+                .map(Duration::from_secs),
+            proxy: provider.and_then(|provider| provider.proxy.clone()),
+            reasoning_effort: None,
+            tools: Vec::new(),
+            seed: None,
+            stop: Vec::new(),
+            json_mode: false,
+            extra: provider
+                .and_then(|provider| provider.extra.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Return these options with unset fields taken from `defaults`
+    pub fn or(&self, defaults: &QueryOptions) -> QueryOptions {
+        QueryOptions {
+            temperature: self.temperature.or(defaults.temperature),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            max_retries: self.max_retries.or(defaults.max_retries),
+            timeout: self.timeout.or(defaults.timeout),
+            proxy: self.proxy.clone().or_else(|| defaults.proxy.clone()),
+            reasoning_effort: self.reasoning_effort.or(defaults.reasoning_effort),
+            tools: if self.tools.is_empty() {
+                defaults.tools.clone()
+            } else {
+                self.tools.clone()
+            },
+            seed: self.seed.or(defaults.seed),
+            stop: if self.stop.is_empty() {
+                defaults.stop.clone()
+            } else {
+                self.stop.clone()
+            },
+            json_mode: self.json_mode || defaults.json_mode,
+            extra: {
+                let mut extra = defaults.extra.clone();
+                extra.extend(self.extra.clone());
+                extra
+            },
+        }
+    }
+}
 
 pub trait Provider {
     fn model(&self) -> String;
     fn name(&self) -> String;
-    fn list_models(&self) -> Result<()>;
-    fn query(&self, message: &str, model: &str, streaming: bool) -> Result<String>;
+    fn list_models(&self) -> Result<Vec<ModelInfo>>;
+
+    /// Send a single prompt to the model.
+    ///
+    /// Arguments are always the model first, then the prompt, as in
+    /// `query_with_history`. With `on_chunk` the reply is streamed and each
+    /// delta is passed to the callback as it arrives; the full reply is
+    /// returned either way.
+    fn query(
+        &self,
+        model: &str,
+        prompt: &str,
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<String>;
+
+    /// Token usage of the last completed query, when the provider reported it
+    fn last_usage(&self) -> Option<Usage> {
+        None
+    }
+
+    /// Whether the last reply was cut off at the token limit
+    fn last_truncated(&self) -> bool {
+        false
+    }
+
+    /// Thinking the model returned before its last reply, when it uses extended thinking
+    fn last_thinking(&self) -> Option<String> {
+        None
+    }
+
+    /// Tool calls the model asked for in the last reply, empty when it replied with text
+    fn last_tool_calls(&self) -> Vec<ToolCall> {
+        Vec::new()
+    }
+
+    /// Send a whole conversation to the model.
+    ///
+    /// The default implementation flattens the conversation into a single
+    /// prompt and forwards it to `query`; providers with native multi-turn
+    /// support should override it.
+    fn query_with_history(
+        &self,
+        model: &str,
+        messages: &[(Role, String)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        _options: &QueryOptions,
+    ) -> Result<String> {
+        let prompt = messages
+            .iter()
+            .map(|(role, content)| format!("{}: {}", role.as_str(), content))
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        self.query(model, &prompt, on_chunk)
+    }
+
+    /// Send a conversation whose messages may carry images.
+    ///
+    /// The default implementation rejects images and forwards the text to
+    /// `query_with_history`; providers with vision support override it.
+    fn query_with_content(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<String> {
+        if messages
+            .iter()
+            .any(|(_, content)| !content.images().is_empty())
+        {
+            return Err(anyhow::anyhow!(
+                "Provider {} does not support image input",
+                self.name()
+            ));
+        }
+
+        let messages = messages
+            .iter()
+            .map(|(role, content)| (*role, content.text().to_string()))
+            .collect::<Vec<_>>();
+        self.query_with_history(model, &messages, on_chunk, options)
+    }
+
+    /// Send a conversation and return the reply with its metadata.
+    ///
+    /// The default implementation forwards to `query_with_content` and only
+    /// fills in the usage; providers that parse the rest override it.
+    fn query_full(
+        &self,
+        model: &str,
+        messages: &[(Role, PromptContent)],
+        on_chunk: Option<&mut dyn FnMut(&str)>,
+        options: &QueryOptions,
+    ) -> Result<QueryResponse> {
+        let text = self.query_with_content(model, messages, on_chunk, options)?;
+        Ok(QueryResponse {
+            text,
+            usage: self.last_usage(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Timing of a query, reported with `--stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueryStats {
+    /// Wall-clock time until the whole reply was received
+    pub latency: Duration,
+    /// Time until the first streamed text arrived; unset without streaming
+    pub first_token: Option<Duration>,
+    /// Output tokens, when the provider reported usage
+    pub output_tokens: Option<u64>,
+}
+
+impl QueryStats {
+    /// Output tokens per second of wall-clock time, when usage is known
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        let seconds = self.latency.as_secs_f64();
+        self.output_tokens
+            .filter(|_| seconds > 0.0)
+            .map(|tokens| tokens as f64 / seconds)
+    }
+
+    /// One-line summary for stderr
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Latency: {:.2}s", self.latency.as_secs_f64());
+        if let Some(first_token) = self.first_token {
+            summary.push_str(&format!(", first token: {:.2}s", first_token.as_secs_f64()));
+        }
+        if let (Some(tokens), Some(rate)) = (self.output_tokens, self.tokens_per_second()) {
+            summary.push_str(&format!(
+                ", {} output tokens ({:.1} tokens/s)",
+                tokens, rate
+            ));
+        }
+        summary
+    }
+}
+
+/// Run `query_with_content`, measuring its latency and, when streaming,
+/// the time to the first chunk
+pub fn timed_query(
+    provider: &dyn Provider,
+    model: &str,
+    messages: &[(Role, PromptContent)],
+    on_chunk: Option<&mut dyn FnMut(&str)>,
+    options: &QueryOptions,
+) -> (Result<QueryResponse>, QueryStats) {
+    let started = Instant::now();
+    let mut first_token = None;
+
+    let result = match on_chunk {
+        Some(on_chunk) => {
+            let mut timed = |chunk: &str| {
+                first_token.get_or_insert_with(|| started.elapsed());
+                on_chunk(chunk);
+            };
+            provider.query_full(model, messages, Some(&mut timed), options)
+        }
+        None => provider.query_full(model, messages, None, options),
+    };
+
+    let stats = QueryStats {
+        latency: started.elapsed(),
+        first_token,
+        output_tokens: provider.last_usage().map(|usage| usage.output_tokens),
+    };
+    (result, stats)
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask the streaming query in progress to stop, e.g. from a Ctrl-C handler
+pub fn request_interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether an interrupt was requested since the last call, clearing the request
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// Flag set by [`request_interrupt`]; streaming loops stop reading once it is
+/// set and leave it for the caller to [`take_interrupt`]
+pub fn interrupt_flag() -> &'static AtomicBool {
+    &INTERRUPTED
+}
+
+/// Warn that a reply from `provider` stopped at the token limit
+pub fn warn_truncated(provider: &str) {
+    log::warn!(
+        "{} reply was cut off at the token limit; use a higher --max-tokens for a complete answer",
+        provider
+    );
+}
+
+/// Fail when more stop sequences are given than `provider` accepts
+pub fn check_stop_sequences(provider: &str, stop: &[String], max: usize) -> Result<()> {
+    if stop.len() > max {
+        return Err(anyhow::anyhow!(
+            "{} accepts at most {} stop sequences, got {}",
+            provider,
+            max,
+            stop.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Parse the `--extra-json` value, which must be a JSON object
+pub fn parse_extra_json(json: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+    match serde_json::from_str(json)? {
+        serde_json::Value::Object(extra) => Ok(extra),
+        other => Err(anyhow::anyhow!("Expected a JSON object, got {}", other)),
+    }
+}
+
+/// Add the `extra` fields the request `body` does not set already
+pub fn apply_extra(
+    body: &mut serde_json::Value,
+    extra: &serde_json::Map<String, serde_json::Value>,
+) {
+    let Some(body) = body.as_object_mut() else {
+        return;
+    };
+    for (key, value) in extra {
+        if body.contains_key(key) {
+            log::debug!(
+                "Not overriding request field {:?} with the extra value",
+                key
+            );
+        } else {
+            body.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// Output token limit sent when neither `--max-tokens` nor the config sets one
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Default output token limit per model family, keyed by model prefix.
+///
+/// Families missing here get [`DEFAULT_MAX_TOKENS`]. More specific prefixes
+/// must come before shorter ones sharing the same start.
+const MAX_TOKENS: &[(&str, u32)] = &[
+    ("claude-opus-4", 32000),
+    ("claude-sonnet-4", 64000),
+    ("claude-haiku-4", 64000),
+    ("claude-3-7-sonnet", 64000),
+    ("claude-3-5-sonnet", 8192),
+    ("claude-3-5-haiku", 8192),
+];
+
+/// Output token limit for `model` when the request does not set one
+pub fn default_max_tokens(model: &str) -> u32 {
+    MAX_TOKENS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map_or(DEFAULT_MAX_TOKENS, |(_, tokens)| *tokens)
 }
 
+/// Names accepted by [`create_provider`]
+pub const PROVIDER_NAMES: [&str; 4] = [
+    ClaudeProvider::PROVIDER_NAME,
+    OpenAIProvider::AZURE_PROVIDER_NAME,
+    MistralProvider::PROVIDER_NAME,
+    OpenAIProvider::PROVIDER_NAME,
+];
+
 /// Factory function to create AI providers
 pub fn create_provider(provider_name: &str, config: &Config) -> Result<Box<dyn Provider>> {
     match provider_name {
-        ClaudeProvider::PROVIDER_NAME => Ok(Box::new(crate::claude::ClaudeProvider::new(config)?)),
-        "mistral" => Ok(Box::new(crate::mistral::MistralProvider::new(config)?)),
-        "openai" => Ok(Box::new(crate::openai::OpenAIProvider::new(config)?)),
+        ClaudeProvider::PROVIDER_NAME => Ok(Box::new(ClaudeProvider::new(config)?)),
+        MistralProvider::PROVIDER_NAME => Ok(Box::new(MistralProvider::new(config)?)),
+        OpenAIProvider::PROVIDER_NAME => Ok(Box::new(OpenAIProvider::new(config)?)),
+        OpenAIProvider::AZURE_PROVIDER_NAME => Ok(Box::new(OpenAIProvider::azure(config)?)),
+        #[cfg(any(test, feature = "test-provider"))]
+        crate::mock::MockProvider::PROVIDER_NAME => {
+            Ok(Box::new(crate::mock::MockProvider::new(config)))
+        }
+        _ => Err(anyhow::anyhow!("Unsupported provider: {}", provider_name)),
+    }
+}
+
+/// Like [`create_provider`], but with `api_key` instead of the key from the environment or config
+pub fn create_provider_with_key(
+    provider_name: &str,
+    config: &Config,
+    api_key: String,
+) -> Result<Box<dyn Provider>> {
+    match provider_name {
+        ClaudeProvider::PROVIDER_NAME => {
+            Ok(Box::new(ClaudeProvider::with_api_key(config, api_key)?))
+        }
+        MistralProvider::PROVIDER_NAME => {
+            Ok(Box::new(MistralProvider::with_api_key(config, api_key)?))
+        }
+        OpenAIProvider::PROVIDER_NAME => {
+            Ok(Box::new(OpenAIProvider::with_api_key(config, api_key)?))
+        }
+        OpenAIProvider::AZURE_PROVIDER_NAME => Ok(Box::new(OpenAIProvider::azure_with_api_key(
+            config, api_key,
+        )?)),
         _ => Err(anyhow::anyhow!("Unsupported provider: {}", provider_name)),
     }
 }
@@ -47,9 +582,29 @@ pub fn create_provider(provider_name: &str, config: &Config) -> Result<Box<dyn P
 #[cfg(test)]
 mod tests {
     use crate::{
-        config::{Config, Credentials},
-        provider::create_provider,
+        config::{Config, Credentials, test_config_for},
+        provider::{
+            DEFAULT_MAX_TOKENS, ModelInfo, QueryOptions, QueryStats, Role, apply_extra,
+            create_provider, default_max_tokens, parse_extra_json, select_models, timed_query,
+        },
     };
+    use std::time::Duration;
+
+    #[test]
+    fn test_default_max_tokens_depends_on_model() {
+        assert_eq!(default_max_tokens("claude-sonnet-4-5-20250929"), 64000);
+        assert_eq!(default_max_tokens("claude-opus-4-1"), 32000);
+        assert_eq!(default_max_tokens("claude-3-5-haiku-latest"), 8192);
+        assert_eq!(default_max_tokens("gpt-5"), DEFAULT_MAX_TOKENS);
+        assert_eq!(
+            default_max_tokens("mistral-large-latest"),
+            DEFAULT_MAX_TOKENS
+        );
+        assert_ne!(
+            default_max_tokens("claude-sonnet-4-5"),
+            default_max_tokens("gpt-5")
+        );
+    }
 
     #[test]
     fn test_create_anthropic_provider() {
@@ -58,6 +613,7 @@ mod tests {
                 anthropic_api_key: Some("test-anthropic-key".to_string()),
                 openai_api_key: None,
                 mistral_api_key: None,
+                azure_openai_api_key: None,
             }),
             ..Default::default()
         };
@@ -66,6 +622,197 @@ mod tests {
         assert!(provider.is_ok());
     }
 
+    #[test]
+    fn test_provider_names() {
+        let mut config = Config {
+            credentials: Some(Credentials {
+                anthropic_api_key: Some("test-anthropic-key".to_string()),
+                openai_api_key: Some("test-openai-key".to_string()),
+                mistral_api_key: Some("test-mistral-key".to_string()),
+                azure_openai_api_key: Some("test-azure-key".to_string()),
+            }),
+            ..Default::default()
+        };
+        config.providers.insert(
+            "azure".to_string(),
+            crate::config::Provider {
+                endpoint: Some("https://my-resource.openai.azure.com".to_string()),
+                deployment: Some("gpt-4o".to_string()),
+                ..Default::default()
+            },
+        );
+
+        for name in ["anthropic", "azure", "mistral", "openai"] {
+            let provider = create_provider(name, &config).unwrap();
+            assert_eq!(provider.name(), name);
+        }
+    }
+
+    #[test]
+    fn test_model_comes_from_selected_provider_config() {
+        let config: Config = toml::from_str(
+            r#"
+              [credentials]
+              anthropic_api_key = "test-anthropic-key"
+              mistral_api_key = "test-mistral-key"
+
+              [providers.anthropic]
+              model = "claude-configured"
+
+              [providers.mistral]
+              model = "mistral-configured"
+
+              [inputs]
+
+              [prompts]
+            "#,
+        )
+        .unwrap();
+
+        let mistral = create_provider("mistral", &config).unwrap();
+        assert_eq!(mistral.model(), "mistral-configured");
+
+        let anthropic = create_provider("anthropic", &config).unwrap();
+        assert_eq!(anthropic.model(), "claude-configured");
+    }
+
+    #[test]
+    fn test_default_config_model_is_used_by_anthropic_provider() {
+        let mut config = crate::config::get_default_config();
+        config.credentials = Some(Credentials {
+            anthropic_api_key: Some("test-anthropic-key".to_string()),
+            ..Default::default()
+        });
+        // Distinct from the provider's built-in default, so only a matching key passes
+        let provider_config = config.providers.get_mut("anthropic").unwrap();
+        provider_config.model = Some("claude-from-default-config".to_string());
+
+        let provider = create_provider("anthropic", &config).unwrap();
+        assert_eq!(provider.model(), "claude-from-default-config");
+    }
+
+    #[test]
+    fn test_role_serializes_lowercase() {
+        assert_eq!(
+            serde_json::to_string(&Role::Assistant).unwrap(),
+            "\"assistant\""
+        );
+        assert_eq!(Role::System.as_str(), "system");
+    }
+
+    #[test]
+    fn test_query_options_fall_back_to_defaults() {
+        let defaults = QueryOptions {
+            temperature: Some(0.7),
+            max_tokens: Some(1024),
+            ..Default::default()
+        };
+        let options = QueryOptions {
+            temperature: Some(0.2),
+            ..Default::default()
+        };
+
+        let merged = options.or(&defaults);
+        assert_eq!(merged.temperature, Some(0.2));
+        assert_eq!(merged.max_tokens, Some(1024));
+    }
+
+    #[test]
+    fn test_parse_and_apply_extra_json() {
+        let extra = parse_extra_json(r#"{"top_p": 0.9, "model": "other"}"#).unwrap();
+        assert!(parse_extra_json("[1, 2]").is_err());
+        assert!(parse_extra_json("{not json").is_err());
+
+        let mut body = serde_json::json!({"model": "gpt-5"});
+        apply_extra(&mut body, &extra);
+        assert_eq!(body, serde_json::json!({"model": "gpt-5", "top_p": 0.9}));
+    }
+
+    #[test]
+    fn test_timed_query_measures_streamed_reply() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(
+                "data: {\"type\":\"message_start\",\"message\":{\"content\":[],\"usage\":{\"input_tokens\":3,\"output_tokens\":1}}}\n\n\
+                 data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n\
+                 data: {\"type\":\"message_stop\"}\n\n",
+            )
+            .create();
+
+        let config = test_config_for(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let mut received = String::new();
+        let (result, stats) = timed_query(
+            provider.as_ref(),
+            "test-model",
+            &[(Role::User, "Hello".to_string().into())],
+            Some(&mut |chunk: &str| received.push_str(chunk)),
+            &QueryOptions::default(),
+        );
+
+        mock.assert();
+        assert_eq!(result.unwrap().text, "Hi");
+        assert_eq!(received, "Hi");
+        assert!(stats.latency > Duration::ZERO);
+        assert!(
+            stats
+                .first_token
+                .is_some_and(|first| first <= stats.latency)
+        );
+    }
+
+    #[test]
+    fn test_query_stats_summary() {
+        let stats = QueryStats {
+            latency: Duration::from_millis(2000),
+            first_token: Some(Duration::from_millis(500)),
+            output_tokens: Some(100),
+        };
+        assert_eq!(stats.tokens_per_second(), Some(50.0));
+        assert_eq!(
+            stats.summary(),
+            "Latency: 2.00s, first token: 0.50s, 100 output tokens (50.0 tokens/s)"
+        );
+
+        let stats = QueryStats {
+            latency: Duration::from_millis(250),
+            ..Default::default()
+        };
+        assert_eq!(stats.summary(), "Latency: 0.25s");
+    }
+
+    #[test]
+    fn test_select_models_filters_and_sorts() {
+        let model = |id: &str, name: Option<&str>| ModelInfo {
+            id: id.to_string(),
+            display_name: name.map(str::to_string),
+        };
+        let models = vec![
+            model("claude-sonnet-4-5", Some("Claude Sonnet 4.5")),
+            model("claude-haiku-4-5", Some("Claude Haiku 4.5")),
+            model("claude-opus-4-1", Some("Claude Opus 4.1")),
+        ];
+
+        let ids = |models: Vec<ModelInfo>| models.into_iter().map(|m| m.id).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(select_models(models.clone(), None, true)),
+            ["claude-haiku-4-5", "claude-opus-4-1", "claude-sonnet-4-5"]
+        );
+        assert_eq!(
+            ids(select_models(models.clone(), Some("4-5"), false)),
+            ["claude-sonnet-4-5", "claude-haiku-4-5"]
+        );
+        assert_eq!(
+            ids(select_models(models, Some("OPUS"), false)),
+            ["claude-opus-4-1"]
+        );
+    }
+
     #[test]
     fn test_unsupported_provider_returns_error() {
         let config = Config::default();
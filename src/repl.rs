@@ -1,13 +1,29 @@
-use anyhow::Result;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use anyhow::{Context, Result};
 use rustyline::DefaultEditor;
 use rustyline::error::ReadlineError;
+use serde::{Deserialize, Serialize};
 
-use crate::provider::Provider;
+use crate::config::Config;
+use crate::models_cache::{cache_dir, cached_models};
+use crate::output::{Spinner, format_models, print_chunk, render_transcript, spinner_enabled};
+use crate::provider::{
+    Provider, QueryOptions, Role, create_provider, request_interrupt, take_interrupt,
+};
+use crate::template::now;
 
-pub fn run_repl(provider: Box<dyn Provider>, model: Option<String>, debug: bool) -> Result<()> {
+pub fn run_repl(
+    config: &Config,
+    mut provider: Box<dyn Provider>,
+    model: Option<String>,
+    quiet: bool,
+) -> Result<()> {
     let mut rl = DefaultEditor::new()?;
 
-    let model_name = model.as_deref().unwrap_or(&provider.model()).to_string();
+    let mut model_name =
+        config.resolve_model(&provider.name(), &model.unwrap_or_else(|| provider.model()));
 
     println!("Aika REPL - Interactive mode");
     println!("Provider: {}", provider.name());
@@ -16,12 +32,25 @@ pub fn run_repl(provider: Box<dyn Provider>, model: Option<String>, debug: bool)
     println!("Type '/help' for available commands");
     println!();
 
-    let mut conversation_history: Vec<(String, String)> = Vec::new();
+    let mut conversation_history: Vec<Turn> = Vec::new();
+    let mut multiline = MultilineInput::default();
+    let mut streaming = true;
+
+    // Ctrl-C outside the line editor stops the reply being generated instead of exiting
+    ctrlc::set_handler(request_interrupt)?;
 
     loop {
-        let readline = rl.readline("aika> ");
+        let prompt = if multiline.is_pending() {
+            "...> "
+        } else {
+            "aika> "
+        };
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
+                let Some(line) = multiline.push(&line) else {
+                    continue;
+                };
                 let trimmed = line.trim();
 
                 if trimmed.is_empty() {
@@ -31,7 +60,8 @@ pub fn run_repl(provider: Box<dyn Provider>, model: Option<String>, debug: bool)
                 rl.add_history_entry(trimmed)?;
 
                 // Handle special commands
-                match trimmed {
+                let (command, arg) = split_command(trimmed);
+                match command {
                     "exit" | "quit" => {
                         println!("Goodbye!");
                         break;
@@ -50,7 +80,95 @@ pub fn run_repl(provider: Box<dyn Provider>, model: Option<String>, debug: bool)
                         continue;
                     }
                     "/models" => {
-                        provider.list_models()?;
+                        let models = cache_dir().and_then(|dir| {
+                            cached_models(provider.as_ref(), &dir, config.models_cache_ttl(), false)
+                        });
+                        match models {
+                            Ok(models) => {
+                                println!("{}", format_models(&provider.name(), &models, "none"))
+                            }
+                            Err(e) => eprintln!("Error: {:#}", e),
+                        }
+                        continue;
+                    }
+                    "/stream" => {
+                        match parse_toggle(arg) {
+                            Some(enabled) => {
+                                streaming = enabled;
+                                println!("Streaming {}", if streaming { "on" } else { "off" });
+                            }
+                            None => println!("Usage: /stream on|off"),
+                        }
+                        continue;
+                    }
+                    "/model" if !arg.is_empty() => {
+                        model_name = config.resolve_model(&provider.name(), arg);
+                        println!("Model: {}", model_name);
+                        continue;
+                    }
+                    "/provider" if !arg.is_empty() => {
+                        match create_provider(arg, config) {
+                            Ok(new_provider) => {
+                                provider = new_provider;
+                                model_name =
+                                    config.resolve_model(&provider.name(), &provider.model());
+                                println!("Provider: {}", provider.name());
+                                println!("Model: {}", model_name);
+                            }
+                            Err(e) => eprintln!("Error: {:#}", e),
+                        }
+                        continue;
+                    }
+                    "/save" if !arg.is_empty() => {
+                        let session = Session {
+                            provider: provider.name(),
+                            model: model_name.clone(),
+                            turns: conversation_history.clone(),
+                        };
+                        match session.save(Path::new(arg)) {
+                            Ok(()) => println!("Saved {} turns to {}", session.turns.len(), arg),
+                            Err(e) => eprintln!("Error: {:#}", e),
+                        }
+                        continue;
+                    }
+                    "/export" if !arg.is_empty() => {
+                        let transcript = render_transcript(
+                            &provider.name(),
+                            &model_name,
+                            &now(),
+                            &history_messages(&conversation_history),
+                        );
+                        match std::fs::write(arg, transcript) {
+                            Ok(()) => {
+                                println!("Exported {} turns to {}", conversation_history.len(), arg)
+                            }
+                            Err(e) => {
+                                eprintln!("Error: Failed to write transcript {:?}: {}", arg, e)
+                            }
+                        }
+                        continue;
+                    }
+                    "/load" if !arg.is_empty() => {
+                        match Session::load(Path::new(arg)) {
+                            Ok(session) => {
+                                println!(
+                                    "Restored {} turns (saved with {} / {})",
+                                    session.turns.len(),
+                                    session.provider,
+                                    session.model
+                                );
+                                conversation_history = session.turns;
+                            }
+                            Err(e) => eprintln!("Error: {:#}", e),
+                        }
+                        continue;
+                    }
+                    "/model" | "/provider" => {
+                        println!("Usage: {} <name>", command);
+                        continue;
+                    }
+                    "/save" | "/load" | "/export" => {
+                        println!("Usage: {} <path>", command);
                         continue;
                     }
                     _ if trimmed.starts_with("/") => {
@@ -64,22 +182,51 @@ pub fn run_repl(provider: Box<dyn Provider>, model: Option<String>, debug: bool)
                 }
 
                 // Send query to AI provider
-                if debug {
-                    println!("Sending query to {}...", provider.name());
-                }
+                log::debug!("Sending query to {}", provider.name());
+
+                let messages = build_messages(&conversation_history, trimmed);
 
-                match provider.query(&model_name, trimmed, false) {
+                // Render the reply live while keeping the full text for the history
+                println!();
+                take_interrupt();
+                let mut print = print_chunk;
+                let on_chunk: Option<&mut dyn FnMut(&str)> =
+                    if streaming { Some(&mut print) } else { None };
+                let spinner = Spinner::start(
+                    "Waiting for reply",
+                    !streaming && spinner_enabled(quiet, std::io::stdout().is_terminal()),
+                );
+                let reply = provider.query_with_history(
+                    &model_name,
+                    &messages,
+                    on_chunk,
+                    &QueryOptions::default(),
+                );
+                spinner.stop();
+
+                match reply {
+                    Ok(_) if take_interrupt() => {
+                        println!("\nGeneration interrupted.\n");
+                    }
                     Ok(response) => {
-                        println!("\n{}\n", response);
-                        conversation_history.push((trimmed.to_string(), response));
+                        if streaming {
+                            println!("\n");
+                        } else {
+                            println!("{}\n", response);
+                        }
+                        conversation_history.push(Turn {
+                            user: trimmed.to_string(),
+                            assistant: response,
+                        });
                     }
                     Err(e) => {
-                        eprintln!("Error: {}", e);
+                        eprintln!("\nError: {}\n", e);
                     }
                 }
             }
             Err(ReadlineError::Interrupted) => {
                 println!("^C");
+                multiline.clear();
                 continue;
             }
             Err(ReadlineError::Eof) => {
@@ -96,18 +243,159 @@ pub fn run_repl(provider: Box<dyn Provider>, model: Option<String>, debug: bool)
     Ok(())
 }
 
+/// One exchange of the conversation: the user's prompt and the model's reply
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Turn {
+    user: String,
+    assistant: String,
+}
+
+/// Conversation written by `/save` and restored by `/load`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Session {
+    provider: String,
+    model: String,
+    turns: Vec<Turn>,
+}
+
+impl Session {
+    fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write session {:?}", path))
+    }
+
+    fn load(path: &Path) -> Result<Session> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read session {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid session file {:?}", path))
+    }
+}
+
+/// Terminator of a multiline block, or a backslash continuation in progress
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pending {
+    Block(&'static str),
+    Continuation,
+}
+
+/// Accumulates input spanning several lines into a single prompt.
+///
+/// A `"""` line opens a block closed by another `"""`, `/paste` opens one
+/// closed by `/end`, and a trailing backslash continues on the next line.
+#[derive(Debug, Default)]
+struct MultilineInput {
+    lines: Vec<String>,
+    pending: Option<Pending>,
+}
+
+impl MultilineInput {
+    /// Feed one line read from the editor; returns the full input once complete
+    fn push(&mut self, line: &str) -> Option<String> {
+        match self.pending {
+            None => match line.trim() {
+                "\"\"\"" => self.pending = Some(Pending::Block("\"\"\"")),
+                "/paste" => self.pending = Some(Pending::Block("/end")),
+                _ => match line.strip_suffix('\\') {
+                    Some(line) => {
+                        self.lines.push(line.to_string());
+                        self.pending = Some(Pending::Continuation);
+                    }
+                    None => return Some(line.to_string()),
+                },
+            },
+            Some(Pending::Block(terminator)) => {
+                if line.trim() == terminator {
+                    return Some(self.finish());
+                }
+                self.lines.push(line.to_string());
+            }
+            Some(Pending::Continuation) => match line.strip_suffix('\\') {
+                Some(line) => self.lines.push(line.to_string()),
+                None => {
+                    self.lines.push(line.to_string());
+                    return Some(self.finish());
+                }
+            },
+        }
+
+        None
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Drop a partially entered block
+    fn clear(&mut self) {
+        self.lines.clear();
+        self.pending = None;
+    }
+
+    fn finish(&mut self) -> String {
+        let text = self.lines.join("\n");
+        self.clear();
+        text
+    }
+}
+
+/// Parse the argument of a toggle command such as `/stream on`
+fn parse_toggle(arg: &str) -> Option<bool> {
+    match arg.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" => Some(true),
+        "off" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Split a slash command from its argument; other input is returned whole
+fn split_command(line: &str) -> (&str, &str) {
+    if !line.starts_with('/') {
+        return (line, "");
+    }
+
+    match line.split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    }
+}
+
+/// Past turns as alternating user and assistant messages
+fn history_messages(history: &[Turn]) -> Vec<(Role, String)> {
+    let mut messages = Vec::with_capacity(history.len() * 2 + 1);
+    for turn in history {
+        messages.push((Role::User, turn.user.clone()));
+        messages.push((Role::Assistant, turn.assistant.clone()));
+    }
+    messages
+}
+
+/// Build the message list sent to the provider from past turns and the new prompt
+fn build_messages(history: &[Turn], prompt: &str) -> Vec<(Role, String)> {
+    let mut messages = history_messages(history);
+    messages.push((Role::User, prompt.to_string()));
+    messages
+}
+
 fn print_help() {
     println!("Available commands:");
     println!("  /help     - Show this help message");
     println!("  /clear    - Clear conversation history");
     println!("  /history  - Show conversation history");
     println!("  /models   - List available models");
+    println!("  /model <name>    - Switch to another model");
+    println!("  /provider <name> - Switch to another provider and its default model");
+    println!("  /stream on|off   - Stream replies as they are generated (default on)");
+    println!("  /paste    - Enter a multiline prompt, ending with /end");
+    println!("  /save <path> - Save the conversation to a JSON file");
+    println!("  /load <path> - Restore a conversation saved with /save");
+    println!("  /export <path> - Write the conversation as a Markdown transcript");
     println!("  exit/quit - Exit the REPL");
     println!();
     println!("Just type your message to interact with the AI.");
+    println!("Wrap multiline input in \"\"\" lines or end a line with \\ to continue it.");
 }
 
-fn print_history(history: &[(String, String)]) {
+fn print_history(history: &[Turn]) {
     if history.is_empty() {
         println!("No conversation history.");
         return;
@@ -115,9 +403,138 @@ fn print_history(history: &[(String, String)]) {
 
     println!("\nConversation History:");
     println!("━━━━━━━━━━━━━━━━━━━━");
-    for (i, (prompt, response)) in history.iter().enumerate() {
-        println!("\n[{}] User: {}", i + 1, prompt);
-        println!("Assistant: {}", response);
+    for (i, turn) in history.iter().enumerate() {
+        println!("\n[{}] User: {}", i + 1, turn.user);
+        println!("Assistant: {}", turn.assistant);
     }
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_messages_includes_history() {
+        let history = vec![Turn {
+            user: "Hi".to_string(),
+            assistant: "Hello".to_string(),
+        }];
+        let messages = build_messages(&history, "How are you?");
+
+        assert_eq!(
+            messages,
+            vec![
+                (Role::User, "Hi".to_string()),
+                (Role::Assistant, "Hello".to_string()),
+                (Role::User, "How are you?".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        let session = Session {
+            provider: "anthropic".to_string(),
+            model: "claude-sonnet-4-5".to_string(),
+            turns: vec![
+                Turn {
+                    user: "Hi".to_string(),
+                    assistant: "Hello!".to_string(),
+                },
+                Turn {
+                    user: "Write \"quoted\"\ntext".to_string(),
+                    assistant: "Done".to_string(),
+                },
+            ],
+        };
+
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), session);
+    }
+
+    #[test]
+    fn test_session_load_rejects_invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        std::fs::write(&path, "not json").unwrap();
+
+        assert!(Session::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("/save  chat.json "), ("/save", "chat.json"));
+        assert_eq!(split_command("/history"), ("/history", ""));
+        assert_eq!(split_command("exit the loop"), ("exit the loop", ""));
+    }
+
+    #[test]
+    fn test_split_command_extracts_switch_target() {
+        assert_eq!(split_command("/model gpt-5"), ("/model", "gpt-5"));
+        assert_eq!(
+            split_command("/provider\tmistral"),
+            ("/provider", "mistral")
+        );
+        assert_eq!(split_command("/provider"), ("/provider", ""));
+    }
+
+    fn feed(input: &mut MultilineInput, lines: &[&str]) -> Vec<Option<String>> {
+        lines.iter().map(|line| input.push(line)).collect()
+    }
+
+    #[test]
+    fn test_multiline_single_line() {
+        let mut input = MultilineInput::default();
+        assert_eq!(input.push("hello"), Some("hello".to_string()));
+        assert!(!input.is_pending());
+    }
+
+    #[test]
+    fn test_multiline_fence_keeps_blank_lines() {
+        let mut input = MultilineInput::default();
+        let results = feed(&mut input, &["\"\"\"", "fn main() {", "", "}", "\"\"\""]);
+
+        assert!(results[..4].iter().all(Option::is_none));
+        assert_eq!(results[4].as_deref(), Some("fn main() {\n\n}"));
+        assert!(!input.is_pending());
+    }
+
+    #[test]
+    fn test_multiline_paste_ends_with_end() {
+        let mut input = MultilineInput::default();
+        let results = feed(&mut input, &["/paste", "\"\"\"", "/end"]);
+
+        assert_eq!(results[2].as_deref(), Some("\"\"\""));
+    }
+
+    #[test]
+    fn test_multiline_backslash_continuation() {
+        let mut input = MultilineInput::default();
+        let results = feed(&mut input, &["first \\", "second \\", "third"]);
+
+        assert!(input.pending.is_none());
+        assert_eq!(results[2].as_deref(), Some("first \nsecond \nthird"));
+    }
+
+    #[test]
+    fn test_multiline_clear_drops_pending_block() {
+        let mut input = MultilineInput::default();
+        feed(&mut input, &["\"\"\"", "partial"]);
+        input.clear();
+
+        assert!(!input.is_pending());
+        assert_eq!(input.push("next"), Some("next".to_string()));
+    }
+
+    #[test]
+    fn test_parse_toggle() {
+        assert_eq!(parse_toggle("on"), Some(true));
+        assert_eq!(parse_toggle("OFF"), Some(false));
+        assert_eq!(parse_toggle("yes"), Some(true));
+        assert_eq!(parse_toggle(""), None);
+        assert_eq!(parse_toggle("maybe"), None);
+    }
+}
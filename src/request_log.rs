@@ -0,0 +1,134 @@
+//! Record of every provider request and response, appended to the `--log-file`.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::redact::redact;
+
+static LOG_FILE: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// One line of the log file
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    /// Seconds since the epoch when the response was received
+    timestamp: u64,
+    provider: &'a str,
+    model: &'a str,
+    request: &'a serde_json::Value,
+    status: u16,
+    /// Raw response body; the `data` of each event, one per line, for streams
+    response: &'a str,
+}
+
+/// Append a record of each request to `path` from now on, or stop with `None`
+pub fn set_log_file(path: Option<&Path>) {
+    *LOG_FILE.write().unwrap_or_else(|e| e.into_inner()) = path.map(Path::to_path_buf);
+}
+
+/// Whether requests are being logged, so streams only keep their events when needed
+pub fn enabled() -> bool {
+    LOG_FILE.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Append one JSON line for a request to the log file, if one is set.
+///
+/// Keys are masked as in errors. Failing to write is only logged, so
+/// debugging never breaks a query.
+pub fn record(
+    provider: &str,
+    model: &str,
+    request: &serde_json::Value,
+    status: u16,
+    response: &str,
+) {
+    let Some(path) = LOG_FILE.read().unwrap_or_else(|e| e.into_inner()).clone() else {
+        return;
+    };
+
+    let record = Record {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        provider,
+        model,
+        request,
+        status,
+        response,
+    };
+    let line = match serde_json::to_string(&record) {
+        Ok(line) => redact(&line),
+        Err(e) => {
+            log::warn!("Failed to encode request log record: {}", e);
+            return;
+        }
+    };
+
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = written {
+        log::warn!("Failed to write request log {:?}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config_for;
+    use crate::provider::create_provider;
+
+    #[test]
+    fn test_query_appends_one_redacted_record() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(r#"{"content": [{"type": "text", "text": "Logged reply"}]}"#)
+            .create();
+
+        let config = test_config_for(&server.url());
+        let provider = create_provider("anthropic", &config).unwrap();
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("requests.jsonl");
+        set_log_file(Some(&path));
+        let reply = provider.query(
+            "claude-request-log",
+            "my key is sk-abcdefghijklmnop1234",
+            None,
+        );
+        set_log_file(None);
+
+        mock.assert();
+        assert_eq!(reply.unwrap(), "Logged reply");
+
+        // Other tests may query while logging is on, so only look at this model
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|record: &serde_json::Value| record["model"] == "claude-request-log")
+            .collect();
+        assert_eq!(records.len(), 1);
+
+        let record = &records[0];
+        assert_eq!(record["provider"], "anthropic");
+        assert_eq!(record["status"], 200);
+        assert_eq!(record["request"]["model"], "claude-request-log");
+        assert!(
+            record["response"]
+                .as_str()
+                .unwrap()
+                .contains("Logged reply")
+        );
+        assert!(!contents.contains("sk-abcdefghijklmnop1234"));
+    }
+}
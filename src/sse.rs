@@ -0,0 +1,318 @@
+//! Parsing of server-sent event streams, and reading replies that may come
+//! as one.
+
+use std::io::{self, BufRead, BufReader, Lines};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ureq::{Body, http::Response};
+
+use crate::http::is_json_response;
+use crate::provider::interrupt_flag;
+use crate::request_log;
+
+/// An event dispatched by the stream
+#[derive(Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Value of the `event:` field, empty when the event has none
+    pub event: String,
+    /// `data:` lines of the event, joined with newlines
+    pub data: String,
+}
+
+/// Iterator over the events of a stream, see [`events`]
+pub struct Events<R> {
+    lines: Lines<R>,
+    stop: Option<&'static AtomicBool>,
+}
+
+/// Read the events of a stream from `reader`.
+///
+/// Consecutive `data:` lines are joined until a blank line ends the event;
+/// events without data (such as keepalive comments) are skipped. A final
+/// event not followed by a blank line is still returned.
+pub fn events<R: BufRead>(reader: R) -> Events<R> {
+    Events {
+        lines: reader.lines(),
+        stop: None,
+    }
+}
+
+impl<R> Events<R> {
+    /// End the stream early, before reading further, once `flag` is set
+    pub fn stop_on(mut self, flag: &'static AtomicBool) -> Self {
+        self.stop = Some(flag);
+        self
+    }
+}
+
+impl<R: BufRead> Iterator for Events<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = String::new();
+        let mut data: Option<String> = None;
+
+        loop {
+            if self.stop.is_some_and(|flag| flag.load(Ordering::SeqCst)) {
+                return None;
+            }
+
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return data.map(|data| Ok(Event { event, data })),
+            };
+
+            match parse_line(&line) {
+                Line::Data(value) => match &mut data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => data = Some(value.to_string()),
+                },
+                Line::Event(name) => event = name.to_string(),
+                Line::Dispatch => match data.take() {
+                    Some(data) => return Some(Ok(Event { event, data })),
+                    None => event.clear(),
+                },
+                Line::Ignored => {}
+            }
+        }
+    }
+}
+
+/// Part of a reply passed to the callback of [`read_reply`]
+pub enum Part<'a> {
+    /// Whole body of a reply that was not streamed
+    Body(&'a str),
+    /// Event of a streamed reply
+    Event(&'a Event),
+}
+
+/// Read the reply in `response`, as a stream of events when `on_chunk` is set.
+///
+/// Each part goes to `on_part`, which returns the reply text it holds, or
+/// breaks at the end of the stream. That text is passed on to `on_chunk` as
+/// it arrives, and returned whole. The raw body, or the data of the events,
+/// goes to `record` for the request log.
+pub fn read_reply(
+    response: &mut Response<Body>,
+    on_chunk: Option<&mut dyn FnMut(&str)>,
+    record: &dyn Fn(&str),
+    mut on_part: impl FnMut(Part) -> anyhow::Result<ControlFlow<(), String>>,
+) -> anyhow::Result<String> {
+    let on_chunk = match on_chunk {
+        // Some proxies ignore the stream flag and answer with a plain JSON body
+        Some(on_chunk) if is_json_response(response) => {
+            log::debug!("Requested a stream but received a JSON body, reading it whole");
+            let text = read_body(response, record, on_part)?;
+            // Hand the whole reply over at once so it is still printed
+            if !text.is_empty() {
+                on_chunk(&text);
+            }
+            return Ok(text);
+        }
+        Some(on_chunk) => on_chunk,
+        None => return read_body(response, record, on_part),
+    };
+
+    let reader = BufReader::new(response.body_mut().with_config().reader());
+    let mut data = Vec::new();
+    let mut text = String::new();
+
+    // An interrupt ends the stream early and returns what was received so far
+    for event in events(reader).stop_on(interrupt_flag()) {
+        let event = event?;
+        if request_log::enabled() {
+            data.push(event.data.clone());
+        }
+
+        match on_part(Part::Event(&event)) {
+            Ok(ControlFlow::Continue(chunk)) => {
+                if !chunk.is_empty() {
+                    on_chunk(&chunk);
+                    text.push_str(&chunk);
+                }
+            }
+            Ok(ControlFlow::Break(())) => break,
+            Err(e) => {
+                record(&data.join("\n"));
+                return Err(e);
+            }
+        }
+    }
+    record(&data.join("\n"));
+
+    Ok(text)
+}
+
+/// Read `response` as a single body and hand it to `on_part`
+fn read_body(
+    response: &mut Response<Body>,
+    record: &dyn Fn(&str),
+    mut on_part: impl FnMut(Part) -> anyhow::Result<ControlFlow<(), String>>,
+) -> anyhow::Result<String> {
+    let body = response.body_mut().read_to_string()?;
+    record(&body);
+
+    match on_part(Part::Body(&body))? {
+        ControlFlow::Continue(text) => Ok(text),
+        ControlFlow::Break(()) => Ok(String::new()),
+    }
+}
+
+/// A field line of an event stream
+#[derive(Debug, PartialEq, Eq)]
+enum Line<'a> {
+    /// `data:` payload
+    Data(&'a str),
+    /// `event:` type of the event being sent
+    Event(&'a str),
+    /// Blank line ending an event
+    Dispatch,
+    /// Comment or keepalive (`:` prefix), or a field we do not use (`id:`, `retry:`)
+    Ignored,
+}
+
+/// Classify one line of an event stream.
+///
+/// As in the SSE spec, the field name runs up to the first `:` and a single
+/// space after it is dropped from the value.
+fn parse_line(line: &str) -> Line<'_> {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if line.is_empty() {
+        return Line::Dispatch;
+    }
+    if line.starts_with(':') {
+        return Line::Ignored;
+    }
+
+    let (field, value) = line.split_once(':').unwrap_or((line, ""));
+    let value = value.strip_prefix(' ').unwrap_or(value);
+
+    match field {
+        "data" => Line::Data(value),
+        "event" => Line::Event(value),
+        _ => Line::Ignored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("data: {\"a\":1}"), Line::Data("{\"a\":1}"));
+        assert_eq!(parse_line("data:{\"a\":1}"), Line::Data("{\"a\":1}"));
+        assert_eq!(parse_line("data:  x"), Line::Data(" x"));
+        assert_eq!(parse_line("event: ping"), Line::Event("ping"));
+        assert_eq!(parse_line(": keepalive"), Line::Ignored);
+        assert_eq!(parse_line("id: 42"), Line::Ignored);
+        assert_eq!(parse_line("retry: 1000"), Line::Ignored);
+        assert_eq!(parse_line(""), Line::Dispatch);
+        assert_eq!(parse_line("\r"), Line::Dispatch);
+    }
+
+    fn collect(stream: &str) -> Vec<(String, String)> {
+        events(stream.as_bytes())
+            .map(|event| {
+                let event = event.unwrap();
+                (event.event, event.data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_events_join_multi_line_data() {
+        let stream = "event: delta\ndata: {\"text\":\ndata: \"split\"}\n\n: ping\n\ndata: last";
+        assert_eq!(
+            collect(stream),
+            [
+                ("delta".to_string(), "{\"text\":\n\"split\"}".to_string()),
+                (String::new(), "last".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_without_data_are_skipped() {
+        let stream = "event: ping\n\n: keepalive\n\ndata: x\n\n";
+        assert_eq!(collect(stream), [(String::new(), "x".to_string())]);
+    }
+
+    fn reply(content_type: &str, body: &str) -> Response<Body> {
+        Response::builder()
+            .header("content-type", content_type)
+            .body(Body::builder().data(body))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_reply_streams_until_break() {
+        let mut response = reply(
+            "text/event-stream",
+            "data: Hel\n\ndata: lo\n\ndata: [DONE]\n\ndata: ignored\n\n",
+        );
+        let mut chunks = Vec::new();
+
+        let text = read_reply(
+            &mut response,
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+            &|_| {},
+            |part| match part {
+                Part::Event(event) if event.data == "[DONE]" => Ok(ControlFlow::Break(())),
+                Part::Event(event) => Ok(ControlFlow::Continue(event.data.clone())),
+                Part::Body(_) => panic!("expected a stream"),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(text, "Hello");
+        assert_eq!(chunks, ["Hel", "lo"]);
+    }
+
+    #[test]
+    fn test_read_reply_hands_over_json_body_to_stream() {
+        let mut response = reply("application/json", r#"{"text":"Whole reply"}"#);
+        let recorded = std::cell::RefCell::new(String::new());
+        let mut chunks = Vec::new();
+
+        let text = read_reply(
+            &mut response,
+            Some(&mut |chunk: &str| chunks.push(chunk.to_string())),
+            &|body| recorded.borrow_mut().push_str(body),
+            |part| match part {
+                Part::Body(body) => {
+                    let body: serde_json::Value = serde_json::from_str(body)?;
+                    Ok(ControlFlow::Continue(
+                        body["text"].as_str().unwrap().to_string(),
+                    ))
+                }
+                Part::Event(_) => panic!("expected a JSON body"),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(text, "Whole reply");
+        assert_eq!(chunks, ["Whole reply"]);
+        assert_eq!(*recorded.borrow(), r#"{"text":"Whole reply"}"#);
+    }
+
+    #[test]
+    fn test_events_stop_once_flag_is_set() {
+        static STOP: AtomicBool = AtomicBool::new(false);
+
+        let stream = "data: first\n\ndata: second\n\ndata: third\n\n";
+        let mut received = Vec::new();
+        for event in events(stream.as_bytes()).stop_on(&STOP) {
+            received.push(event.unwrap().data);
+            STOP.store(true, Ordering::SeqCst);
+        }
+
+        assert_eq!(received, ["first"]);
+        assert!(STOP.load(Ordering::SeqCst));
+    }
+}
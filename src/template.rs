@@ -0,0 +1,201 @@
+//! Placeholder substitution for prompt templates.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+/// Replace `{name}` placeholders in `template` with values from `vars`.
+///
+/// The template is scanned once, so placeholders appearing inside substituted
+/// values (such as a diff mentioning `{date}`) are left alone. Braces that do
+/// not wrap a plain name are copied as-is. Unknown names are kept verbatim,
+/// or reported as an error when `strict` is set.
+pub fn render_prompt(
+    template: &str,
+    vars: &HashMap<String, String>,
+    strict: bool,
+) -> Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let name = after
+            .find('}')
+            .map(|end| &after[..end])
+            .filter(|name| is_placeholder_name(name));
+
+        match name {
+            Some(name) => {
+                match vars.get(name) {
+                    Some(value) => result.push_str(value),
+                    None if strict => {
+                        return Err(anyhow::anyhow!(
+                            "Unknown template variable {{{}}}; define it with --var {}=<value>",
+                            name,
+                            name
+                        ));
+                    }
+                    None => {
+                        result.push('{');
+                        result.push_str(name);
+                        result.push('}');
+                    }
+                }
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = after;
+            }
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parse a `key=value` pair given with `--var`
+pub fn parse_var(pair: &str) -> Result<(String, String)> {
+    let (key, value) = pair
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected key=value, got {:?}", pair))?;
+
+    if !is_placeholder_name(key) {
+        return Err(anyhow::anyhow!(
+            "Invalid variable name {:?}; use letters, digits, '_' or '-'",
+            key
+        ));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Today's date (UTC) as `YYYY-MM-DD`
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_date(secs / 86_400)
+}
+
+/// Current time (UTC) as `YYYY-MM-DD HH:MM UTC`
+pub fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_timestamp(secs)
+}
+
+/// Format seconds since the epoch as a UTC date and time to the minute
+fn format_timestamp(secs: u64) -> String {
+    let minutes = secs % 86_400 / 60;
+    format!(
+        "{} {:02}:{:02} UTC",
+        format_date(secs / 86_400),
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Format a count of days since 1970-01-01 as a civil date
+fn format_date(days: u64) -> String {
+    // Howard Hinnant's civil_from_days, restricted to dates after the epoch
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_prompt_multiple_variables() {
+        let vars = vars(&[
+            ("input", "diff --git"),
+            ("model", "gpt-5"),
+            ("ticket", "ABC-12"),
+        ]);
+        let rendered =
+            render_prompt("[{ticket}] {model} review:\n{input}\n{input}", &vars, true).unwrap();
+        assert_eq!(rendered, "[ABC-12] gpt-5 review:\ndiff --git\ndiff --git");
+    }
+
+    #[test]
+    fn test_render_prompt_does_not_expand_substituted_values() {
+        let vars = vars(&[("input", "fn f() { {date} }"), ("date", "2024-01-01")]);
+        let rendered = render_prompt("{input} on {date}", &vars, true).unwrap();
+        assert_eq!(rendered, "fn f() { {date} } on 2024-01-01");
+    }
+
+    #[test]
+    fn test_render_prompt_undefined_variable() {
+        let vars = vars(&[("input", "x")]);
+
+        let lenient = render_prompt("{input} for {team}", &vars, false).unwrap();
+        assert_eq!(lenient, "x for {team}");
+
+        let err = render_prompt("{input} for {team}", &vars, true).unwrap_err();
+        assert!(err.to_string().contains("{team}"));
+    }
+
+    #[test]
+    fn test_render_prompt_ignores_non_placeholder_braces() {
+        let rendered = render_prompt(r#"Reply as {"title": "..."} { }"#, &vars(&[]), true).unwrap();
+        assert_eq!(rendered, r#"Reply as {"title": "..."} { }"#);
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("team=core=infra").unwrap(),
+            ("team".to_string(), "core=infra".to_string())
+        );
+        assert!(parse_var("novalue").is_err());
+        assert!(parse_var("bad name=x").is_err());
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(19_782), "2024-02-29");
+        assert_eq!(format_date(20_454), "2026-01-01");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(
+            format_timestamp(20_454 * 86_400 + 13 * 3600 + 5 * 60 + 59),
+            "2026-01-01 13:05 UTC"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Rough token and cost estimation for prompts.
+//!
+//! These are heuristics meant to give an order of magnitude before sending a
+//! request, not exact counts: each provider uses its own tokenizer.
+
+use crate::provider::{PromptContent, Role};
+
+/// Price per million tokens as (input, output), in USD, keyed by model prefix.
+///
+/// More specific prefixes must come before shorter ones sharing the same start.
+const PRICES: &[(&str, (f64, f64))] = &[
+    ("claude-opus-4", (15.0, 75.0)),
+    ("claude-sonnet-4", (3.0, 15.0)),
+    ("claude-haiku-4", (1.0, 5.0)),
+    ("claude-3-7-sonnet", (3.0, 15.0)),
+    ("claude-3-5-sonnet", (3.0, 15.0)),
+    ("claude-3-5-haiku", (0.8, 4.0)),
+    ("gpt-5-nano", (0.05, 0.4)),
+    ("gpt-5-mini", (0.25, 2.0)),
+    ("gpt-5", (1.25, 10.0)),
+    ("gpt-4.1-nano", (0.1, 0.4)),
+    ("gpt-4.1-mini", (0.4, 1.6)),
+    ("gpt-4.1", (2.0, 8.0)),
+    ("gpt-4o-mini", (0.15, 0.6)),
+    ("gpt-4o", (2.5, 10.0)),
+    ("mistral-large", (2.0, 6.0)),
+    ("mistral-medium", (0.4, 2.0)),
+    ("mistral-small", (0.2, 0.6)),
+    ("codestral", (0.3, 0.9)),
+];
+
+/// Estimate the number of tokens in `text`, assuming ~4 characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimate the tokens of the text of all `messages`; images are not counted
+pub fn estimate_message_tokens(messages: &[(Role, PromptContent)]) -> usize {
+    messages
+        .iter()
+        .map(|(_, content)| estimate_tokens(content.text()))
+        .sum()
+}
+
+/// Look up the (input, output) price per million tokens for a model
+pub fn price_for(model: &str) -> Option<(f64, f64)> {
+    PRICES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, price)| *price)
+}
+
+/// Estimate the cost in USD of sending `tokens` input tokens to `model`
+pub fn estimate_input_cost(model: &str, tokens: usize) -> Option<f64> {
+    price_for(model).map(|(input, _)| input * tokens as f64 / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abc"), 1);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens(&"a".repeat(400)), 100);
+    }
+
+    #[test]
+    fn test_estimate_tokens_counts_chars_not_bytes() {
+        assert_eq!(estimate_tokens("éééé"), 1);
+    }
+
+    #[test]
+    fn test_estimate_message_tokens_sums_every_message() {
+        let messages = [
+            (Role::System, PromptContent::Text("abcd".to_string())),
+            (Role::User, PromptContent::Text("abcde".to_string())),
+        ];
+        assert_eq!(estimate_message_tokens(&messages), 3);
+    }
+
+    #[test]
+    fn test_price_for_known_models() {
+        assert_eq!(price_for("claude-sonnet-4-5-20250929"), Some((3.0, 15.0)));
+        assert_eq!(price_for("gpt-5"), Some((1.25, 10.0)));
+        assert_eq!(price_for("gpt-5-mini"), Some((0.25, 2.0)));
+        assert_eq!(price_for("mistral-large-latest"), Some((2.0, 6.0)));
+    }
+
+    #[test]
+    fn test_price_for_unknown_model() {
+        assert_eq!(price_for("some-local-model"), None);
+    }
+
+    #[test]
+    fn test_estimate_input_cost() {
+        let cost = estimate_input_cost("gpt-5", 1_000_000).unwrap();
+        assert!((cost - 1.25).abs() < f64::EPSILON);
+        assert!(estimate_input_cost("unknown", 1000).is_none());
+    }
+}
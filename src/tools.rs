@@ -0,0 +1,101 @@
+//! Tools offered to the model and the calls it asks for.
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A function the model may call, as given in the `--tools` file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments; Claude's `input_schema` name is accepted too
+    #[serde(alias = "input_schema")]
+    pub parameters: Value,
+}
+
+/// A call the model asked for instead of replying with text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// Read the tool definitions from a JSON file holding an array of tools
+pub fn load_tools(path: &Path) -> anyhow::Result<Vec<Tool>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read tools file {:?}", path))?;
+    let tools: Vec<Tool> = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse tools file {:?}", path))?;
+
+    if let Some(tool) = tools.iter().find(|tool| !tool.parameters.is_object()) {
+        return Err(anyhow::anyhow!(
+            "Tool {:?} in {:?}: parameters must be a JSON schema object",
+            tool.name,
+            path
+        ));
+    }
+
+    Ok(tools)
+}
+
+/// Arguments of a call sent as a JSON-encoded string, kept as a string if
+/// the model produced invalid JSON so the caller can still see it
+pub fn parse_arguments(arguments: &str) -> Value {
+    serde_json::from_str(arguments).unwrap_or_else(|_| Value::String(arguments.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_load_tools() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("tools.json");
+        std::fs::write(
+            &path,
+            r#"[
+              {
+                "name": "get_weather",
+                "description": "Current weather for a city",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+              },
+              {"name": "now", "input_schema": {"type": "object"}}
+            ]"#,
+        )
+        .unwrap();
+
+        let tools = load_tools(&path).unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0].name, "get_weather");
+        assert_eq!(
+            tools[0].description.as_deref(),
+            Some("Current weather for a city")
+        );
+        assert_eq!(tools[1].parameters, json!({"type": "object"}));
+    }
+
+    #[test]
+    fn test_load_tools_rejects_non_object_schema() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("tools.json");
+        std::fs::write(&path, r#"[{"name": "now", "parameters": "none"}]"#).unwrap();
+
+        let err = load_tools(&path).unwrap_err().to_string();
+        assert!(err.contains("\"now\""), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        assert_eq!(
+            parse_arguments(r#"{"city":"Paris"}"#),
+            json!({"city": "Paris"})
+        );
+        assert_eq!(parse_arguments("{\"city\":"), json!("{\"city\":"));
+    }
+}